@@ -0,0 +1,115 @@
+use regex::Regex;
+use std::path::PathBuf;
+
+use crate::query::{parse_time, QueryArgs};
+use crate::stream::TailArgs;
+
+pub const USAGE: &str = "Usage: plclogger [--config <path>] [query [<filters>] | tail [<filters>]]
+
+Commands:
+  query                search the live log and the archives of a sink, oldest first
+  tail                 print the records of a running logger as they are received,
+                       through its streaming endpoint (stream_port)
+
+Options:
+  -c, --config <path>  config file to use (default: config.toml)
+  -h, --help           print this help
+
+Query filters:
+  --sink <name>        sink to search (default: plc)
+  --from <time>        records received at or after the time, local time like
+                       2024-03-01, \"2024-03-01 14:30\" or a time ago like 15m, 2h, 7d
+  --to <time>          records received at or before the time
+  --source <name>      records of one source
+  --level <level>      records of this level and up: trace, debug, info, warn, error, critical
+  --grep <regex>       records whose line matches the regex
+  --json               print json objects instead of lines
+  --limit <count>      stop after this many records
+  --newest             newest records first, the last ones matching with --limit
+
+Tail filters:
+  --source, --level, --grep and --json as above
+  --connect <host:port> streaming endpoint to connect to (default: stream_bind and
+                       stream_port of the config file)";
+
+// what the program was started to do
+pub enum Command {
+    // log the plc messages
+    Run,
+    Query(QueryArgs),
+    // print the records of a running logger
+    Tail(TailArgs),
+}
+
+// options given on the command line
+pub struct Args {
+    pub config_path: PathBuf,
+    pub help: bool,
+    pub command: Command,
+}
+
+pub fn parse_args(args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut config_path = PathBuf::from("config.toml");
+    let mut help = false;
+    let mut command = Command::Run;
+
+    let mut args = args.skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), &mut command) {
+            ("-c" | "--config", _) => match args.next() {
+                Some(path) => config_path = PathBuf::from(path),
+                None => return Err(format!("{arg} requires a path")),
+            },
+            ("-h" | "--help", _) => help = true,
+            ("query", Command::Run) => command = Command::Query(QueryArgs::default()),
+            ("--sink", Command::Query(query)) => query.sink = Some(value(&mut args, &arg)?),
+            ("--from", Command::Query(query)) => {
+                query.from = Some(parse_time(&value(&mut args, &arg)?)?)
+            }
+            ("--to", Command::Query(query)) => {
+                query.to = Some(parse_time(&value(&mut args, &arg)?)?)
+            }
+            ("--source", Command::Query(query)) => query.source = Some(value(&mut args, &arg)?),
+            ("--level", Command::Query(query)) => {
+                query.level = Some(value(&mut args, &arg)?.parse()?)
+            }
+            ("--grep", Command::Query(query)) => {
+                let grep = value(&mut args, &arg)?;
+                query.grep =
+                    Some(Regex::new(&grep).map_err(|err| format!("invalid --grep: {err}"))?);
+            }
+            ("--json", Command::Query(query)) => query.json = true,
+            ("--newest", Command::Query(query)) => query.newest = true,
+            ("--limit", Command::Query(query)) => {
+                let limit = value(&mut args, &arg)?;
+                query.limit = Some(limit.parse().map_err(|_| format!("invalid --limit '{limit}'"))?);
+            }
+            ("tail", Command::Run) => command = Command::Tail(TailArgs::default()),
+            ("--source", Command::Tail(tail)) => tail.filter.source = Some(value(&mut args, &arg)?),
+            ("--level", Command::Tail(tail)) => {
+                tail.filter.level = Some(value(&mut args, &arg)?.parse()?)
+            }
+            ("--grep", Command::Tail(tail)) => {
+                let grep = value(&mut args, &arg)?;
+                tail.filter.grep =
+                    Some(Regex::new(&grep).map_err(|err| format!("invalid --grep: {err}"))?);
+            }
+            ("--json", Command::Tail(tail)) => tail.filter.json = true,
+            ("--connect", Command::Tail(tail)) => tail.connect = Some(value(&mut args, &arg)?),
+            _ => match arg.strip_prefix("--config=") {
+                Some(path) => config_path = PathBuf::from(path),
+                None => return Err(format!("unknown argument '{arg}'")),
+            },
+        }
+    }
+
+    Ok(Args {
+        config_path,
+        help,
+        command,
+    })
+}
+
+fn value(args: &mut impl Iterator<Item = String>, arg: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("{arg} requires a value"))
+}
@@ -0,0 +1,246 @@
+use std::fmt;
+use std::str::{from_utf8, FromStr};
+
+// how the raw bytes of a datagram are turned into the text that gets logged
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodePolicy {
+    // reject anything that is not valid utf-8
+    Strict,
+    // replace invalid utf-8 sequences with U+FFFD
+    Lossy,
+    // every byte is a code point, as used by older plc string types
+    Latin1,
+    // latin-1 with the 0x80 - 0x9f range mapped as on windows
+    Windows1252,
+    // raw bytes as a hex dump
+    Hex,
+    // raw bytes as base64
+    Base64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decoded {
+    // payload was valid utf-8 and is logged as is
+    Text(String),
+    // payload had to be converted to be logged
+    Transcoded(String),
+}
+
+impl Decoded {
+    pub fn into_string(self) -> String {
+        match self {
+            Decoded::Text(text) => text,
+            Decoded::Transcoded(text) => text,
+        }
+    }
+}
+
+impl FromStr for DecodePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" | "utf8" | "utf-8" => Ok(DecodePolicy::Strict),
+            "lossy" => Ok(DecodePolicy::Lossy),
+            "latin1" | "latin-1" | "iso-8859-1" => Ok(DecodePolicy::Latin1),
+            "windows1252" | "windows-1252" | "cp1252" => Ok(DecodePolicy::Windows1252),
+            "hex" => Ok(DecodePolicy::Hex),
+            "base64" => Ok(DecodePolicy::Base64),
+            _ => Err(format!(
+                "unknown decode policy '{s}', expected one of: strict, lossy, latin1, windows1252, hex, base64"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DecodePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DecodePolicy::Strict => "strict",
+            DecodePolicy::Lossy => "lossy",
+            DecodePolicy::Latin1 => "latin1",
+            DecodePolicy::Windows1252 => "windows1252",
+            DecodePolicy::Hex => "hex",
+            DecodePolicy::Base64 => "base64",
+        };
+        write!(f, "{name}")
+    }
+}
+
+// characters for 0x80 - 0x9f in windows-1252, undefined bytes fall back to latin-1
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}', '\u{17e}', '\u{178}',
+];
+
+// decode a payload according to the policy, an error means the payload was rejected
+pub fn decode(policy: DecodePolicy, data: &[u8]) -> Result<Decoded, String> {
+    match policy {
+        DecodePolicy::Strict => match from_utf8(data) {
+            Ok(text) => Ok(Decoded::Text(text.to_string())),
+            Err(err) => Err(err.to_string()),
+        },
+        DecodePolicy::Lossy => match from_utf8(data) {
+            Ok(text) => Ok(Decoded::Text(text.to_string())),
+            Err(_) => Ok(Decoded::Transcoded(
+                String::from_utf8_lossy(data).into_owned(),
+            )),
+        },
+        DecodePolicy::Latin1 | DecodePolicy::Windows1252 => {
+            if data.is_ascii() {
+                // identical in every supported encoding
+                return Ok(Decoded::Text(from_utf8(data).unwrap().to_string()));
+            }
+            let text = data
+                .iter()
+                .map(|&byte| match (policy, byte) {
+                    (DecodePolicy::Windows1252, 0x80..=0x9f) => {
+                        WINDOWS_1252_HIGH[(byte - 0x80) as usize]
+                    }
+                    _ => byte as char,
+                })
+                .collect();
+            Ok(Decoded::Transcoded(text))
+        }
+        DecodePolicy::Hex => {
+            let text = data
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<Vec<String>>()
+                .join(" ");
+            Ok(Decoded::Transcoded(text))
+        }
+        DecodePolicy::Base64 => Ok(Decoded::Transcoded(base64::encode(data))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICIES: [DecodePolicy; 6] = [
+        DecodePolicy::Strict,
+        DecodePolicy::Lossy,
+        DecodePolicy::Latin1,
+        DecodePolicy::Windows1252,
+        DecodePolicy::Hex,
+        DecodePolicy::Base64,
+    ];
+
+    fn text(text: &str) -> Result<Decoded, String> {
+        Ok(Decoded::Text(text.to_string()))
+    }
+
+    fn transcoded(text: &str) -> Result<Decoded, String> {
+        Ok(Decoded::Transcoded(text.to_string()))
+    }
+
+    #[test]
+    fn valid_utf8() {
+        let data = "Füllstand 80%".as_bytes();
+        for (policy, expected) in [
+            (DecodePolicy::Strict, text("Füllstand 80%")),
+            (DecodePolicy::Lossy, text("Füllstand 80%")),
+            // every byte is a character of its own, utf-8 does not survive
+            (DecodePolicy::Latin1, transcoded("FÃ¼llstand 80%")),
+            (DecodePolicy::Windows1252, transcoded("FÃ¼llstand 80%")),
+            (
+                DecodePolicy::Hex,
+                transcoded("46 c3 bc 6c 6c 73 74 61 6e 64 20 38 30 25"),
+            ),
+            (DecodePolicy::Base64, transcoded("RsO8bGxzdGFuZCA4MCU=")),
+        ] {
+            assert_eq!(decode(policy, data), expected, "{policy}");
+        }
+    }
+
+    #[test]
+    fn ascii_is_logged_as_is() {
+        for policy in POLICIES {
+            let expected = match policy {
+                DecodePolicy::Hex => transcoded("6f 6b"),
+                DecodePolicy::Base64 => transcoded("b2s="),
+                _ => text("ok"),
+            };
+            assert_eq!(decode(policy, b"ok"), expected, "{policy}");
+        }
+        assert_eq!(decode(DecodePolicy::Hex, b""), transcoded(""));
+        assert_eq!(decode(DecodePolicy::Strict, b""), text(""));
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        // latin-1 text from an older plc, 0x80 and 0x96 differ in windows-1252
+        let data = b"F\xfcllstand \x80 5 \x96 80%";
+        for (policy, expected) in [
+            (
+                DecodePolicy::Strict,
+                Err(String::from(
+                    "invalid utf-8 sequence of 1 bytes from index 1",
+                )),
+            ),
+            (
+                DecodePolicy::Lossy,
+                transcoded("F\u{fffd}llstand \u{fffd} 5 \u{fffd} 80%"),
+            ),
+            (
+                DecodePolicy::Latin1,
+                transcoded("F\u{fc}llstand \u{80} 5 \u{96} 80%"),
+            ),
+            (DecodePolicy::Windows1252, transcoded("Füllstand € 5 – 80%")),
+            (
+                DecodePolicy::Hex,
+                transcoded("46 fc 6c 6c 73 74 61 6e 64 20 80 20 35 20 96 20 38 30 25"),
+            ),
+            (
+                DecodePolicy::Base64,
+                transcoded("RvxsbHN0YW5kIIAgNSCWIDgwJQ=="),
+            ),
+        ] {
+            assert_eq!(decode(policy, data), expected, "{policy}");
+        }
+    }
+
+    #[test]
+    fn windows_1252_range() {
+        let data: Vec<u8> = (0x80..=0x9f).collect();
+        let decoded = decode(DecodePolicy::Windows1252, &data)
+            .unwrap()
+            .into_string();
+        assert_eq!(
+            decoded,
+            "€\u{81}‚ƒ„…†‡ˆ‰Š‹Œ\u{8d}Ž\u{8f}\u{90}‘’“”•–—˜™š›œ\u{9d}žŸ"
+        );
+        // the undefined bytes are the only ones kept as latin-1
+        let latin1 = decode(DecodePolicy::Latin1, &data).unwrap().into_string();
+        let same: Vec<u32> = decoded
+            .chars()
+            .zip(latin1.chars())
+            .filter(|(windows, latin1)| windows == latin1)
+            .map(|(windows, _)| windows as u32)
+            .collect();
+        assert_eq!(same, [0x81, 0x8d, 0x8f, 0x90, 0x9d]);
+        let upper: Vec<u8> = (0xa0..=0xff).collect();
+        assert_eq!(
+            decode(DecodePolicy::Windows1252, &upper),
+            decode(DecodePolicy::Latin1, &upper)
+        );
+    }
+
+    #[test]
+    fn policy_names() {
+        for policy in POLICIES {
+            assert_eq!(policy.to_string().parse(), Ok(policy));
+        }
+        for (name, policy) in [
+            ("UTF-8", DecodePolicy::Strict),
+            ("iso-8859-1", DecodePolicy::Latin1),
+            ("CP1252", DecodePolicy::Windows1252),
+        ] {
+            assert_eq!(name.parse(), Ok(policy));
+        }
+        assert!("ebcdic".parse::<DecodePolicy>().is_err());
+    }
+}
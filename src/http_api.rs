@@ -0,0 +1,582 @@
+use chrono::{DateTime, Local, Utc};
+use log::{error, info, warn};
+use regex::Regex;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Header, Request, Response, Server, StatusCode};
+
+use crate::listeners::Protocol;
+use crate::metrics::{self, SinkUsage};
+use crate::plc_writer::source_paths;
+use crate::query::{archives_in_order, parse_time, search, QueryArgs, QueryError};
+use crate::record::RECEIVED_FORMAT;
+use crate::settings::AppConfig;
+use crate::sinks::SinkFormat;
+use crate::sqlite_sink;
+use crate::stats::Stats;
+
+// requests served at the same time
+const WORKERS: usize = 4;
+// records returned by /records unless the request asks for fewer
+const MAX_RECORDS: usize = 1000;
+// records waiting for the writer, as a share of queue_depth, at which the logger is
+// reported as falling behind
+const BACKLOG_LIMIT: f64 = 0.9;
+// seconds a drop or failure keeps the health from being ok
+const HEALTH_WINDOW_SECS: i64 = 300;
+
+// settings of the http api, present when http_port is set
+#[derive(Debug, Clone)]
+pub struct HttpSettings {
+    pub addr: SocketAddr,
+    // requests have to carry "Authorization: Bearer <token>" when set
+    pub token: Option<String>,
+}
+
+// read-only view of the logger for dashboards
+struct Api {
+    appconfig: Arc<AppConfig>,
+    stats: Arc<Stats>,
+    started: DateTime<Local>,
+}
+
+// failing while records failed to be written or synced, degraded while messages were
+// dropped or the writer falls behind, failures count for HEALTH_WINDOW_SECS. nothing
+// is reset by asking, every poller gets the same answer
+fn health(stats: &Stats, queue_depth: usize, now: i64) -> (&'static str, Vec<String>) {
+    let recent = |counter: &AtomicU64, at: &AtomicI64, what: &str| {
+        let at = at.load(Ordering::Relaxed);
+        (at > 0 && now - at < HEALTH_WINDOW_SECS).then(|| {
+            format!(
+                "{what} {}s ago, {} in total",
+                (now - at).max(0),
+                counter.load(Ordering::Relaxed)
+            )
+        })
+    };
+    let mut failing = Vec::new();
+    failing.extend(recent(&stats.write_failed, &stats.write_failed_at, "records failed to write"));
+    failing.extend(recent(&stats.sync_failed, &stats.sync_failed_at, "syncs to disk failed"));
+    let mut degraded = Vec::new();
+    degraded.extend(recent(&stats.dropped, &stats.dropped_at, "messages dropped, queue full"));
+    let backlog = stats.records_queued.load(Ordering::Relaxed).max(0);
+    if backlog as f64 >= queue_depth as f64 * BACKLOG_LIMIT {
+        degraded.push(format!("{backlog} of {queue_depth} records waiting for the writer"));
+    }
+    let status = match (failing.is_empty(), degraded.is_empty()) {
+        (false, _) => "failing",
+        (true, false) => "degraded",
+        (true, true) => "ok",
+    };
+    failing.extend(degraded);
+    (status, failing)
+}
+
+// an archive file of a sink, split sinks have archives per source
+struct Archive {
+    sink: String,
+    source: Option<String>,
+    path: PathBuf,
+}
+
+impl Archive {
+    fn name(&self) -> String {
+        self.path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn url(&self) -> String {
+        let mut parts = vec![self.sink.as_str()];
+        parts.extend(self.source.as_deref());
+        let name = self.name();
+        parts.push(&name);
+        let parts: Vec<String> = parts
+            .iter()
+            .map(|part| urlencoding::encode(part).into_owned())
+            .collect();
+        format!("/archives/{}", parts.join("/"))
+    }
+}
+
+type HttpResponse = Response<Box<dyn Read + Send>>;
+
+pub fn spawn_server(
+    settings: HttpSettings,
+    appconfig: Arc<AppConfig>,
+    stats: Arc<Stats>,
+) -> Result<(), String> {
+    let server = Server::http(settings.addr).map_err(|err| err.to_string())?;
+    info!("Serving the http api on {}", settings.addr);
+    let server = Arc::new(server);
+    let api = Arc::new(Api {
+        appconfig,
+        stats,
+        started: Local::now(),
+    });
+    let token = settings.token.map(|token| format!("Bearer {token}"));
+
+    for _ in 0..WORKERS {
+        let server = Arc::clone(&server);
+        let api = Arc::clone(&api);
+        let token = token.clone();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let authorized = token.as_ref().is_none_or(|token| {
+                    request
+                        .headers()
+                        .iter()
+                        .any(|header| header.field.equiv("Authorization") && header.value == *token)
+                });
+                let response = match (authorized, request.method()) {
+                    (false, _) => error_response(401, "missing or wrong bearer token")
+                        .with_header(header("WWW-Authenticate", "Bearer")),
+                    (true, tiny_http::Method::Get) => api.handle(&request),
+                    (true, _) => error_response(405, "only GET requests are served"),
+                };
+                if let Err(err) = request.respond(response) {
+                    warn!("Failed to answer http request: {err}");
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+impl Api {
+    fn handle(&self, request: &Request) -> HttpResponse {
+        let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+        let params = match query_params(query) {
+            Ok(params) => params,
+            Err(err) => return error_response(400, &err),
+        };
+        match path {
+            "/health" => {
+                let health = self.health();
+                // failing checks make load balancers and monitors notice
+                let status = match health["status"] == "failing" {
+                    true => 503,
+                    false => 200,
+                };
+                json_response(status, &health)
+            }
+            "/sources" => json_response(200, &self.sources()),
+            "/config" => json_response(200, &self.appconfig.redacted),
+            "/records" => self.records(&params),
+            "/archives" => json_response(200, &self.archive_list()),
+            "/metrics" => {
+                let metrics = metrics::render(&self.appconfig, &self.stats, &self.usage());
+                body_response(200, metrics.into_bytes(), "text/plain; version=0.0.4")
+            }
+            _ => match path.strip_prefix("/archives/") {
+                Some(archive) => self.download(archive),
+                None => error_response(404, &format!("no such endpoint '{path}'")),
+            },
+        }
+    }
+
+    fn health(&self) -> Value {
+        let counters: Map<String, Value> = self
+            .stats
+            .counters()
+            .into_iter()
+            .map(|(name, _, value)| (name.to_string(), Value::from(value)))
+            .collect();
+        let last_received = self
+            .appconfig
+            .sources
+            .sources()
+            .iter()
+            .filter_map(|source| self.stats.sources.get(&source.name).last_received)
+            .max();
+        let listeners: Vec<Value> = self
+            .appconfig
+            .listeners
+            .iter()
+            .map(|listener| {
+                let protocol = match listener.protocol {
+                    Protocol::Udp(_) => "udp",
+                    Protocol::Tcp(_) => "tcp",
+                };
+                json!({
+                    "name": &*listener.name,
+                    "protocol": protocol,
+                    "port": listener.port,
+                    "sink": &*listener.sink,
+                })
+            })
+            .collect();
+        let (status, problems) = self.status();
+        json!({
+            "status": status,
+            "problems": problems,
+            "version": env!("CARGO_PKG_VERSION"),
+            "started": self.started.format(RECEIVED_FORMAT).to_string(),
+            "uptime_secs": (Local::now() - self.started).num_seconds(),
+            "last_received": last_received,
+            "listeners": listeners,
+            "counters": counters,
+        })
+    }
+
+    fn status(&self) -> (&'static str, Vec<String>) {
+        health(&self.stats, self.appconfig.queue_depth, Utc::now().timestamp())
+    }
+
+    // configured sources with what they sent so far
+    fn sources(&self) -> Value {
+        let sequences: HashMap<String, _> = self
+            .stats
+            .sequences
+            .by_source(&self.appconfig.sources)
+            .into_iter()
+            .collect();
+        let sources: Vec<Value> = self
+            .appconfig
+            .sources
+            .sources()
+            .iter()
+            .map(|source| {
+                let mut object = json!({
+                    "name": source.name,
+                    "area": source.area,
+                    "line": source.line,
+                    "min_level": source.min_level.as_str(),
+                });
+                let counters = serde_json::to_value(self.stats.sources.get(&source.name));
+                if let (Value::Object(object), Ok(Value::Object(counters))) =
+                    (&mut object, counters)
+                {
+                    object.extend(counters);
+                }
+                if let Some(sequence) = sequences.get(&source.name) {
+                    object["sequence"] = json!({
+                        "received": sequence.received,
+                        "lost": sequence.lost,
+                        "duplicates": sequence.duplicates,
+                        "reordered": sequence.reordered,
+                        "resets": sequence.resets,
+                    });
+                }
+                object
+            })
+            .collect();
+        Value::Array(sources)
+    }
+
+    // the filters of `plclogger query` as parameters, json lines unless format=text
+    fn records(&self, params: &[(String, String)]) -> HttpResponse {
+        let args = match record_filters(params) {
+            Ok(args) => args,
+            Err(err) => return error_response(400, &err),
+        };
+        let mut out = Vec::new();
+        match search(&self.appconfig, &args, &mut out) {
+            Ok(_) => {
+                let content_type = match args.json {
+                    true => "application/x-ndjson",
+                    false => "text/plain; charset=utf-8",
+                };
+                body_response(200, out, content_type)
+            }
+            Err(QueryError::Message(err)) => error_response(400, &err),
+            Err(QueryError::Io(err)) => {
+                error!("Failed to search records for the http api: {err}");
+                error_response(500, &err.to_string())
+            }
+        }
+    }
+
+    // every archive of the text and json sinks, oldest first per log
+    fn archives(&self) -> Vec<Archive> {
+        let mut archives = Vec::new();
+        for sink in &self.appconfig.sinks {
+            if sink.format == SinkFormat::Sqlite {
+                continue;
+            }
+            if !sink.split_by_source {
+                archives.extend(
+                    archives_in_order(&sink.archive_pattern)
+                        .into_iter()
+                        .map(|path| Archive {
+                            sink: sink.name.clone(),
+                            source: None,
+                            path,
+                        }),
+                );
+                continue;
+            }
+            for source in self.appconfig.sources.sources() {
+                let (_, archive_pattern) = source_paths(sink, &source);
+                archives.extend(archives_in_order(&archive_pattern).into_iter().map(|path| {
+                    Archive {
+                        sink: sink.name.clone(),
+                        source: Some(source.name.clone()),
+                        path,
+                    }
+                }));
+            }
+        }
+        archives
+    }
+
+    // disk space of every sink, the archives of the text and json sinks and the sqlite
+    // databases
+    fn usage(&self) -> Vec<SinkUsage> {
+        let archives = self.archives();
+        self.appconfig
+            .sinks
+            .iter()
+            .map(|sink| {
+                let sizes: Vec<u64> = archives
+                    .iter()
+                    .filter(|archive| archive.sink == sink.name)
+                    .filter_map(|archive| fs::metadata(&archive.path).ok())
+                    .map(|metadata| metadata.len())
+                    .collect();
+                let database_bytes = match sink.format {
+                    SinkFormat::Sqlite => sqlite_sink::size(&sink.log_path),
+                    _ => 0,
+                };
+                SinkUsage {
+                    sink: sink.name.clone(),
+                    archives: sizes.len() as u64,
+                    archive_bytes: sizes.iter().sum(),
+                    database_bytes,
+                }
+            })
+            .collect()
+    }
+
+    fn archive_list(&self) -> Value {
+        let archives: Vec<Value> = self
+            .archives()
+            .iter()
+            .filter_map(|archive| {
+                let metadata = fs::metadata(&archive.path).ok()?;
+                let modified = metadata.modified().ok().map(|modified| {
+                    DateTime::<Local>::from(modified)
+                        .format(RECEIVED_FORMAT)
+                        .to_string()
+                });
+                Some(json!({
+                    "sink": archive.sink,
+                    "source": archive.source,
+                    "name": archive.name(),
+                    "size": metadata.len(),
+                    "modified": modified,
+                    "url": archive.url(),
+                }))
+            })
+            .collect();
+        Value::Array(archives)
+    }
+
+    // only files in the archive list are served, the request never becomes a path
+    fn download(&self, archive: &str) -> HttpResponse {
+        let parts: Vec<String> = archive
+            .split('/')
+            .map(|part| {
+                urlencoding::decode(part)
+                    .map(|part| part.into_owned())
+                    .unwrap_or_default()
+            })
+            .collect();
+        let found = self.archives().into_iter().find(|candidate| {
+            let mut wanted = vec![candidate.sink.clone()];
+            wanted.extend(candidate.source.clone());
+            wanted.push(candidate.name());
+            wanted == parts
+        });
+        let archive = match found {
+            Some(archive) => archive,
+            None => return error_response(404, &format!("no such archive '{archive}'")),
+        };
+        let file = match File::open(&archive.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return error_response(
+                    404,
+                    &format!("no such archive '{archive}'", archive = archive.name()),
+                )
+            }
+            Err(err) => {
+                error!(
+                    "Failed to open {} for download: {err}",
+                    archive.path.display()
+                );
+                return error_response(500, &err.to_string());
+            }
+        };
+        let content_type = match archive.path.extension().is_some_and(|ext| ext == "gz") {
+            true => "application/gzip",
+            false => "application/octet-stream",
+        };
+        let length = file.metadata().ok().map(|metadata| metadata.len() as usize);
+        Response::new(
+            StatusCode(200),
+            vec![
+                header("Content-Type", content_type),
+                header(
+                    "Content-Disposition",
+                    &format!("attachment; filename=\"{}\"", archive.name()),
+                ),
+            ],
+            Box::new(file) as Box<dyn Read + Send>,
+            length,
+            None,
+        )
+    }
+}
+
+fn record_filters(params: &[(String, String)]) -> Result<QueryArgs, String> {
+    // the latest records unless asked otherwise, a request without a time range then
+    // stops at the newest files
+    let mut args = QueryArgs {
+        json: true,
+        limit: Some(MAX_RECORDS),
+        newest: true,
+        ..Default::default()
+    };
+    for (key, value) in params {
+        match key.as_str() {
+            "sink" => args.sink = Some(value.clone()),
+            "from" => args.from = Some(parse_time(value)?),
+            "to" => args.to = Some(parse_time(value)?),
+            "source" => args.source = Some(value.clone()),
+            "level" => args.level = Some(value.parse()?),
+            "grep" => {
+                args.grep = Some(Regex::new(value).map_err(|err| format!("invalid grep: {err}"))?)
+            }
+            "format" => match value.as_str() {
+                "json" => args.json = true,
+                "text" => args.json = false,
+                _ => return Err(format!("unknown format '{value}', expected json or text")),
+            },
+            "order" => match value.as_str() {
+                "newest" => args.newest = true,
+                "oldest" => args.newest = false,
+                _ => return Err(format!("unknown order '{value}', expected newest or oldest")),
+            },
+            "limit" => match value.parse() {
+                Ok(limit) if (1..=MAX_RECORDS).contains(&limit) => args.limit = Some(limit),
+                _ => return Err(format!("limit must be between 1 - {MAX_RECORDS}")),
+            },
+            _ => {
+                return Err(format!(
+                    "unknown parameter '{key}', expected one of: sink, from, to, source, level, grep, format, order, limit"
+                ))
+            }
+        }
+    }
+    Ok(args)
+}
+
+// "a=1&b=x%20y" as pairs, + standing for a space as sent by html forms
+fn query_params(query: &str) -> Result<Vec<(String, String)>, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = urlencoding::decode(&value.replace('+', " "))
+                .map_err(|_| format!("invalid value for '{key}'"))?
+                .into_owned();
+            Ok((key.to_string(), value))
+        })
+        .collect()
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn body_response(status: u16, body: Vec<u8>, content_type: &str) -> HttpResponse {
+    let length = body.len();
+    Response::new(
+        StatusCode(status),
+        vec![header("Content-Type", content_type)],
+        Box::new(Cursor::new(body)) as Box<dyn Read + Send>,
+        Some(length),
+        None,
+    )
+}
+
+fn json_response(status: u16, value: &Value) -> HttpResponse {
+    body_response(status, value.to_string().into_bytes(), "application/json")
+}
+
+fn error_response(status: u16, message: &str) -> HttpResponse {
+    json_response(status, &json!({ "error": message }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    #[test]
+    fn ok_without_failures() {
+        let stats = Stats::default();
+        assert_eq!(health(&stats, 100, NOW), ("ok", Vec::new()));
+    }
+
+    #[test]
+    fn asking_twice_gives_the_same_answer() {
+        let stats = Stats::default();
+        stats.write_failed.store(3, Ordering::Relaxed);
+        stats.write_failed_at.store(NOW - 10, Ordering::Relaxed);
+        let first = health(&stats, 100, NOW);
+        assert_eq!(first.0, "failing");
+        assert_eq!(first.1, ["records failed to write 10s ago, 3 in total"]);
+        assert_eq!(health(&stats, 100, NOW), first);
+    }
+
+    #[test]
+    fn failures_count_within_the_window() {
+        let stats = Stats::default();
+        stats.sync_failed.store(1, Ordering::Relaxed);
+        stats
+            .sync_failed_at
+            .store(NOW - HEALTH_WINDOW_SECS + 1, Ordering::Relaxed);
+        assert_eq!(health(&stats, 100, NOW).0, "failing");
+        stats
+            .sync_failed_at
+            .store(NOW - HEALTH_WINDOW_SECS, Ordering::Relaxed);
+        assert_eq!(health(&stats, 100, NOW).0, "ok");
+    }
+
+    #[test]
+    fn degraded_by_drops_and_backlog() {
+        let stats = Stats::default();
+        stats.dropped.store(5, Ordering::Relaxed);
+        stats.dropped_at.store(NOW, Ordering::Relaxed);
+        stats.records_queued.store(90, Ordering::Relaxed);
+        let (status, problems) = health(&stats, 100, NOW);
+        assert_eq!(status, "degraded");
+        assert_eq!(
+            problems,
+            [
+                "messages dropped, queue full 0s ago, 5 in total",
+                "90 of 100 records waiting for the writer"
+            ]
+        );
+        // failures come first and win
+        stats.write_failed.store(1, Ordering::Relaxed);
+        stats.write_failed_at.store(NOW, Ordering::Relaxed);
+        let (status, problems) = health(&stats, 100, NOW);
+        assert_eq!(status, "failing");
+        assert_eq!(problems[0], "records failed to write 0s ago, 1 in total");
+        assert_eq!(problems.len(), 3);
+    }
+}
@@ -0,0 +1,512 @@
+use chrono::NaiveDate;
+use serde::Deserialize;
+
+use crate::parsers::FieldValue;
+
+// a [[layouts]] entry from config.toml, the memory block a plc sends as a datagram
+#[derive(Debug, Deserialize)]
+pub struct LayoutConfig {
+    pub name: String,
+    // big (siemens, default) or little
+    pub endianness: Option<String>,
+    // expected datagram length, defaults to the end of the last field
+    pub size: Option<usize>,
+    pub fields: Vec<LayoutFieldConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LayoutFieldConfig {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub field_type: String,
+    // byte offset from the start of the datagram
+    pub offset: usize,
+    // bool: bit within the byte, 0 being the least significant
+    pub bit: Option<u8>,
+    // string and s7_string: number of characters
+    pub length: Option<usize>,
+    // overrides the endianness of the layout
+    pub endianness: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Endianness {
+    Big,
+    Little,
+}
+
+fn parse_endianness(s: &str) -> Result<Endianness, String> {
+    match s {
+        "big" => Ok(Endianness::Big),
+        "little" => Ok(Endianness::Little),
+        _ => Err(format!("unknown endianness '{s}', expected big or little")),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldType {
+    Bool(u8),
+    Byte,
+    Sint,
+    Int,
+    Uint,
+    Dint,
+    Udint,
+    Lint,
+    Real,
+    Lreal,
+    // fixed number of characters, padded with nul or spaces
+    Str(usize),
+    // siemens STRING, max and actual length bytes in front of the characters
+    S7Str(usize),
+    // siemens DATE_AND_TIME, bcd digits from the year (2 digits) down to milliseconds
+    DateAndTime,
+    // siemens DTL, year, month, day, weekday, hour, minute, second and nanoseconds
+    Dtl,
+}
+
+impl FieldType {
+    fn size(&self) -> usize {
+        match self {
+            FieldType::Bool(_) | FieldType::Byte | FieldType::Sint => 1,
+            FieldType::Int | FieldType::Uint => 2,
+            FieldType::Dint | FieldType::Udint | FieldType::Real => 4,
+            FieldType::Lint | FieldType::Lreal | FieldType::DateAndTime => 8,
+            FieldType::Dtl => 12,
+            FieldType::Str(length) => *length,
+            FieldType::S7Str(length) => length + 2,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct LayoutField {
+    name: String,
+    field_type: FieldType,
+    offset: usize,
+    endianness: Endianness,
+}
+
+// decodes binary datagrams into named values
+#[derive(Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub name: String,
+    size: usize,
+    fields: Vec<LayoutField>,
+}
+
+impl Layout {
+    pub fn new(config: &LayoutConfig) -> Result<Layout, String> {
+        let name = &config.name;
+        let endianness = match &config.endianness {
+            Some(endianness) => parse_endianness(endianness).map_err(|err| format!("layout '{name}': {err}"))?,
+            None => Endianness::Big,
+        };
+        if config.fields.is_empty() {
+            return Err(format!("layout '{name}' has no fields"));
+        }
+
+        let mut fields: Vec<LayoutField> = Vec::new();
+        for field in &config.fields {
+            let invalid = |msg: &str| format!("layout '{name}', field '{}': {msg}", field.name);
+            if fields.iter().any(|other| other.name == field.name) {
+                return Err(format!("layout '{name}': duplicate field '{}'", field.name));
+            }
+            let length = || match field.length {
+                Some(length) if (1..=254).contains(&length) => Ok(length),
+                _ => Err(invalid("strings need a length between 1 - 254")),
+            };
+            let field_type = match field.field_type.to_ascii_lowercase().as_str() {
+                "bool" => match field.bit {
+                    Some(bit) if bit < 8 => FieldType::Bool(bit),
+                    _ => return Err(invalid("bool fields need a bit between 0 - 7")),
+                },
+                "byte" | "usint" => FieldType::Byte,
+                "sint" => FieldType::Sint,
+                "int" => FieldType::Int,
+                "uint" | "word" => FieldType::Uint,
+                "dint" => FieldType::Dint,
+                "udint" | "dword" => FieldType::Udint,
+                "lint" => FieldType::Lint,
+                "real" => FieldType::Real,
+                "lreal" => FieldType::Lreal,
+                "string" => FieldType::Str(length()?),
+                "s7_string" => FieldType::S7Str(length()?),
+                "date_and_time" | "dt" => FieldType::DateAndTime,
+                "dtl" => FieldType::Dtl,
+                field_type => {
+                    return Err(invalid(&format!(
+                        "unknown type '{field_type}', expected one of: bool, byte, sint, int, uint, dint, udint, lint, real, lreal, string, s7_string, date_and_time, dtl"
+                    )))
+                }
+            };
+            if field.bit.is_some() && !matches!(field_type, FieldType::Bool(_)) {
+                return Err(invalid("bit is only used with bool fields"));
+            }
+            let field_endianness = match &field.endianness {
+                Some(endianness) => parse_endianness(endianness).map_err(|err| invalid(&err))?,
+                None => endianness,
+            };
+            fields.push(LayoutField {
+                name: field.name.clone(),
+                field_type,
+                offset: field.offset,
+                endianness: field_endianness,
+            });
+        }
+
+        let end = fields
+            .iter()
+            .map(|field| field.offset + field.field_type.size())
+            .max()
+            .unwrap_or(0);
+        let size = config.size.unwrap_or(end);
+        if size < end {
+            return Err(format!(
+                "layout '{name}': fields end at byte {end}, beyond its size of {size}"
+            ));
+        }
+
+        Ok(Layout {
+            name: name.clone(),
+            size,
+            fields,
+        })
+    }
+
+    // shorter datagrams are rejected, longer ones decoded as far as the layout goes
+    pub fn decode(&self, data: &[u8]) -> Result<Vec<(String, FieldValue)>, String> {
+        if data.len() < self.size {
+            return Err(format!("{} bytes, expected {}", data.len(), self.size));
+        }
+        self.fields
+            .iter()
+            .map(|field| {
+                let bytes = &data[field.offset..field.offset + field.field_type.size()];
+                let value = field
+                    .value(bytes)
+                    .map_err(|err| format!("field '{}': {err}", field.name))?;
+                Ok((field.name.clone(), value))
+            })
+            .collect()
+    }
+}
+
+impl LayoutField {
+    fn value(&self, bytes: &[u8]) -> Result<FieldValue, String> {
+        // the bytes are in big-endian order from here on
+        let mut ordered = bytes.to_vec();
+        let numeric = !matches!(
+            self.field_type,
+            FieldType::Str(_) | FieldType::S7Str(_) | FieldType::DateAndTime | FieldType::Dtl
+        );
+        if numeric && self.endianness == Endianness::Little {
+            ordered.reverse();
+        }
+        let int = |bytes: &[u8]| bytes.iter().fold(0u64, |acc, b| (acc << 8) | u64::from(*b));
+        let value = match self.field_type {
+            FieldType::Bool(bit) => FieldValue::Bool((bytes[0] >> bit) & 1 == 1),
+            FieldType::Byte => FieldValue::Int(i64::from(ordered[0])),
+            FieldType::Sint => FieldValue::Int(i64::from(ordered[0] as i8)),
+            FieldType::Int => FieldValue::Int(i64::from(int(&ordered) as u16 as i16)),
+            FieldType::Uint => FieldValue::Int(int(&ordered) as i64),
+            FieldType::Dint => FieldValue::Int(i64::from(int(&ordered) as u32 as i32)),
+            FieldType::Udint => FieldValue::Int(int(&ordered) as i64),
+            FieldType::Lint => FieldValue::Int(int(&ordered) as i64),
+            // through the shortest text of the f32, so 21.3 is not logged as 21.299999237060547
+            FieldType::Real => {
+                let value = f32::from_bits(int(&ordered) as u32);
+                FieldValue::Float(value.to_string().parse().unwrap_or(f64::from(value)))
+            }
+            FieldType::Lreal => FieldValue::Float(f64::from_bits(int(&ordered))),
+            FieldType::Str(_) => FieldValue::Str(text(bytes)),
+            FieldType::S7Str(length) => {
+                let actual = usize::from(bytes[1]).min(length);
+                FieldValue::Str(text(&bytes[2..2 + actual]))
+            }
+            // times are logged as text, as expected by plc_timestamp = "field"
+            FieldType::DateAndTime => FieldValue::Str(date_and_time(bytes)?),
+            FieldType::Dtl => FieldValue::Str(dtl(bytes)?),
+        };
+        Ok(value)
+    }
+}
+
+fn bcd(byte: u8) -> Result<u32, String> {
+    let (high, low) = (byte >> 4, byte & 0x0f);
+    if high > 9 || low > 9 {
+        return Err(format!("{byte:#04x} is not a bcd value"));
+    }
+    Ok(u32::from(high * 10 + low))
+}
+
+fn date_and_time(bytes: &[u8]) -> Result<String, String> {
+    // two digit years from 1990 to 2089
+    let year = bcd(bytes[0])?;
+    let year = if year >= 90 { 1900 + year } else { 2000 + year };
+    let (month, day) = (bcd(bytes[1])?, bcd(bytes[2])?);
+    let (hour, minute, second) = (bcd(bytes[3])?, bcd(bytes[4])?, bcd(bytes[5])?);
+    let millis = bcd(bytes[6])? * 10 + u32::from(bytes[7] >> 4);
+    let time = NaiveDate::from_ymd_opt(year as i32, month, day)
+        .and_then(|date| date.and_hms_milli_opt(hour, minute, second, millis))
+        .ok_or("not a valid DATE_AND_TIME")?;
+    Ok(time.format("%Y-%m-%dT%H:%M:%S%.3f").to_string())
+}
+
+fn dtl(bytes: &[u8]) -> Result<String, String> {
+    let year = u16::from_be_bytes([bytes[0], bytes[1]]);
+    let nanos = u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+    let time = NaiveDate::from_ymd_opt(i32::from(year), u32::from(bytes[2]), u32::from(bytes[3]))
+        .and_then(|date| {
+            date.and_hms_nano_opt(u32::from(bytes[5]), u32::from(bytes[6]), u32::from(bytes[7]), nanos)
+        })
+        .ok_or("not a valid DTL")?;
+    Ok(time.format("%Y-%m-%dT%H:%M:%S%.9f").to_string())
+}
+
+// plc strings are single byte characters, padded at the end
+fn text(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    let text: String = bytes[..end].iter().map(|b| char::from(*b)).collect();
+    text.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str, field_type: &str, offset: usize) -> LayoutFieldConfig {
+        LayoutFieldConfig {
+            name: name.to_string(),
+            field_type: field_type.to_string(),
+            offset,
+            bit: None,
+            length: None,
+            endianness: None,
+        }
+    }
+
+    fn layout(endianness: Option<&str>, fields: Vec<LayoutFieldConfig>) -> Result<Layout, String> {
+        Layout::new(&LayoutConfig {
+            name: String::from("press"),
+            endianness: endianness.map(str::to_string),
+            size: None,
+            fields,
+        })
+    }
+
+    fn decode(layout: &Layout, data: &[u8]) -> Vec<FieldValue> {
+        let values = layout.decode(data).unwrap();
+        values.into_iter().map(|(_, value)| value).collect()
+    }
+
+    #[test]
+    fn integers_big_endian() {
+        let layout = layout(
+            None,
+            vec![
+                field("byte", "byte", 0),
+                field("sint", "sint", 1),
+                field("int", "int", 2),
+                field("uint", "word", 4),
+                field("dint", "dint", 6),
+                field("udint", "udint", 10),
+                field("lint", "lint", 14),
+            ],
+        )
+        .unwrap();
+        let data = [
+            0xff, 0xff, 0xff, 0xfe, 0xff, 0xfe, 0xff, 0xff, 0xff, 0xfd, 0x00, 0x01, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(
+            decode(&layout, &data),
+            vec![
+                FieldValue::Int(255),
+                FieldValue::Int(-1),
+                FieldValue::Int(-2),
+                FieldValue::Int(65534),
+                FieldValue::Int(-3),
+                FieldValue::Int(65536),
+                FieldValue::Int(1 << 32),
+            ]
+        );
+    }
+
+    #[test]
+    fn little_endian_layout_and_field_override() {
+        let mut big = field("big", "int", 2);
+        big.endianness = Some(String::from("big"));
+        let layout = layout(Some("little"), vec![field("little", "int", 0), big]).unwrap();
+        assert_eq!(
+            decode(&layout, &[0x01, 0x02, 0x01, 0x02]),
+            vec![FieldValue::Int(0x0201), FieldValue::Int(0x0102)]
+        );
+    }
+
+    #[test]
+    fn bools_by_bit() {
+        let mut low = field("low", "bool", 0);
+        low.bit = Some(0);
+        let mut high = field("high", "bool", 0);
+        high.bit = Some(7);
+        let mut off = field("off", "bool", 0);
+        off.bit = Some(3);
+        let layout = layout(None, vec![low, high, off]).unwrap();
+        assert_eq!(
+            decode(&layout, &[0b1000_0001]),
+            vec![
+                FieldValue::Bool(true),
+                FieldValue::Bool(true),
+                FieldValue::Bool(false)
+            ]
+        );
+    }
+
+    #[test]
+    fn reals_keep_their_shortest_text() {
+        let layout = layout(
+            None,
+            vec![field("real", "real", 0), field("lreal", "lreal", 4)],
+        )
+        .unwrap();
+        let mut data = 21.3f32.to_be_bytes().to_vec();
+        data.extend((-0.125f64).to_be_bytes());
+        assert_eq!(
+            decode(&layout, &data),
+            vec![FieldValue::Float(21.3), FieldValue::Float(-0.125)]
+        );
+    }
+
+    #[test]
+    fn strings_are_trimmed() {
+        let mut padded = field("padded", "string", 0);
+        padded.length = Some(8);
+        let mut s7 = field("s7", "s7_string", 8);
+        s7.length = Some(6);
+        let mut clamped = field("clamped", "s7_string", 16);
+        clamped.length = Some(2);
+        let layout = layout(None, vec![padded, s7, clamped]).unwrap();
+        let mut data = b"belt  \0x".to_vec();
+        data.extend(b"\x06\x04stopxx");
+        data.extend(b"\x02\x09ab");
+        assert_eq!(
+            decode(&layout, &data),
+            vec![
+                FieldValue::Str(String::from("belt")),
+                FieldValue::Str(String::from("stop")),
+                FieldValue::Str(String::from("ab"))
+            ]
+        );
+    }
+
+    #[test]
+    fn date_and_time_is_bcd() {
+        let layout = layout(None, vec![field("time", "date_and_time", 0)]).unwrap();
+        assert_eq!(
+            decode(&layout, &[0x24, 0x03, 0x15, 0x13, 0x45, 0x30, 0x12, 0x36]),
+            vec![FieldValue::Str(String::from("2024-03-15T13:45:30.123"))]
+        );
+        // two digit years from 1990
+        assert_eq!(
+            decode(&layout, &[0x95, 0x12, 0x31, 0x23, 0x59, 0x59, 0x99, 0x92]),
+            vec![FieldValue::Str(String::from("1995-12-31T23:59:59.999"))]
+        );
+        assert_eq!(
+            layout.decode(&[0x24, 0x1a, 0x15, 0x13, 0x45, 0x30, 0x12, 0x36]),
+            Err(String::from("field 'time': 0x1a is not a bcd value"))
+        );
+        assert_eq!(
+            layout.decode(&[0x24, 0x13, 0x15, 0x13, 0x45, 0x30, 0x12, 0x36]),
+            Err(String::from("field 'time': not a valid DATE_AND_TIME"))
+        );
+    }
+
+    #[test]
+    fn dtl_is_binary() {
+        let layout = layout(None, vec![field("time", "dtl", 0)]).unwrap();
+        let mut data = vec![0x07, 0xe8, 3, 15, 6, 13, 45, 30];
+        data.extend(123_456_789u32.to_be_bytes());
+        assert_eq!(
+            decode(&layout, &data),
+            vec![FieldValue::Str(String::from(
+                "2024-03-15T13:45:30.123456789"
+            ))]
+        );
+        data[2] = 2;
+        data[3] = 30;
+        assert_eq!(
+            layout.decode(&data),
+            Err(String::from("field 'time': not a valid DTL"))
+        );
+    }
+
+    #[test]
+    fn short_datagrams_are_rejected() {
+        let layout = layout(None, vec![field("count", "dint", 0)]).unwrap();
+        assert_eq!(
+            layout.decode(&[0, 0, 1]),
+            Err(String::from("3 bytes, expected 4"))
+        );
+        // longer ones are decoded as far as the layout goes
+        assert_eq!(decode(&layout, &[0, 0, 0, 1, 9]), vec![FieldValue::Int(1)]);
+    }
+
+    #[test]
+    fn invalid_layouts() {
+        let mut bit_out_of_range = field("flag", "bool", 0);
+        bit_out_of_range.bit = Some(8);
+        let mut bit_on_int = field("count", "int", 0);
+        bit_on_int.bit = Some(1);
+        let mut middle = field("count", "int", 0);
+        middle.endianness = Some(String::from("middle"));
+        let cases = [
+            (vec![], "layout 'press' has no fields"),
+            (
+                vec![field("count", "int", 0), field("count", "int", 2)],
+                "layout 'press': duplicate field 'count'",
+            ),
+            (
+                vec![bit_out_of_range],
+                "layout 'press', field 'flag': bool fields need a bit between 0 - 7",
+            ),
+            (
+                vec![bit_on_int],
+                "layout 'press', field 'count': bit is only used with bool fields",
+            ),
+            (
+                vec![field("name", "string", 0)],
+                "layout 'press', field 'name': strings need a length between 1 - 254",
+            ),
+            (
+                vec![middle],
+                "layout 'press', field 'count': unknown endianness 'middle', expected big or little",
+            ),
+        ];
+        for (fields, err) in cases {
+            assert_eq!(layout(None, fields), Err(String::from(err)));
+        }
+        assert!(layout(None, vec![field("count", "float", 0)])
+            .unwrap_err()
+            .starts_with("layout 'press', field 'count': unknown type 'float'"));
+        assert_eq!(
+            layout(Some("middle"), vec![field("count", "int", 0)]),
+            Err(String::from(
+                "layout 'press': unknown endianness 'middle', expected big or little"
+            ))
+        );
+
+        let too_small = Layout::new(&LayoutConfig {
+            name: String::from("press"),
+            endianness: None,
+            size: Some(3),
+            fields: vec![field("count", "dint", 0)],
+        });
+        assert_eq!(
+            too_small,
+            Err(String::from(
+                "layout 'press': fields end at byte 4, beyond its size of 3"
+            ))
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use log::info;
+use serde::Deserialize;
+use socket2::{Domain, Protocol as SocketProtocol, Socket, Type};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, SocketAddrV6, TcpListener, UdpSocket};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::decode::DecodePolicy;
+use crate::parsers::Parser;
+use crate::plc_time::PlcTimestamp;
+use crate::pipeline::{self, UdpSettings, WorkQueue};
+use crate::sources::SourceMap;
+use crate::stats::Stats;
+use crate::tcp::{self, TcpSettings};
+
+// a [[listeners]] entry from config.toml, unset keys fall back to the top-level settings
+#[derive(Debug, Default, Deserialize)]
+pub struct ListenerConfig {
+    pub name: Option<String>,
+    pub protocol: String,
+    pub bind: Option<BindConfig>,
+    pub port: i64,
+    pub decode_policy: Option<String>,
+    pub message_format: Option<String>,
+    pub parser: Option<String>,
+    pub sequence_numbers: Option<bool>,
+    pub ack: Option<bool>,
+    pub sink: Option<String>,
+    pub plc_timestamp: Option<String>,
+    pub plc_timestamp_field: Option<String>,
+    pub plc_timezone: Option<String>,
+    pub clock_skew_warn_secs: Option<i64>,
+    pub udp_max_datagram_bytes: Option<i64>,
+    pub udp_reassembly: Option<bool>,
+    pub udp_reassembly_timeout_ms: Option<i64>,
+    pub tcp_framing: Option<String>,
+    pub tcp_length_prefix_bytes: Option<i64>,
+    pub tcp_frame_size: Option<i64>,
+    pub tcp_max_connections: Option<i64>,
+    pub tcp_max_frame_bytes: Option<i64>,
+    pub tcp_idle_timeout_secs: Option<i64>,
+}
+
+// bind = "10.0.1.5" or bind = ["10.0.1.5", "eth1", "[fd00::5]"]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum BindConfig {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl BindConfig {
+    pub fn entries(&self) -> Vec<&str> {
+        match self {
+            BindConfig::One(entry) => vec![entry.as_str()],
+            BindConfig::Many(entries) => entries.iter().map(String::as_str).collect(),
+        }
+    }
+
+    // socket addresses to bind, entries are ip literals (ipv6 optionally in brackets)
+    // or the name of a network interface, which binds every address it has
+    pub fn resolve(&self, port: u16) -> Result<Vec<SocketAddr>, String> {
+        let mut addrs = Vec::new();
+        for entry in self.entries() {
+            let entry = entry.trim();
+            let literal = entry.strip_prefix('[').and_then(|ip| ip.strip_suffix(']'));
+            if let Ok(ip) = literal.unwrap_or(entry).parse::<IpAddr>() {
+                found_addrs(&mut addrs, vec![SocketAddr::new(ip, port)]);
+                continue;
+            }
+
+            let interfaces = if_addrs::get_if_addrs()
+                .map_err(|err| format!("failed to list network interfaces: {err}"))?;
+            let found: Vec<SocketAddr> = interfaces
+                .iter()
+                .filter(|interface| interface.name == entry)
+                .map(|interface| match interface.ip() {
+                    // link-local ipv6 addresses only work together with their interface
+                    IpAddr::V6(ip) if ip.is_unicast_link_local() => {
+                        SocketAddr::V6(SocketAddrV6::new(ip, port, 0, interface.index.unwrap_or(0)))
+                    }
+                    ip => SocketAddr::new(ip, port),
+                })
+                .collect();
+            if found.is_empty() {
+                return Err(format!(
+                    "bind address '{entry}' is neither an ip address nor an interface with an address"
+                ));
+            }
+            found_addrs(&mut addrs, found);
+        }
+
+        if addrs.is_empty() {
+            return Err(String::from("at least one bind address is required"));
+        }
+        Ok(addrs)
+    }
+}
+
+// an interface can be listed next to one of its own addresses, bind each only once
+fn found_addrs(addrs: &mut Vec<SocketAddr>, found: Vec<SocketAddr>) {
+    for addr in found {
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+}
+
+// how a decoded message is turned into a record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+    // the whole message is the payload
+    Raw,
+    // rfc 3164 or rfc 5424, the header goes into the record fields
+    Syslog,
+}
+
+impl FromStr for MessageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(MessageFormat::Raw),
+            "syslog" => Ok(MessageFormat::Syslog),
+            _ => Err(format!(
+                "unknown message format '{s}', expected one of: raw, syslog"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MessageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            MessageFormat::Raw => "raw",
+            MessageFormat::Syslog => "syslog",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Protocol {
+    Udp(UdpSettings),
+    Tcp(TcpSettings),
+}
+
+// a set of sockets the logger receives plc messages on
+#[derive(Debug)]
+pub struct Listener {
+    pub name: Arc<str>,
+    pub protocol: Protocol,
+    pub addrs: Vec<SocketAddr>,
+    pub port: u16,
+    pub decode: DecodePolicy,
+    pub format: MessageFormat,
+    // turns the message into fields
+    pub parser: Option<Arc<Parser>>,
+    // messages start with "#<seq> "
+    pub sequence_numbers: bool,
+    // confirm every message to the plc once written, udp with sequence numbers only
+    pub ack: bool,
+    // where the plc time of a message is read from, None when the plcs do not send it
+    pub plc_timestamp: Option<PlcTimestamp>,
+    // name of the sink the messages are written to
+    pub sink: Arc<str>,
+}
+
+impl Listener {
+    // [::] also receives ipv4 unless the listener binds ipv4 addresses itself,
+    // which would clash with it
+    fn dual_stack(&self, addr: &SocketAddr) -> bool {
+        addr.is_ipv6() && addr.ip().is_unspecified() && !self.addrs.iter().any(SocketAddr::is_ipv4)
+    }
+
+    fn addrs_display(&self) -> String {
+        let addrs: Vec<String> = self.addrs.iter().map(SocketAddr::to_string).collect();
+        addrs.join(", ")
+    }
+}
+
+// bind every socket of the listener and start receiving on them
+pub fn start(
+    listener: Arc<Listener>,
+    queue: WorkQueue,
+    sources: Arc<SourceMap>,
+    stats: Arc<Stats>,
+) -> io::Result<()> {
+    match &listener.protocol {
+        Protocol::Udp(settings) => {
+            info!(
+                "Starting UDP Listener '{}' on {} (max datagram: {} bytes, reassembly: {}, acks: {}, decode: {}, format: {}, sink: {})",
+                listener.name,
+                listener.addrs_display(),
+                settings.max_datagram_bytes,
+                if settings.reassembly_timeout.is_some() { "on" } else { "off" },
+                if listener.ack { "on" } else { "off" },
+                listener.decode,
+                listener.format,
+                listener.sink
+            );
+            for addr in &listener.addrs {
+                let socket = bind(addr, Type::DGRAM, listener.dual_stack(addr))?;
+                let socket = UdpSocket::from(socket);
+                // acks go out from the socket the message arrived on
+                let reply = match listener.ack {
+                    true => Some(Arc::new(socket.try_clone()?)),
+                    false => None,
+                };
+                pipeline::spawn_receiver(
+                    socket,
+                    Arc::clone(&listener),
+                    settings.clone(),
+                    reply,
+                    queue.clone(),
+                    Arc::clone(&sources),
+                    Arc::clone(&stats),
+                );
+            }
+        }
+        Protocol::Tcp(settings) => {
+            info!(
+                "Starting TCP Listener '{}' on {} (framing: {:?}, decode: {}, format: {}, sink: {})",
+                listener.name,
+                listener.addrs_display(),
+                settings.framing,
+                listener.decode,
+                listener.format,
+                listener.sink
+            );
+            for addr in &listener.addrs {
+                let socket = bind(addr, Type::STREAM, listener.dual_stack(addr))?;
+                socket.listen(128)?;
+                let socket = TcpListener::from(socket);
+                tcp::spawn_listener(
+                    socket,
+                    Arc::clone(&listener),
+                    settings.clone(),
+                    queue.clone(),
+                    Arc::clone(&stats),
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+// sockets are set up by hand so that ipv6-only can be chosen before binding
+fn bind(addr: &SocketAddr, socket_type: Type, dual_stack: bool) -> io::Result<Socket> {
+    let protocol = match socket_type {
+        Type::STREAM => SocketProtocol::TCP,
+        _ => SocketProtocol::UDP,
+    };
+    let socket = Socket::new(Domain::for_address(*addr), socket_type, Some(protocol))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(!dual_stack)?;
+    }
+    if socket_type == Type::STREAM {
+        // same as the standard library, allows restarting while old connections linger
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+    }
+    socket
+        .bind(&(*addr).into())
+        .map_err(|err| io::Error::new(err.kind(), format!("failed to bind {addr}: {err}")))?;
+    Ok(socket)
+}
@@ -0,0 +1,90 @@
+use log::LevelFilter;
+use log4rs::append::console::ConsoleAppender;
+use log4rs::append::rolling_file::policy::compound::CompoundPolicy;
+use log4rs::append::rolling_file::policy::compound::trigger::size::SizeTrigger;
+use log4rs::append::rolling_file::RollingFileAppender;
+use log4rs::append::Append;
+use log4rs::config::{Appender, Root};
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::Config as LogConfig;
+use log4rs::{self, Handle};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::rotation::{DatedRollingAppender, NumberedRoller, Rotation, RotationInterval};
+use crate::settings::AppConfig;
+
+// pattern used for the logger's own diagnostic messages
+pub const LOG_PATTERN: &str = "{d(%Y-%m-%d %H:%M:%S%.6f)} | {({l}):5.5} | {m}{n}";
+
+pub fn logger_setup(appconfig: &AppConfig) -> Handle {
+    let config = logger_config(LOG_PATTERN, appconfig);
+
+    log4rs::init_config(config).unwrap()
+}
+
+// diagnostics go to stdout and to their own rolling file, never to the plc log
+fn logger_config(log_pattern: &str, appconfig: &AppConfig) -> LogConfig {
+    let log_line_pattern = log_pattern;
+
+    let step_ap = rolling_appender(
+        &appconfig.app_log_path,
+        &app_archive_pattern(appconfig),
+        log_line_pattern,
+        &appconfig.rotation(),
+    )
+    .unwrap();
+
+    let stdout = ConsoleAppender::builder()
+        .encoder(Box::new(PatternEncoder::new(log_line_pattern)))
+        .build();
+
+    let appenders = vec![String::from("stdout"), String::from("step_ap")];
+
+    LogConfig::builder()
+        .appender(Appender::builder().build("stdout", Box::new(stdout)))
+        .appender(Appender::builder().build("step_ap", step_ap))
+        .build(
+            Root::builder()
+                .appenders(appenders)
+                .build(LevelFilter::Debug),
+        )
+        .unwrap()
+}
+
+// archives of the diagnostic log are kept next to the plc archives
+pub fn app_archive_pattern(appconfig: &AppConfig) -> PathBuf {
+    appconfig.archive_dir.join("plclogger_{}.gz")
+}
+
+// rolling file appender using the rotation settings, archived as gzip files when the
+// archive pattern ends with .gz
+//
+// size only rotation numbers the archives in the {} of the pattern, time based
+// rotation puts the date of the archived period there instead
+pub fn rolling_appender(
+    log_path: &Path,
+    roller_pattern: &Path,
+    log_line_pattern: &str,
+    rotation: &Rotation,
+) -> Result<Box<dyn Append>, Box<dyn Error>> {
+    let encoder = Box::new(PatternEncoder::new(log_line_pattern));
+
+    if rotation.interval != RotationInterval::None {
+        let appender = DatedRollingAppender::new(log_path, roller_pattern, encoder, rotation)?;
+        return Ok(Box::new(appender));
+    }
+
+    let trigger_size = rotation.max_size_bytes();
+    let trigger = Box::new(SizeTrigger::new(trigger_size));
+
+    let roller = Box::new(NumberedRoller::new(roller_pattern, rotation.history_to_keep));
+
+    let compound_policy = Box::new(CompoundPolicy::new(trigger, roller));
+
+    let appender = RollingFileAppender::builder()
+        .encoder(encoder)
+        .build(log_path, compound_policy)?;
+
+    Ok(Box::new(appender))
+}
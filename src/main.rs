@@ -1,214 +1,272 @@
-use config::{Config, ConfigError};
-use core::time;
-use std::net::UdpSocket;
-use std::str::from_utf8;
-use std::sync::Mutex;
-use std::sync::mpsc::channel;
-use std::thread;
-use log::LevelFilter;
-use log::{error, info};
-use log4rs::append::console::ConsoleAppender;
-use log4rs::append::rolling_file::policy::compound::CompoundPolicy;
-use log4rs::append::rolling_file::policy::compound::{
-    roll::fixed_window::FixedWindowRoller, trigger::size::SizeTrigger,
-};
-use log4rs::append::rolling_file::RollingFileAppender;
-use log4rs::config::{Appender, Root};
-use log4rs::encode::pattern::PatternEncoder;
-use log4rs::Config as LogConfig;
-use log4rs::{self, Handle};
-use std::{process, thread::sleep, thread::spawn};
-
-struct AppConfig {
-    listening_port: u16,
-    log_max_size_mb: u128,
-    log_history_to_keep: u32,
-}
+mod cli;
+mod decode;
+mod http_api;
+mod layouts;
+mod listeners;
+mod logging;
+mod metrics;
+mod parsers;
+mod pipeline;
+mod plc_time;
+mod plc_writer;
+mod query;
+mod reassembly;
+mod record;
+mod retention;
+mod rotation;
+mod sequence;
+mod settings;
+mod severity;
+mod sinks;
+mod sources;
+mod sqlite_sink;
+mod stats;
+mod stream;
+mod syslog;
+mod tcp;
+
+use log::{error, info, warn};
+use std::env;
+use std::process;
+use std::sync::mpsc::sync_channel;
+use std::sync::Arc;
+use std::time::Instant;
+
+use logging::logger_setup;
+use plc_writer::PlcWriter;
+use sequence::{LoggedSequences, SequenceEvent};
+use settings::app_config;
+use stats::Stats;
+use stream::{StreamHub, TailArgs};
+
+fn main() {
+    // constants
+    const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
+    // records written before their acks are sent at the latest
+    const MAX_PENDING_ACKS: usize = 1000;
 
-fn app_config() -> Result<AppConfig, ConfigError> {
-    // read config.toml file
-    let cfg;
-    match Config::builder()
-        .add_source(config::File::with_name("config.toml"))
-        .build()
-    {
-        Ok(cfg_ok) => cfg = cfg_ok,
-        Err(err) => return Err(err),
-    };
-
-    // check for keys in config.toml file
-    let listening_port = match cfg.get_int("listening_port") {
-        Ok(val_ok) => val_ok,
-        Err(err) => return Err(err),
-    };
-
-    let log_max_size_mb = match cfg.get_int("log_max_size_mb") {
-        Ok(val_ok) => val_ok,
-        Err(err) => return Err(err),
-    };
-
-    let log_history_to_keep = match cfg.get_int("log_history_to_keep") {
-        Ok(val_ok) => val_ok,
-        Err(err) => return Err(err),
-    };
-
-    // check if values from config.toml file are in valid range
-    if (listening_port < 0) || (listening_port > 65535) {
-        return Err(ConfigError::Message(String::from(
-            "listening port must be between 0 - 65535",
-        )));
+    // read command line
+    let args = cli::parse_args(env::args())
+        .unwrap_or_else(|err| {
+            println!("{err}");
+            println!("{}", cli::USAGE);
+            process::exit(1);
+        });
+    if args.help {
+        println!("{}", cli::USAGE);
+        return;
     }
-    let listening_port: u16 = listening_port.try_into().unwrap();
 
-    if (log_max_size_mb < 1) || (log_max_size_mb > 100) {
-        return Err(ConfigError::Message(String::from(
-            "max log size must be between 1 - 100 (mb)",
-        )));
+    // tail only needs the config file to find the streaming endpoint
+    if let cli::Command::Tail(TailArgs { filter, connect: Some(addr) }) = &args.command {
+        exit_on_error(stream::tail(addr, filter));
     }
-    let log_max_size_mb: u128 = log_max_size_mb.try_into().unwrap();
 
-    if (log_history_to_keep < 0) || (log_history_to_keep > 1000) {
-        return Err(ConfigError::Message(String::from(
-            "log history must be between 0 - 1000",
-        )));
+    // read config file
+    let app_config = app_config(&args.config_path)
+        .unwrap_or_else(|err| {
+            println!("{err}");
+            process::exit(1);
+        });
+
+    // queries and tail only read what the logger wrote, they run without setting it up
+    if let cli::Command::Query(query) = &args.command {
+        exit_on_error(query::run(&app_config, query).map(|_| ()));
+    }
+    if let cli::Command::Tail(tail) = &args.command {
+        let addr = match &app_config.stream {
+            // a logger listening on every address is reached on this host
+            Some(stream) if stream.addr.ip().is_unspecified() => {
+                format!("localhost:{}", stream.addr.port())
+            }
+            Some(stream) => stream.addr.to_string(),
+            None => {
+                println!("Streaming is turned off, set stream_port in the config file");
+                process::exit(1);
+            }
+        };
+        exit_on_error(stream::tail(&addr, &tail.filter));
     }
-    let log_history_to_keep: u32 = log_history_to_keep.try_into().unwrap();
 
-    Ok(AppConfig {
-        listening_port: listening_port,
-        log_max_size_mb: log_max_size_mb,
-        log_history_to_keep: log_history_to_keep,
-    })
-}
+    // shared with the http api
+    let app_config = Arc::new(app_config);
 
-fn logger_setup(appconfig: &AppConfig, log_pattern: &str) -> Handle {
-    let config = logger_config(log_pattern, appconfig);
+    // setup logger
+    let _log_handle = logger_setup(&app_config);
 
-    let handle = log4rs::init_config(config).unwrap();
-    handle
-}
+    // start application
+    info!("Rusty PLC Logger v{APP_VERSION} - Starting Up...");
+    info!("Using config file: {}", args.config_path.display());
 
-fn logger_config(log_pattern: &str, appconfig: &AppConfig) -> LogConfig {
-    let log_line_pattern = log_pattern;
+    // setup the writer used for plc messages, kept apart from the diagnostic log
+    let mut plc_writer = PlcWriter::new(&app_config)
+        .unwrap_or_else(|err| {
+            error!("{err}");
+            process::exit(1);
+        });
 
-    let trigger_size = byte_unit::n_mb_bytes!(appconfig.log_max_size_mb) as u64;
-    let trigger = Box::new(SizeTrigger::new(trigger_size));
+    info!("Loaded {} named sources", app_config.sources.len());
 
-    let roller_pattern = "history/plclog_{}.gz";
-    let roller_count = appconfig.log_history_to_keep;
-    let roller_base = 1;
-    let roller = Box::new(
-        FixedWindowRoller::builder()
-            .base(roller_base)
-            .build(roller_pattern, roller_count)
-            .unwrap(),
-    );
+    // prune archives and database rows beyond the retention limits
+    let mut archive_patterns = plc_writer::archive_patterns(&app_config);
+    archive_patterns.push(logging::app_archive_pattern(&app_config));
+    let databases = plc_writer::databases(&app_config);
+    retention::spawn_pruner(app_config.retention.clone(), archive_patterns, databases);
 
-    let compound_policy = Box::new(CompoundPolicy::new(trigger, roller));
+    // counters shared across the pipeline threads
+    let stats = Arc::new(Stats::default());
+    stats::spawn_reporter(Arc::clone(&stats), app_config.stats_interval_secs);
+    if app_config.listeners.iter().any(|listener| listener.sequence_numbers) {
+        sequence::spawn_reporter(
+            Arc::clone(&stats),
+            Arc::clone(&app_config.sources),
+            app_config.stats_interval_secs,
+        );
+    }
 
-    let step_ap = RollingFileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(log_line_pattern)))
-        .build("plc.log", compound_policy)
-        .unwrap();
+    // status and records for dashboards
+    if let Some(http) = &app_config.http {
+        http_api::spawn_server(http.clone(), Arc::clone(&app_config), Arc::clone(&stats))
+            .unwrap_or_else(|err| {
+                error!("Failed to serve the http api on {}: {err}", http.addr);
+                process::exit(1);
+            });
+    }
 
-    let stdout = ConsoleAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(log_line_pattern)))
-        .build();
+    // records are streamed to clients from the writer thread, never waiting for them
+    let hub = Arc::new(StreamHub::default());
+    if let Some(stream) = &app_config.stream {
+        stream::spawn_server(stream.clone(), Arc::clone(&hub)).unwrap_or_else(|err| {
+            error!("Failed to stream on {}: {err}", stream.addr);
+            process::exit(1);
+        });
+    }
 
-    let appenders = vec![String::from("stdout"), String::from("step_ap")];
+    // setup the bounded channels used to communicate across threads:
+    // receiver -> decode workers -> writer
+    let (work_queue, worker_queues) =
+        pipeline::work_queue(app_config.decode_workers, app_config.queue_depth);
+    let (record_tx, record_rx) = sync_channel(app_config.queue_depth);
 
-    let config = LogConfig::builder()
-        .appender(Appender::builder().build("stdout", Box::new(stdout)))
-        .appender(Appender::builder().build("step_ap", Box::new(step_ap)))
-        .build(
-            Root::builder()
-                .appenders(appenders)
-                .build(LevelFilter::Debug),
+    // udp and tcp listeners, all feeding the same queue
+    for listener in &app_config.listeners {
+        listeners::start(
+            Arc::clone(listener),
+            work_queue.clone(),
+            Arc::clone(&app_config.sources),
+            Arc::clone(&stats),
         )
-        .unwrap();
-    config
-}
-
-fn main() {
-    // constants
-    const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
-    const LOG_PATTERN: &str = "{d(%Y-%m-%d %H:%M:%S%.6f)} | {({l}):5.5} | {m}{n}";
-    const LOG_PATTERN_PLC: &str = "{m}{n}";
-    
-    // read config.toml file
-    let app_config = app_config()
         .unwrap_or_else(|err| {
-            println!("{err}");
-            process::exit(1);
-        });
-    
-    // setup logger
-    let log_handle = logger_setup(&app_config, LOG_PATTERN);
+                error!("{err}");
+                error!("Check if another instance of the logger is running, or if another application is using port {}", listener.port);
+                process::exit(1);
+            });
+    }
+    drop(work_queue);
 
-    // start application
-    info!("Rusty PLC Logger v{APP_VERSION} - Starting Up...");
+    pipeline::spawn_workers(
+        worker_queues,
+        record_tx,
+        Arc::clone(&app_config.sources),
+        Arc::clone(&app_config.severity_rules),
+        Arc::clone(&stats),
+    );
+    info!(
+        "Started {} decode workers, queue depth: {}",
+        app_config.decode_workers, app_config.queue_depth
+    );
 
-    // setup channel to be used to communicate across threads
-    let (tx, rx) = channel();
-
-    // udp listener
-    let listening_port = app_config.listening_port;
-
-    // spawn a thread to handle the UDP socket
-    let address_with_port = String::from("0.0.0.0:") + &listening_port.to_string();
-
-    thread::spawn(move || {
-        let socket = UdpSocket::bind(address_with_port)
-                           .unwrap_or_else(|err| {
-                                error!("{err}");
-                                error!("Check if another instance of the logger is running, or if another application is using port {}", &listening_port);
-                                process::exit(1);
-                           });
-    info!("Starting UDP Listener on port: {}", &listening_port);
-
-    loop {
-        let tx_thread = tx.clone();
-        let mut buf = [0u8; 1500];
-        info!("Cloning socket...");
-        let sock = socket.try_clone()
-            .unwrap_or_else(|err| {
-                    error!("{err}");
-                    process::exit(1);
-                });
-
-        info!("Waiting for packet...");
-        match sock.recv_from(&mut buf) {
-            Ok((amt, src)) => {
-                thread::spawn(move || {
-                    info!("Handling connection from {}", src);
-                    let buf = &mut buf[..amt];
-                    let string_data = from_utf8(buf).unwrap().to_string();
-                    tx_thread.send(string_data)
-                        .unwrap_or_else(|err| {
-                            error!("{err}");
-                        });
-                
-                });
-            },
-            Err(e) => {
-                error!("{}", e);
+    // plc messages are written from this thread only. acks wait until everything
+    // queued up has been written and synced to disk, so one sync covers many records
+    let mut acks = Vec::new();
+    let mut logged = LoggedSequences::default();
+    while let Ok(r) = record_rx.recv() {
+        let mut next = Some(r);
+        while let Some(r) = next {
+            Stats::dequeued(&stats.records_queued);
+            // with acks a message that was logged already is a retransmit after a lost
+            // ack, it is acked again but not logged twice
+            let retransmit = match &r.ack {
+                Some(ack) => {
+                    if let Some(SequenceEvent::Reset { .. }) = r.sequence_event {
+                        logged.reset(&r.listener, r.src.ip());
+                    }
+                    logged.contains(&r.listener, r.src.ip(), ack.seq)
+                }
+                None => false,
+            };
+            if retransmit {
+                Stats::incr(&stats.duplicates_suppressed);
+                acks.extend(r.ack);
+            } else {
+                let started = Instant::now();
+                let written = plc_writer.write(&r);
+                stats.write_seconds.observe(started.elapsed());
+                match written {
+                    Ok(written) => {
+                        match written {
+                            true => Stats::incr(&stats.written),
+                            false => Stats::incr(&stats.filtered),
+                        }
+                        stats.sources.count(&r, written);
+                        if let Some(ack) = &r.ack {
+                            logged.insert(&r.listener, r.src.ip(), ack.seq);
+                            acks.push(ack.clone());
+                        }
+                    }
+                    // not acked, the plc sends the message again
+                    Err(err) => {
+                        Stats::failed(&stats.write_failed, &stats.write_failed_at);
+                        error!("Failed to write record from {}: {err}", r.src);
+                    }
+                }
+                // clients set their own level, records below min_level are streamed as well
+                hub.publish(&r, &stats);
             }
+            next = match acks.len() < MAX_PENDING_ACKS {
+                true => record_rx.try_recv().ok(),
+                false => None,
+            };
         }
-    }
-    });
 
-    for r in rx {
-        let log_handle = logger_setup(&app_config, LOG_PATTERN_PLC);
-        info!("{r}");
+        let started = Instant::now();
+        if acks.is_empty() {
+            // ends the batch of the sqlite sinks, acks wait for the files as well
+            let flushed = plc_writer.flush();
+            stats.sync_seconds.observe(started.elapsed());
+            if let Err(err) = flushed {
+                Stats::failed(&stats.sync_failed, &stats.sync_failed_at);
+                error!("Failed to write {err}");
+            }
+            continue;
+        }
+        let synced = plc_writer.sync();
+        stats.sync_seconds.observe(started.elapsed());
+        if let Err(err) = synced {
+            // without durable lines the plcs have to retransmit
+            Stats::failed(&stats.sync_failed, &stats.sync_failed_at);
+            error!("Failed to sync plc logs, acks not sent: {err}");
+            acks.clear();
+            logged.forget_unsynced();
+            continue;
+        }
+        logged.synced();
+        for ack in acks.drain(..) {
+            match ack.send() {
+                Ok(()) => Stats::incr(&stats.acked),
+                Err(err) => warn!("Failed to ack {} to {}: {err}", ack.seq, ack.dst),
+            }
+        }
     }
+}
 
-    /* for _ in 0..5 {
-        sleep(time::Duration::from_millis(1000));
-        error!("first log error");
-        info!("first log info");
-        log_handle.set_config(logger_config(LOG_PATTERN_PLC, &app_config));
-    } */
-
-    log_handle.set_config(logger_config(LOG_PATTERN, &app_config));
-    info!("last log");
+// end a command that runs instead of the logger
+fn exit_on_error(result: Result<(), String>) -> ! {
+    match result {
+        Ok(()) => process::exit(0),
+        Err(err) => {
+            println!("{err}");
+            process::exit(1);
+        }
+    }
 }
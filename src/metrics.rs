@@ -0,0 +1,262 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::rotation::ROTATIONS;
+use crate::settings::AppConfig;
+use crate::stats::{Stats, Traffic};
+
+// upper bounds of the latency buckets, in seconds
+const BUCKETS: [f64; 12] = [
+    0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 1.0,
+];
+
+// latency distribution in the buckets above, the last one counting anything slower
+#[derive(Debug, Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    // cumulative bucket counts in the order of the bounds, then the total
+    fn counts(&self) -> Vec<u64> {
+        let mut total = 0;
+        self.buckets
+            .iter()
+            .map(|bucket| {
+                total += bucket.load(Ordering::Relaxed);
+                total
+            })
+            .collect()
+    }
+}
+
+// size of the archives and databases of a sink, in bytes
+pub struct SinkUsage {
+    pub sink: String,
+    pub archives: u64,
+    pub archive_bytes: u64,
+    pub database_bytes: u64,
+}
+
+// everything in the prometheus text format
+pub fn render(appconfig: &AppConfig, stats: &Stats, usage: &[SinkUsage]) -> String {
+    let mut out = String::new();
+
+    for (name, label, value) in stats.counters() {
+        let name = format!("plclogger_{name}_total");
+        family(
+            &mut out,
+            &name,
+            "counter",
+            label,
+            [(String::new(), value.to_string())],
+        );
+    }
+
+    let by_listener = stats.traffic.by_listener();
+    let listener_samples = |value: fn(&Traffic) -> u64| {
+        by_listener.iter().map(move |(listener, traffic)| {
+            (
+                labels(&[("listener", listener)]),
+                value(traffic).to_string(),
+            )
+        })
+    };
+    family(
+        &mut out,
+        "plclogger_listener_messages_total",
+        "counter",
+        "messages received per listener",
+        listener_samples(|traffic| traffic.messages),
+    );
+    family(
+        &mut out,
+        "plclogger_listener_bytes_total",
+        "counter",
+        "bytes received per listener",
+        listener_samples(|traffic| traffic.bytes),
+    );
+
+    let by_source = stats.traffic.by_source();
+    let source_samples = |value: fn(&Traffic) -> u64| {
+        by_source.iter().map(move |((listener, source), traffic)| {
+            let labels = labels(&[("listener", listener), ("source", source)]);
+            (labels, value(traffic).to_string())
+        })
+    };
+    family(
+        &mut out,
+        "plclogger_source_messages_total",
+        "counter",
+        "messages per listener and source",
+        source_samples(|traffic| traffic.messages),
+    );
+    family(
+        &mut out,
+        "plclogger_source_bytes_total",
+        "counter",
+        "bytes per listener and source",
+        source_samples(|traffic| traffic.bytes),
+    );
+    // the newest message of a source over all of its listeners
+    let mut last_seen: Vec<(&str, f64)> = Vec::new();
+    for ((_, source), traffic) in &by_source {
+        let seen = match traffic.last_seen {
+            Some(seen) => seen.timestamp_millis() as f64 / 1000.0,
+            None => continue,
+        };
+        match last_seen.iter_mut().find(|(name, _)| name == source) {
+            Some((_, last)) => *last = last.max(seen),
+            None => last_seen.push((source, seen)),
+        }
+    }
+    family(
+        &mut out,
+        "plclogger_source_last_seen_timestamp_seconds",
+        "gauge",
+        "time the last message of a source was received at",
+        last_seen
+            .iter()
+            .map(|(source, seen)| (labels(&[("source", source)]), format!("{seen:.3}"))),
+    );
+
+    let queues = [
+        ("datagrams", &stats.datagrams_queued),
+        ("records", &stats.records_queued),
+    ];
+    family(
+        &mut out,
+        "plclogger_queue_depth",
+        "gauge",
+        "messages waiting in the queues between the threads",
+        queues.iter().map(|(queue, gauge)| {
+            let depth = gauge.load(Ordering::Relaxed).max(0);
+            (labels(&[("queue", queue)]), depth.to_string())
+        }),
+    );
+    // every decode worker has a datagram queue of its own
+    let capacities = [
+        ("datagrams", appconfig.decode_workers * appconfig.queue_depth),
+        ("records", appconfig.queue_depth),
+    ];
+    family(
+        &mut out,
+        "plclogger_queue_capacity",
+        "gauge",
+        "messages each queue holds at most",
+        capacities.iter().map(|(queue, capacity)| {
+            (labels(&[("queue", queue)]), capacity.to_string())
+        }),
+    );
+
+    let mut latency = Vec::new();
+    for (op, histogram) in [
+        ("write", &stats.write_seconds),
+        ("sync", &stats.sync_seconds),
+    ] {
+        let counts = histogram.counts();
+        let total = counts.last().copied().unwrap_or(0);
+        let bounds = BUCKETS
+            .iter()
+            .map(|bound| bound.to_string())
+            .chain([String::from("+Inf")]);
+        for (bound, count) in bounds.zip(&counts) {
+            let labels = labels(&[("op", op), ("le", &bound)]);
+            latency.push((format!("_bucket{labels}"), count.to_string()));
+        }
+        let sum = histogram.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        latency.push((format!("_sum{}", labels(&[("op", op)])), sum.to_string()));
+        latency.push((
+            format!("_count{}", labels(&[("op", op)])),
+            total.to_string(),
+        ));
+    }
+    family(
+        &mut out,
+        "plclogger_write_duration_seconds",
+        "histogram",
+        "time taken to write a record and to sync a batch of records to disk",
+        latency,
+    );
+
+    family(
+        &mut out,
+        "plclogger_rotations_total",
+        "counter",
+        "log files rolled over, plc logs and the diagnostic log",
+        [(String::new(), ROTATIONS.load(Ordering::Relaxed).to_string())],
+    );
+
+    let usage_samples = |value: fn(&SinkUsage) -> u64| {
+        usage
+            .iter()
+            .map(move |sink| (labels(&[("sink", &sink.sink)]), value(sink).to_string()))
+    };
+    family(
+        &mut out,
+        "plclogger_archive_files",
+        "gauge",
+        "archive files per sink",
+        usage_samples(|sink| sink.archives),
+    );
+    family(
+        &mut out,
+        "plclogger_archive_bytes",
+        "gauge",
+        "disk space taken by the archives of a sink",
+        usage_samples(|sink| sink.archive_bytes),
+    );
+    family(
+        &mut out,
+        "plclogger_database_bytes",
+        "gauge",
+        "disk space taken by the database of a sqlite sink",
+        usage_samples(|sink| sink.database_bytes),
+    );
+
+    out
+}
+
+// one metric with its samples, each sample being the part after the name (labels, or a
+// histogram suffix and labels) and the value
+fn family(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: impl IntoIterator<Item = (String, String)>,
+) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{name}{labels} {value}");
+    }
+}
+
+// {a="x",b="y"} with the values escaped
+fn labels(pairs: &[(&str, &str)]) -> String {
+    let pairs: Vec<String> = pairs
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{name}=\"{value}\"")
+        })
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
@@ -0,0 +1,557 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+// a [[parsers]] entry from config.toml
+#[derive(Debug, Deserialize)]
+pub struct ParserConfig {
+    pub name: String,
+    // kv, json, csv or regex
+    #[serde(rename = "type")]
+    pub parser_type: String,
+    // kv: between pairs (default ";") and between key and value (default "=")
+    pub separator: Option<String>,
+    pub assign: Option<String>,
+    // csv: column names in the order they are sent, delimiter defaults to ","
+    pub columns: Option<Vec<String>>,
+    pub delimiter: Option<String>,
+    // regex: named captures become the fields
+    pub pattern: Option<String>,
+    // field types, "string", "int", "float" or "bool". fields not listed are
+    // guessed from their value, json keeps the types of the payload
+    pub types: Option<HashMap<String, String>>,
+    // field whose value replaces the payload as the logged message
+    pub message_field: Option<String>,
+}
+
+// a parsed value, as written to the structured sinks
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum FieldValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FieldValue::Bool(value) => write!(f, "{value}"),
+            FieldValue::Int(value) => write!(f, "{value}"),
+            FieldValue::Float(value) => write!(f, "{value}"),
+            FieldValue::Str(value) => f.write_str(value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldType {
+    Str,
+    Int,
+    Float,
+    Bool,
+}
+
+#[derive(Debug)]
+enum Format {
+    KeyValue { separator: String, assign: String },
+    Json,
+    Csv { delimiter: char, columns: Vec<String> },
+    Regex(Regex),
+}
+
+// turns payloads into named, typed fields
+#[derive(Debug)]
+pub struct Parser {
+    pub name: String,
+    format: Format,
+    types: HashMap<String, FieldType>,
+    pub message_field: Option<String>,
+}
+
+impl Parser {
+    pub fn new(config: &ParserConfig) -> Result<Parser, String> {
+        let name = &config.name;
+        let format = match config.parser_type.as_str() {
+            "kv" => {
+                let separator = config.separator.clone().unwrap_or_else(|| String::from(";"));
+                let assign = config.assign.clone().unwrap_or_else(|| String::from("="));
+                if separator.is_empty() || assign.is_empty() {
+                    return Err(format!("parser '{name}': separator and assign must not be empty"));
+                }
+                Format::KeyValue { separator, assign }
+            }
+            "json" => Format::Json,
+            "csv" => {
+                let columns = match &config.columns {
+                    Some(columns) if !columns.is_empty() => columns.clone(),
+                    _ => return Err(format!("parser '{name}': csv parsers need columns")),
+                };
+                let delimiter = config.delimiter.as_deref().unwrap_or(",");
+                let mut chars = delimiter.chars();
+                let delimiter = match (chars.next(), chars.next()) {
+                    (Some(delimiter), None) => delimiter,
+                    _ => return Err(format!("parser '{name}': delimiter must be a single character")),
+                };
+                Format::Csv { delimiter, columns }
+            }
+            "regex" => {
+                let pattern = match &config.pattern {
+                    Some(pattern) => pattern,
+                    None => return Err(format!("parser '{name}': regex parsers need a pattern")),
+                };
+                let regex = Regex::new(pattern).map_err(|err| format!("parser '{name}': {err}"))?;
+                if regex.capture_names().flatten().next().is_none() {
+                    return Err(format!("parser '{name}': pattern has no named captures"));
+                }
+                Format::Regex(regex)
+            }
+            parser_type => {
+                return Err(format!(
+                    "parser '{name}': unknown type '{parser_type}', expected one of: kv, json, csv, regex"
+                ))
+            }
+        };
+
+        let mut types = HashMap::new();
+        for (field, field_type) in config.types.iter().flatten() {
+            let field_type = match field_type.as_str() {
+                "string" => FieldType::Str,
+                "int" => FieldType::Int,
+                "float" => FieldType::Float,
+                "bool" => FieldType::Bool,
+                _ => {
+                    return Err(format!(
+                        "parser '{name}': unknown type '{field_type}' for field '{field}', expected one of: string, int, float, bool"
+                    ))
+                }
+            };
+            types.insert(field.clone(), field_type);
+        }
+
+        Ok(Parser {
+            name: name.clone(),
+            format,
+            types,
+            message_field: config.message_field.clone(),
+        })
+    }
+
+    pub fn parse(&self, payload: &str) -> Result<Vec<(String, FieldValue)>, String> {
+        let fields = match &self.format {
+            Format::KeyValue { separator, assign } => payload
+                .split(separator.as_str())
+                .filter(|pair| !pair.trim().is_empty())
+                .map(|pair| match pair.split_once(assign.as_str()) {
+                    Some((key, value)) => Ok((key.trim().to_string(), value.trim().to_string())),
+                    None => Err(format!("'{}' is not a key{assign}value pair", pair.trim())),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            Format::Json => return self.parse_json(payload),
+            Format::Csv { delimiter, columns } => {
+                let values = split_csv(payload, *delimiter)?;
+                if values.len() != columns.len() {
+                    return Err(format!(
+                        "expected {} columns, found {}",
+                        columns.len(),
+                        values.len()
+                    ));
+                }
+                columns.iter().cloned().zip(values).collect()
+            }
+            Format::Regex(regex) => {
+                let captures = match regex.captures(payload) {
+                    Some(captures) => captures,
+                    None => return Err(String::from("pattern does not match")),
+                };
+                regex
+                    .capture_names()
+                    .flatten()
+                    .filter_map(|name| {
+                        let value = captures.name(name)?;
+                        Some((name.to_string(), value.as_str().to_string()))
+                    })
+                    .collect()
+            }
+        };
+
+        fields
+            .into_iter()
+            .map(|(key, value)| {
+                let value = self.typed(&key, value)?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    // objects are flattened with dotted keys, arrays are kept as json text
+    fn parse_json(&self, payload: &str) -> Result<Vec<(String, FieldValue)>, String> {
+        let value: serde_json::Value = serde_json::from_str(payload).map_err(|err| err.to_string())?;
+        let object = match value {
+            serde_json::Value::Object(object) => object,
+            _ => return Err(String::from("payload is not a json object")),
+        };
+
+        let mut fields = Vec::new();
+        let mut stack: Vec<(String, serde_json::Value)> = object.into_iter().rev().collect();
+        while let Some((key, value)) = stack.pop() {
+            let value = match value {
+                serde_json::Value::Object(object) => {
+                    for (child, value) in object.into_iter().rev() {
+                        stack.push((format!("{key}.{child}"), value));
+                    }
+                    continue;
+                }
+                serde_json::Value::Null => continue,
+                serde_json::Value::Bool(value) => FieldValue::Bool(value),
+                serde_json::Value::Number(number) => match number.as_i64() {
+                    Some(value) => FieldValue::Int(value),
+                    None => FieldValue::Float(number.as_f64().unwrap_or(f64::NAN)),
+                },
+                serde_json::Value::String(value) => FieldValue::Str(value),
+                array => FieldValue::Str(array.to_string()),
+            };
+            // configured types still apply, plcs often send numbers as strings
+            let value = match self.types.get(&key) {
+                Some(_) => self.typed(&key, value.to_string())?,
+                None => value,
+            };
+            fields.push((key, value));
+        }
+        Ok(fields)
+    }
+
+    fn typed(&self, key: &str, value: String) -> Result<FieldValue, String> {
+        let invalid = |field_type: &str| format!("field '{key}': '{value}' is not a valid {field_type}");
+        match self.types.get(key) {
+            Some(FieldType::Str) => Ok(FieldValue::Str(value)),
+            Some(FieldType::Int) => value.parse().map(FieldValue::Int).map_err(|_| invalid("int")),
+            Some(FieldType::Float) => value.parse().map(FieldValue::Float).map_err(|_| invalid("float")),
+            Some(FieldType::Bool) => parse_bool(&value).map(FieldValue::Bool).ok_or_else(|| invalid("bool")),
+            None => Ok(guess(value)),
+        }
+    }
+}
+
+// fields as key=value pairs, as logged for binary layouts and {X(fields)}
+pub fn summary(fields: &[(String, FieldValue)]) -> String {
+    let pairs: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect();
+    pairs.join(" ")
+}
+
+// values of untyped fields, numbers and true/false are taken as such
+fn guess(value: String) -> FieldValue {
+    if let Ok(int) = value.parse() {
+        return FieldValue::Int(int);
+    }
+    if let Ok(float) = value.parse::<f64>() {
+        if float.is_finite() {
+            return FieldValue::Float(float);
+        }
+    }
+    match value.as_str() {
+        "true" | "TRUE" => FieldValue::Bool(true),
+        "false" | "FALSE" => FieldValue::Bool(false),
+        _ => FieldValue::Str(value),
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "1" | "on" | "yes" => Some(true),
+        "false" | "0" | "off" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+// one csv line, values may be quoted with "" for a quote inside them
+fn split_csv(line: &str, delimiter: char) -> Result<Vec<String>, String> {
+    let mut values = Vec::new();
+    let mut value = String::new();
+    let mut chars = line.trim_end_matches(['\r', '\n']).chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                value.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if value.trim().is_empty() => {
+                value.clear();
+                quoted = true;
+            }
+            c if c == delimiter && !quoted => values.push(std::mem::take(&mut value)),
+            c => value.push(c),
+        }
+    }
+    if quoted {
+        return Err(String::from("unterminated quote"));
+    }
+    values.push(value);
+    Ok(values.into_iter().map(|value| value.trim().to_string()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(parser_type: &str) -> ParserConfig {
+        ParserConfig {
+            name: String::from("test"),
+            parser_type: parser_type.to_string(),
+            separator: None,
+            assign: None,
+            columns: None,
+            delimiter: None,
+            pattern: None,
+            types: None,
+            message_field: None,
+        }
+    }
+
+    fn types(types: &[(&str, &str)]) -> Option<HashMap<String, String>> {
+        Some(
+            types
+                .iter()
+                .map(|(field, field_type)| (field.to_string(), field_type.to_string()))
+                .collect(),
+        )
+    }
+
+    fn csv(columns: &[&str]) -> ParserConfig {
+        let mut config = config("csv");
+        config.columns = Some(columns.iter().map(|column| column.to_string()).collect());
+        config
+    }
+
+    fn parse(config: ParserConfig, payload: &str) -> Result<Vec<(String, FieldValue)>, String> {
+        Parser::new(&config).unwrap().parse(payload)
+    }
+
+    fn field(key: &str, value: FieldValue) -> (String, FieldValue) {
+        (key.to_string(), value)
+    }
+
+    fn text(value: &str) -> FieldValue {
+        FieldValue::Str(value.to_string())
+    }
+
+    #[test]
+    fn key_value_pairs() {
+        let fields = parse(config("kv"), "motor=on; speed=1200;temp=21.5 ; ok=true;;").unwrap();
+        assert_eq!(
+            fields,
+            [
+                field("motor", text("on")),
+                field("speed", FieldValue::Int(1200)),
+                field("temp", FieldValue::Float(21.5)),
+                field("ok", FieldValue::Bool(true)),
+            ]
+        );
+        let mut custom = config("kv");
+        custom.separator = Some(String::from(","));
+        custom.assign = Some(String::from(":"));
+        let fields = parse(custom, "motor:on,url:http://plc").unwrap();
+        assert_eq!(
+            fields,
+            [field("motor", text("on")), field("url", text("http://plc"))]
+        );
+        assert_eq!(
+            parse(config("kv"), "motor=on;stopped"),
+            Err(String::from("'stopped' is not a key=value pair"))
+        );
+    }
+
+    #[test]
+    fn csv_quoting() {
+        let fields = parse(
+            csv(&["text", "quote", "count"]),
+            " \"belt 1, left\" ,\"say \"\"stop\"\"\",3\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            fields,
+            [
+                field("text", text("belt 1, left")),
+                field("quote", text("say \"stop\"")),
+                field("count", FieldValue::Int(3)),
+            ]
+        );
+        // quotes inside a value are kept as they are
+        let fields = parse(csv(&["a", "b"]), "5\" pipe,").unwrap();
+        assert_eq!(fields, [field("a", text("5\" pipe")), field("b", text(""))]);
+        let mut semicolon = csv(&["a", "b"]);
+        semicolon.delimiter = Some(String::from(";"));
+        let fields = parse(semicolon, "1,5;2").unwrap();
+        assert_eq!(
+            fields,
+            [field("a", text("1,5")), field("b", FieldValue::Int(2))]
+        );
+    }
+
+    #[test]
+    fn csv_failures() {
+        assert_eq!(
+            parse(csv(&["a", "b", "c"]), "1,2"),
+            Err(String::from("expected 3 columns, found 2"))
+        );
+        assert_eq!(
+            parse(csv(&["a", "b"]), "1,\"2"),
+            Err(String::from("unterminated quote"))
+        );
+    }
+
+    #[test]
+    fn json_flattened() {
+        let payload = r#"{"a": 1, "b": {"c": "x", "d": {"e": true}}, "f": [1, {"g": 2}], "h": null, "i": 1.5}"#;
+        assert_eq!(
+            parse(config("json"), payload).unwrap(),
+            [
+                field("a", FieldValue::Int(1)),
+                field("b.c", text("x")),
+                field("b.d.e", FieldValue::Bool(true)),
+                field("f", text("[1,{\"g\":2}]")),
+                field("i", FieldValue::Float(1.5)),
+            ]
+        );
+        // configured types apply to json values as well
+        let mut typed = config("json");
+        typed.types = types(&[("speed", "int"), ("id", "string")]);
+        assert_eq!(
+            parse(typed, r#"{"speed": "1200", "id": 7}"#).unwrap(),
+            [
+                field("id", text("7")),
+                field("speed", FieldValue::Int(1200))
+            ]
+        );
+    }
+
+    #[test]
+    fn json_failures() {
+        assert_eq!(
+            parse(config("json"), "[1, 2]"),
+            Err(String::from("payload is not a json object"))
+        );
+        assert!(parse(config("json"), "{\"a\": ").is_err());
+        let mut typed = config("json");
+        typed.types = types(&[("speed", "int")]);
+        assert_eq!(
+            parse(typed, r#"{"speed": "fast"}"#),
+            Err(String::from("field 'speed': 'fast' is not a valid int"))
+        );
+    }
+
+    #[test]
+    fn regex_captures() {
+        let mut regex = config("regex");
+        regex.pattern = Some(String::from(
+            r"^(?P<code>\w+) (?P<temp>\d+)(?: (?P<unit>[CF]))?",
+        ));
+        let parser = Parser::new(&regex).unwrap();
+        // captures that did not take part are left out
+        assert_eq!(
+            parser.parse("E12 85 trailing").unwrap(),
+            [
+                field("code", text("E12")),
+                field("temp", FieldValue::Int(85))
+            ]
+        );
+        assert_eq!(
+            parser.parse("E12 85 C").unwrap(),
+            [
+                field("code", text("E12")),
+                field("temp", FieldValue::Int(85)),
+                field("unit", text("C")),
+            ]
+        );
+        assert_eq!(
+            parser.parse("-"),
+            Err(String::from("pattern does not match"))
+        );
+    }
+
+    #[test]
+    fn field_types() {
+        let mut kv = config("kv");
+        kv.types = types(&[
+            ("code", "string"),
+            ("speed", "int"),
+            ("temp", "float"),
+            ("on", "bool"),
+        ]);
+        let parser = Parser::new(&kv).unwrap();
+        assert_eq!(
+            parser.parse("code=007;speed=-3;temp=21;on=YES").unwrap(),
+            [
+                field("code", text("007")),
+                field("speed", FieldValue::Int(-3)),
+                field("temp", FieldValue::Float(21.0)),
+                field("on", FieldValue::Bool(true)),
+            ]
+        );
+        assert_eq!(
+            parser.parse("on=maybe"),
+            Err(String::from("field 'on': 'maybe' is not a valid bool"))
+        );
+        assert_eq!(
+            parser.parse("temp=warm"),
+            Err(String::from("field 'temp': 'warm' is not a valid float"))
+        );
+    }
+
+    #[test]
+    fn guessed_types() {
+        assert_eq!(guess(String::from("007")), FieldValue::Int(7));
+        assert_eq!(guess(String::from("1e3")), FieldValue::Float(1000.0));
+        assert_eq!(guess(String::from("inf")), text("inf"));
+        assert_eq!(guess(String::from("TRUE")), FieldValue::Bool(true));
+        assert_eq!(guess(String::from("yes")), text("yes"));
+        assert_eq!(
+            summary(&[
+                field("a", FieldValue::Int(1)),
+                field("b", FieldValue::Bool(false)),
+                field("c", text("x y")),
+            ]),
+            "a=1 b=false c=x y"
+        );
+    }
+
+    #[test]
+    fn invalid_configs() {
+        let mut empty_separator = config("kv");
+        empty_separator.separator = Some(String::new());
+        let mut long_delimiter = csv(&["a"]);
+        long_delimiter.delimiter = Some(String::from(";;"));
+        let mut no_captures = config("regex");
+        no_captures.pattern = Some(String::from(r"\d+"));
+        let mut bad_regex = config("regex");
+        bad_regex.pattern = Some(String::from("(?P<a>"));
+        let mut unknown_field_type = config("kv");
+        unknown_field_type.types = types(&[("speed", "number")]);
+        let cases = [
+            (config("xml"), "parser 'test': unknown type 'xml', expected one of: kv, json, csv, regex"),
+            (empty_separator, "parser 'test': separator and assign must not be empty"),
+            (config("csv"), "parser 'test': csv parsers need columns"),
+            (csv(&[]), "parser 'test': csv parsers need columns"),
+            (long_delimiter, "parser 'test': delimiter must be a single character"),
+            (config("regex"), "parser 'test': regex parsers need a pattern"),
+            (no_captures, "parser 'test': pattern has no named captures"),
+            (
+                unknown_field_type,
+                "parser 'test': unknown type 'number' for field 'speed', expected one of: string, int, float, bool",
+            ),
+        ];
+        for (config, err) in cases {
+            assert_eq!(Parser::new(&config).unwrap_err(), err);
+        }
+        assert!(Parser::new(&bad_regex)
+            .unwrap_err()
+            .starts_with("parser 'test': "));
+    }
+}
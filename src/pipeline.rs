@@ -0,0 +1,449 @@
+use chrono::{DateTime, FixedOffset, Local};
+use log::{error, info, log, warn};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::decode::{decode, Decoded};
+use crate::listeners::{Listener, MessageFormat};
+use crate::parsers::{self, FieldValue};
+use crate::plc_time::ClockEvent;
+use crate::reassembly::Reassembler;
+use crate::sequence::{self, SequenceEvent};
+use crate::severity::{Severity, SeverityRules};
+use crate::record::{Ack, PlcRecord};
+use crate::sources::{normalize_addr, SourceMap};
+use crate::stats::Stats;
+use crate::syslog::{self, SyslogHeader};
+
+// unknown senders warned about by a worker or receiver at most, their packets are
+// counted either way
+const MAX_UNKNOWN_REPORTED: usize = 1000;
+
+// senders rejected as unknown, each one is only warned about once
+#[derive(Default)]
+struct UnknownSenders(HashSet<IpAddr>);
+
+impl UnknownSenders {
+    fn reject(&mut self, ip: IpAddr, stats: &Stats) {
+        Stats::incr(&stats.unknown_rejected);
+        if self.0.len() < MAX_UNKNOWN_REPORTED && self.0.insert(ip) {
+            warn!("Rejecting packets from unknown source {ip}, further packets are only counted");
+        }
+    }
+}
+
+// raw message as read from a socket (a udp datagram or a tcp frame), handed from
+// the receivers to the workers
+pub struct Datagram {
+    pub data: Vec<u8>,
+    pub src: SocketAddr,
+    pub received: DateTime<Local>,
+    pub listener: Arc<Listener>,
+    pub seq: Option<u64>,
+    // socket to ack the message on, with the address of the sender as seen by it
+    pub reply: Option<(Arc<UdpSocket>, SocketAddr)>,
+}
+
+impl Datagram {
+    // takes the sequence number off the message on listeners using them
+    pub fn new(
+        data: Vec<u8>,
+        src: SocketAddr,
+        received: DateTime<Local>,
+        listener: Arc<Listener>,
+        stats: &Stats,
+    ) -> Datagram {
+        stats.traffic.listener(&listener.name, data.len(), received);
+        let mut datagram = Datagram {
+            data,
+            src,
+            received,
+            listener,
+            seq: None,
+            reply: None,
+        };
+        if !datagram.listener.sequence_numbers {
+            return datagram;
+        }
+        if let Some((seq, rest)) = sequence::split_header(&datagram.data) {
+            datagram.seq = Some(seq);
+            datagram.data = rest.to_vec();
+        }
+        datagram
+    }
+}
+
+// the datagram queue, one per worker. messages of a sender always go to the same
+// worker, so that they reach the writer in the order they were received
+#[derive(Clone)]
+pub struct WorkQueue {
+    workers: Vec<SyncSender<Datagram>>,
+}
+
+impl WorkQueue {
+    // queue of the worker handling the messages of a sender
+    pub fn worker(&self, src: &SocketAddr) -> &SyncSender<Datagram> {
+        let mut hasher = DefaultHasher::new();
+        src.ip().hash(&mut hasher);
+        &self.workers[hasher.finish() as usize % self.workers.len()]
+    }
+}
+
+// queues of `depth` datagrams for every worker, the receiving ends go to spawn_workers
+pub fn work_queue(workers: usize, depth: usize) -> (WorkQueue, Vec<Receiver<Datagram>>) {
+    let (senders, receivers) = (0..workers).map(|_| sync_channel(depth)).unzip();
+    (WorkQueue { workers: senders }, receivers)
+}
+
+// settings shared by every socket of a udp listener
+#[derive(Debug, Clone)]
+pub struct UdpSettings {
+    pub max_datagram_bytes: usize,
+    // fragmented messages are put back together when set
+    pub reassembly_timeout: Option<Duration>,
+}
+
+// reads datagrams from the socket and queues them for the workers, dropping them
+// when the queue is full so that a burst can never block the socket
+pub fn spawn_receiver(
+    socket: UdpSocket,
+    listener: Arc<Listener>,
+    settings: UdpSettings,
+    reply: Option<Arc<UdpSocket>>,
+    queue: WorkQueue,
+    sources: Arc<SourceMap>,
+    stats: Arc<Stats>,
+) {
+    thread::spawn(move || {
+        // one byte more than allowed, so that longer datagrams can be told apart
+        let mut buf = vec![0u8; settings.max_datagram_bytes + 1];
+        let mut reassembler = settings.reassembly_timeout.map(Reassembler::new);
+        if reassembler.is_some() {
+            // wake up now and then to expire incomplete messages
+            if let Err(err) = socket.set_read_timeout(Some(Duration::from_secs(1))) {
+                error!("{err}");
+            }
+        }
+        // fragments of unknown senders are not held, they are rejected right away
+        let mut unknown = UnknownSenders::default();
+        // only the start and the end of a burst of drops (and of evictions) is logged
+        let mut dropping = false;
+        let mut evicting = false;
+        loop {
+            if let Some(reassembler) = &mut reassembler {
+                for expired in reassembler.expire() {
+                    Stats::incr(&stats.fragments_expired);
+                    warn!(
+                        "Discarded message {} from {}, {} of {} fragments missing",
+                        expired.id, expired.src, expired.missing, expired.count
+                    );
+                }
+            }
+
+            match socket.recv_from(&mut buf) {
+                Ok((amt, src)) => {
+                    Stats::incr(&stats.received);
+                    let mut amt = amt;
+                    if amt > settings.max_datagram_bytes {
+                        amt = settings.max_datagram_bytes;
+                        Stats::incr(&stats.truncated);
+                        warn!(
+                            "Datagram from {src} truncated to {} bytes",
+                            settings.max_datagram_bytes
+                        );
+                    }
+
+                    // acks go to the address as seen by the socket, which may be ipv4-mapped
+                    let reply_to = src;
+                    let src = normalize_addr(src);
+                    let (data, received) = match &mut reassembler {
+                        Some(_) if sources.lookup(src.ip()).is_none() => {
+                            unknown.reject(src.ip(), &stats);
+                            continue;
+                        }
+                        Some(reassembler) => {
+                            let message = reassembler.push(src, &buf[..amt], Local::now());
+                            for evicted in reassembler.evicted() {
+                                Stats::incr(&stats.fragments_evicted);
+                                if !evicting {
+                                    evicting = true;
+                                    warn!(
+                                        "Too many incomplete messages, discarding the oldest (first {} from {}, {} of {} fragments missing)",
+                                        evicted.id, evicted.src, evicted.missing, evicted.count
+                                    );
+                                }
+                            }
+                            match message {
+                                Some(message) => {
+                                    if message.fragments > 1 {
+                                        Stats::incr(&stats.reassembled);
+                                        if evicting {
+                                            evicting = false;
+                                            info!("Incomplete messages within limits again");
+                                        }
+                                    }
+                                    (message.data, message.received)
+                                }
+                                None => continue,
+                            }
+                        }
+                        None => (buf[..amt].to_vec(), Local::now()),
+                    };
+                    let mut datagram =
+                        Datagram::new(data, src, received, Arc::clone(&listener), &stats);
+                    datagram.reply = reply.as_ref().map(|socket| (Arc::clone(socket), reply_to));
+                    match queue.worker(&datagram.src).try_send(datagram) {
+                        Ok(()) => {
+                            Stats::queued(&stats.datagrams_queued);
+                            if dropping {
+                                dropping = false;
+                                info!("Queue accepting packets again");
+                            }
+                        }
+                        Err(TrySendError::Full(datagram)) => {
+                            Stats::failed(&stats.dropped, &stats.dropped_at);
+                            if !dropping {
+                                dropping = true;
+                                warn!("Queue full, dropping packets (first from {})", datagram.src);
+                            }
+                        }
+                        Err(TrySendError::Disconnected(_)) => {
+                            error!("Workers stopped, UDP receiver {} exiting", listener.name);
+                            return;
+                        }
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+                Err(e) => {
+                    error!("{}", e);
+                }
+            }
+        }
+    });
+}
+
+// fixed number of workers decoding datagrams and passing them on to the single writer,
+// one for every queue of the work queue
+pub fn spawn_workers(
+    queues: Vec<Receiver<Datagram>>,
+    output: SyncSender<PlcRecord>,
+    sources: Arc<SourceMap>,
+    severity_rules: Arc<SeverityRules>,
+    stats: Arc<Stats>,
+) {
+    for queue in queues {
+        let output = output.clone();
+        let stats = Arc::clone(&stats);
+        let sources = Arc::clone(&sources);
+        let severity_rules = Arc::clone(&severity_rules);
+        let mut unknown = UnknownSenders::default();
+        thread::spawn(move || loop {
+            let datagram = match queue.recv() {
+                Ok(datagram) => datagram,
+                Err(_) => return,
+            };
+            Stats::dequeued(&stats.datagrams_queued);
+
+            let source = match sources.lookup(datagram.src.ip()) {
+                Some(source) => source,
+                None => {
+                    unknown.reject(datagram.src.ip(), &stats);
+                    continue;
+                }
+            };
+
+            let listener = &datagram.listener;
+            stats
+                .traffic
+                .source(&listener.name, &source.name, datagram.data.len(), datagram.received);
+            // plcs with a layout send binary memory blocks, logged as their values
+            let message = match &source.layout {
+                Some(layout) => match layout.decode(&datagram.data) {
+                    Ok(values) => Message {
+                        payload: parsers::summary(&values),
+                        syslog: None,
+                        parsed: values,
+                        plc_time: None,
+                    },
+                    Err(err) => {
+                        Stats::incr(&stats.rejected);
+                        warn!("Rejected packet from {} ({} layout): {err}", datagram.src, layout.name);
+                        continue;
+                    }
+                },
+                None => match text_message(&datagram, &stats) {
+                    Some(message) => message,
+                    None => continue,
+                },
+            };
+
+            let Message {
+                payload,
+                syslog,
+                parsed,
+                plc_time,
+            } = message;
+            let level = severity_rules.classify(&payload, &parsed, syslog.as_ref());
+
+            let plc_time = match (plc_time, &listener.plc_timestamp) {
+                (Some(plc_time), _) => Some(plc_time),
+                (None, Some(plc_timestamp)) => plc_timestamp.find_in_fields(&parsed),
+                (None, None) => None,
+            };
+            let skew_warn = listener.plc_timestamp.as_ref().and_then(|plc_timestamp| plc_timestamp.skew_warn);
+            let clock_event = match (plc_time, skew_warn) {
+                (Some(plc_time), Some(limit)) => {
+                    let skew = plc_time.signed_duration_since(datagram.received);
+                    stats.clocks.check(datagram.src.ip(), skew, limit)
+                }
+                _ => None,
+            };
+
+            // only messages that are logged count, a message dropped or rejected before
+            // is missing when the plc sends it again. the messages of a sender are
+            // handled by one worker, in the order they were received
+            let sequence_event = datagram
+                .seq
+                .and_then(|seq| stats.sequences.track(&listener.name, datagram.src.ip(), seq));
+            let ack = match (datagram.reply, datagram.seq) {
+                (Some((socket, dst)), Some(seq)) => Some(Ack { socket, dst, seq }),
+                _ => None,
+            };
+
+            let record = PlcRecord {
+                received: datagram.received,
+                plc_time,
+                src: datagram.src,
+                source,
+                listener: Arc::clone(&listener.name),
+                sink: Arc::clone(&listener.sink),
+                payload,
+                len: datagram.data.len(),
+                level,
+                syslog,
+                seq: datagram.seq,
+                parsed,
+                ack,
+                sequence_event,
+            };
+
+            // irregular sequence numbers and plc clock changes are logged in front of the
+            // message they were noticed on
+            // with acks a duplicate is a retransmit after a lost ack, the writer decides
+            // whether it was logged already
+            let sequence_notice = record
+                .sequence_event
+                .as_ref()
+                .filter(|event| !(listener.ack && matches!(event, SequenceEvent::Duplicate(_))))
+                .map(|event| (Severity::Warn, event.to_string()));
+            let clock_notice = clock_event.map(|event| match event {
+                ClockEvent::Drifted { .. } => (Severity::Warn, event.to_string()),
+                ClockEvent::Recovered { .. } => (Severity::Info, event.to_string()),
+            });
+            for (level, notice) in sequence_notice.into_iter().chain(clock_notice) {
+                log!(level.log_level(), "Source {} ({}): {notice}", record.source.name, datagram.src);
+                let warning = PlcRecord {
+                    payload: format!("*** {notice}"),
+                    len: 0,
+                    level,
+                    syslog: None,
+                    parsed: Vec::new(),
+                    ack: None,
+                    sequence_event: None,
+                    ..record.clone()
+                };
+                if output.send(warning).is_err() {
+                    return;
+                }
+                Stats::queued(&stats.records_queued);
+            }
+
+            if output.send(record).is_err() {
+                return;
+            }
+            Stats::queued(&stats.records_queued);
+        });
+    }
+}
+
+// a message decoded by a worker, before it becomes a record
+struct Message {
+    payload: String,
+    syslog: Option<SyslogHeader>,
+    parsed: Vec<(String, FieldValue)>,
+    plc_time: Option<DateTime<FixedOffset>>,
+}
+
+// decoded text of a message, after the syslog header and the parser, None when rejected
+fn text_message(datagram: &Datagram, stats: &Stats) -> Option<Message> {
+    let listener = &datagram.listener;
+    let payload = match decode(listener.decode, &datagram.data) {
+        Ok(Decoded::Text(text)) => text,
+        Ok(transcoded) => {
+            Stats::incr(&stats.transcoded);
+            transcoded.into_string()
+        }
+        Err(err) => {
+            Stats::incr(&stats.rejected);
+            warn!(
+                "Rejected packet from {} ({} decoding): {err}",
+                datagram.src, listener.decode
+            );
+            return None;
+        }
+    };
+
+    let (payload, syslog) = match listener.format {
+        MessageFormat::Raw => (payload, None),
+        MessageFormat::Syslog => {
+            let (header, message) = syslog::parse(&payload);
+            (message.to_string(), Some(header))
+        }
+    };
+
+    // a plc time in front of the message is cut off before the parser sees it
+    let prefix = listener
+        .plc_timestamp
+        .as_ref()
+        .and_then(|plc_timestamp| plc_timestamp.split_prefix(&payload))
+        .map(|(plc_time, message)| (plc_time, message.to_string()));
+    let (payload, plc_time) = match prefix {
+        Some((plc_time, message)) => (message, Some(plc_time)),
+        None => (payload, None),
+    };
+
+    let (payload, parsed) = match &listener.parser {
+        Some(parser) => match parser.parse(&payload) {
+            Ok(parsed) => {
+                let message = parser.message_field.as_ref().and_then(|message_field| {
+                    parsed.iter().find(|(key, _)| key == message_field)
+                });
+                let payload = match message {
+                    Some((_, message)) => message.to_string(),
+                    None => payload,
+                };
+                (payload, parsed)
+            }
+            Err(err) => {
+                // the message is still logged, just without fields
+                Stats::incr(&stats.parse_failed);
+                warn!("Failed to parse message from {} ({} parser): {err}", datagram.src, parser.name);
+                (payload, Vec::new())
+            }
+        },
+        None => (payload, Vec::new()),
+    };
+    Some(Message {
+        payload,
+        syslog,
+        parsed,
+        plc_time,
+    })
+}
@@ -0,0 +1,369 @@
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDateTime, Offset, TimeZone, Utc};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use crate::parsers::FieldValue;
+use crate::rotation::RotationTz;
+
+// text forms of a plc time without offset, the siemens literals (DT#2024-03-01-12:00:00.250)
+// have their prefix removed first
+const NAIVE_FORMATS: [&str; 3] = [
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%d-%H:%M:%S%.f",
+];
+
+// where the time the plc sent a message at is taken from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimestampSource {
+    // start of the message, removed from the logged message
+    Prefix,
+    // a parsed or layout field
+    Field(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct PlcTimestamp {
+    pub source: TimestampSource,
+    // zone of plc times without an offset
+    pub timezone: RotationTz,
+    // warn when the plc clock is further off than this, None never warns
+    pub skew_warn: Option<Duration>,
+}
+
+impl PlcTimestamp {
+    // plc time at the start of the message and the message without it
+    pub fn split_prefix<'a>(&self, payload: &'a str) -> Option<(DateTime<FixedOffset>, &'a str)> {
+        if self.source != TimestampSource::Prefix {
+            return None;
+        }
+        // a date and a time may be separated by a space, the longer candidate is tried first
+        let mut ends: Vec<usize> = payload
+            .match_indices(' ')
+            .map(|(index, _)| index)
+            .take(2)
+            .collect();
+        ends.push(payload.len());
+        ends.truncate(2);
+        ends.into_iter().rev().find_map(|end| {
+            let time = parse_timestamp(&payload[..end], self.timezone)?;
+            Some((time, payload[end..].trim_start()))
+        })
+    }
+
+    pub fn find_in_fields(&self, parsed: &[(String, FieldValue)]) -> Option<DateTime<FixedOffset>> {
+        let name = match &self.source {
+            TimestampSource::Field(name) => name,
+            TimestampSource::Prefix => return None,
+        };
+        let (_, value) = parsed.iter().find(|(key, _)| key == name)?;
+        match value {
+            FieldValue::Str(text) => parse_timestamp(text, self.timezone),
+            // seconds since the epoch
+            FieldValue::Int(secs) => Utc.timestamp_opt(*secs, 0).single().map(fixed),
+            _ => None,
+        }
+    }
+}
+
+// iso 8601 with or without offset, or a siemens DT# / DTL# / DATE_AND_TIME# literal
+pub fn parse_timestamp(text: &str, timezone: RotationTz) -> Option<DateTime<FixedOffset>> {
+    let text = text.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time);
+    }
+    let upper = text.to_ascii_uppercase();
+    let literal = ["DATE_AND_TIME#", "DTL#", "DT#"]
+        .iter()
+        .find_map(|prefix| upper.strip_prefix(prefix).map(|_| &text[prefix.len()..]));
+    let text = literal.unwrap_or(text);
+    let naive = NAIVE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())?;
+    localize(naive, timezone)
+}
+
+fn localize(naive: NaiveDateTime, timezone: RotationTz) -> Option<DateTime<FixedOffset>> {
+    // on a daylight saving change the earlier of two possible times is taken
+    match timezone {
+        RotationTz::Local => Local.from_local_datetime(&naive).earliest().map(fixed),
+        RotationTz::Utc => Some(fixed(Utc.from_utc_datetime(&naive))),
+        RotationTz::Named(tz) => tz.from_local_datetime(&naive).earliest().map(fixed),
+    }
+}
+
+fn fixed<Tz: TimeZone>(time: DateTime<Tz>) -> DateTime<FixedOffset> {
+    let offset = time.offset().fix();
+    time.with_timezone(&offset)
+}
+
+// changes of a plc clock relative to the clock of the logger
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClockEvent {
+    Drifted { skew: Duration, limit: Duration },
+    Recovered { skew: Duration },
+}
+
+impl fmt::Display for ClockEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClockEvent::Drifted { skew, limit } => write!(
+                f,
+                "plc clock off by {}, more than {} s",
+                format_skew(*skew),
+                limit.num_seconds()
+            ),
+            ClockEvent::Recovered { skew } => {
+                write!(f, "plc clock back in line, off by {}", format_skew(*skew))
+            }
+        }
+    }
+}
+
+fn format_skew(skew: Duration) -> String {
+    format!("{:+.3} s", skew.num_milliseconds() as f64 / 1000.0)
+}
+
+// plcs whose clock is off, by sender address, so each drift is warned about once
+#[derive(Debug, Default)]
+pub struct ClockTracker {
+    drifted: Mutex<HashMap<IpAddr, bool>>,
+}
+
+impl ClockTracker {
+    pub fn check(&self, ip: IpAddr, skew: Duration, limit: Duration) -> Option<ClockEvent> {
+        let off = skew > limit || skew < -limit;
+        let mut drifted = self.drifted.lock().unwrap();
+        let was_off = drifted.insert(ip, off).unwrap_or(false);
+        match (was_off, off) {
+            (false, true) => Some(ClockEvent::Drifted { skew, limit }),
+            (true, false) => Some(ClockEvent::Recovered { skew }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(text: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(text).unwrap()
+    }
+
+    fn prefix(timezone: RotationTz) -> PlcTimestamp {
+        PlcTimestamp {
+            source: TimestampSource::Prefix,
+            timezone,
+            skew_warn: None,
+        }
+    }
+
+    #[test]
+    fn accepted_formats() {
+        let berlin = RotationTz::Named(chrono_tz::Europe::Berlin);
+        for (text, timezone, expected) in [
+            (
+                "2024-03-01T12:00:00.250Z",
+                RotationTz::Utc,
+                "2024-03-01T12:00:00.250Z",
+            ),
+            (
+                "2024-03-01T12:00:00+02:00",
+                berlin,
+                "2024-03-01T12:00:00+02:00",
+            ),
+            (
+                "2024-03-01T12:00:00.250",
+                RotationTz::Utc,
+                "2024-03-01T12:00:00.250Z",
+            ),
+            (
+                "2024-03-01 12:00:00",
+                RotationTz::Utc,
+                "2024-03-01T12:00:00Z",
+            ),
+            (
+                "2024-03-01-12:00:00",
+                RotationTz::Utc,
+                "2024-03-01T12:00:00Z",
+            ),
+            (
+                "DT#2024-03-01-12:00:00.250",
+                RotationTz::Utc,
+                "2024-03-01T12:00:00.250Z",
+            ),
+            (
+                "dtl#2024-03-01-12:00:00",
+                RotationTz::Utc,
+                "2024-03-01T12:00:00Z",
+            ),
+            (
+                "DATE_AND_TIME#2024-03-01-12:00:00",
+                RotationTz::Utc,
+                "2024-03-01T12:00:00Z",
+            ),
+            (
+                "  2024-03-01T12:00:00 ",
+                RotationTz::Utc,
+                "2024-03-01T12:00:00Z",
+            ),
+            // times without an offset are in the configured zone
+            ("2024-03-01 12:00:00", berlin, "2024-03-01T12:00:00+01:00"),
+            ("2024-07-01 12:00:00", berlin, "2024-07-01T12:00:00+02:00"),
+            // twice on the change back from summer time, the earlier one is taken
+            ("2024-10-27 02:30:00", berlin, "2024-10-27T02:30:00+02:00"),
+        ] {
+            assert_eq!(
+                parse_timestamp(text, timezone),
+                Some(time(expected)),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejected_input() {
+        let berlin = RotationTz::Named(chrono_tz::Europe::Berlin);
+        for text in [
+            "",
+            "DT#",
+            "12:00:00",
+            "2024-03-01",
+            "2024-13-01T12:00:00",
+            "2024-02-30 12:00:00",
+            "2024-03-01 25:00:00",
+            "2024/03/01 12:00:00",
+            "T#2024-03-01-12:00:00",
+            "2024-03-01T12:00:00 tank full",
+        ] {
+            assert_eq!(parse_timestamp(text, RotationTz::Utc), None, "{text}");
+        }
+        // skipped by the change to summer time
+        assert_eq!(parse_timestamp("2024-03-31 02:30:00", berlin), None);
+    }
+
+    #[test]
+    fn time_in_front_of_the_message() {
+        let plc_timestamp = prefix(RotationTz::Utc);
+        let noon = time("2024-03-01T12:00:00Z");
+        for (payload, message) in [
+            ("2024-03-01T12:00:00Z tank full", "tank full"),
+            ("2024-03-01 12:00:00 tank full", "tank full"),
+            ("DT#2024-03-01-12:00:00   tank  full", "tank  full"),
+            // nothing after the time
+            ("2024-03-01T12:00:00Z", ""),
+            ("2024-03-01 12:00:00", ""),
+            ("2024-03-01 12:00:00 ", ""),
+        ] {
+            assert_eq!(
+                plc_timestamp.split_prefix(payload),
+                Some((noon, message)),
+                "{payload}"
+            );
+        }
+        for payload in [
+            "tank full",
+            "",
+            "2024-03-01T12:00:00Ztank full",
+            "2024-03-01 tank full",
+        ] {
+            assert_eq!(plc_timestamp.split_prefix(payload), None, "{payload}");
+        }
+        let field = PlcTimestamp {
+            source: TimestampSource::Field(String::from("time")),
+            ..plc_timestamp
+        };
+        assert_eq!(field.split_prefix("2024-03-01T12:00:00Z tank full"), None);
+    }
+
+    #[test]
+    fn time_in_a_field() {
+        let field = PlcTimestamp {
+            source: TimestampSource::Field(String::from("time")),
+            timezone: RotationTz::Utc,
+            skew_warn: None,
+        };
+        let noon = time("2024-03-01T12:00:00Z");
+        let parsed = |value| {
+            vec![
+                (String::from("level"), FieldValue::Int(3)),
+                (String::from("time"), value),
+            ]
+        };
+        assert_eq!(
+            field.find_in_fields(&parsed(FieldValue::Str(String::from(
+                "DT#2024-03-01-12:00:00"
+            )))),
+            Some(noon)
+        );
+        assert_eq!(
+            field.find_in_fields(&parsed(FieldValue::Int(noon.timestamp()))),
+            Some(noon)
+        );
+        assert_eq!(
+            field.find_in_fields(&parsed(FieldValue::Str(String::from("noon")))),
+            None
+        );
+        assert_eq!(field.find_in_fields(&parsed(FieldValue::Bool(true))), None);
+        assert_eq!(
+            field.find_in_fields(&parsed(FieldValue::Int(i64::MAX))),
+            None
+        );
+        assert_eq!(field.find_in_fields(&[]), None);
+        assert_eq!(
+            prefix(RotationTz::Utc).find_in_fields(&parsed(FieldValue::Int(0))),
+            None
+        );
+    }
+
+    #[test]
+    fn clock_drift_is_reported_once() {
+        let tracker = ClockTracker::default();
+        let plc: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        let limit = Duration::seconds(60);
+        let ms = Duration::milliseconds;
+
+        // within the limit either way, the limit itself included
+        for skew in [ms(0), ms(60_000), ms(-60_000)] {
+            assert_eq!(tracker.check(plc, skew, limit), None);
+        }
+        assert_eq!(
+            tracker.check(plc, ms(-90_500), limit),
+            Some(ClockEvent::Drifted {
+                skew: ms(-90_500),
+                limit
+            })
+        );
+        assert_eq!(tracker.check(plc, ms(120_000), limit), None);
+        // every plc is tracked apart
+        assert_eq!(
+            tracker.check(other, ms(60_001), limit),
+            Some(ClockEvent::Drifted {
+                skew: ms(60_001),
+                limit
+            })
+        );
+        assert_eq!(
+            tracker.check(plc, ms(1_250), limit),
+            Some(ClockEvent::Recovered { skew: ms(1_250) })
+        );
+        assert_eq!(tracker.check(plc, ms(1_250), limit), None);
+
+        assert_eq!(
+            ClockEvent::Drifted {
+                skew: ms(-90_500),
+                limit
+            }
+            .to_string(),
+            "plc clock off by -90.500 s, more than 60 s"
+        );
+        assert_eq!(
+            ClockEvent::Recovered { skew: ms(1_250) }.to_string(),
+            "plc clock back in line, off by +1.250 s"
+        );
+    }
+}
@@ -0,0 +1,432 @@
+use log::{info, Record};
+use log4rs::append::Append;
+use std::collections::HashMap;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::io;
+use std::path::PathBuf;
+
+use crate::logging::rolling_appender;
+use crate::rotation::{sync_file, Rotation};
+use crate::record::PlcRecord;
+use crate::settings::AppConfig;
+use crate::severity::Severity;
+use crate::sinks::{Sink, SinkFormat};
+use crate::sources::{Source, SourceMap};
+use crate::sqlite_sink::SqliteSink;
+
+// default pattern used for lines received from the plcs, record fields are available as {X(<name>)}
+pub const LOG_PATTERN_PLC: &str = "{X(received)} | {X(source)} | {X(src)} | {m}{n}";
+
+#[derive(Debug)]
+struct LogFile {
+    appender: Box<dyn Append>,
+    path: PathBuf,
+}
+
+#[derive(Debug)]
+enum Output {
+    // every plc goes into the same log
+    Single(LogFile),
+    // one log per source name, under <source_log_dir>/<source>/
+    PerSource(HashMap<String, LogFile>),
+    Sqlite(SqliteSink),
+}
+
+// writes plc payloads to their own rolling file(s), independent of the global logger
+#[derive(Debug)]
+pub struct PlcWriter {
+    // output of every sink, by sink name
+    outputs: HashMap<String, (Output, SinkFormat, Severity)>,
+    // logs written to since the last sync
+    unsynced: Vec<PathBuf>,
+}
+
+impl PlcWriter {
+    pub fn new(appconfig: &AppConfig) -> Result<PlcWriter, Box<dyn Error>> {
+        let mut outputs = HashMap::new();
+        for sink in &appconfig.sinks {
+            outputs.insert(
+                sink.name.clone(),
+                (sink_output(appconfig, sink)?, sink.format, sink.min_level),
+            );
+        }
+        Ok(PlcWriter {
+            outputs,
+            unsynced: Vec::new(),
+        })
+    }
+
+    // end the open transactions of the sqlite sinks, every sink is committed even when
+    // one of them fails
+    pub fn flush(&mut self) -> Result<(), String> {
+        let mut result = Ok(());
+        for (name, (output, _, _)) in self.outputs.iter_mut() {
+            if let Output::Sqlite(sqlite) = output {
+                if let Err(err) = sqlite.commit() {
+                    result = Err(format!("sqlite sink '{name}': {err}"));
+                }
+            }
+        }
+        result
+    }
+
+    // make everything written so far durable, the appenders only hand their lines to
+    // the os. fsync on any handle of a file flushes all of its data, lines rolled away
+    // since they were written were synced by the roll. a log that failed to sync stays
+    // unsynced until a later sync gets through
+    pub fn sync(&mut self) -> io::Result<()> {
+        for (output, _, _) in self.outputs.values_mut() {
+            if let Output::Sqlite(sqlite) = output {
+                sqlite.commit().map_err(io::Error::other)?;
+            }
+        }
+        while let Some(path) = self.unsynced.last() {
+            match sync_file(path) {
+                // rolled and not written to again
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                synced => synced?,
+            }
+            self.unsynced.pop();
+        }
+        Ok(())
+    }
+
+    // Ok(false) when the record is below the minimum level of its source or sink
+    pub fn write(&mut self, record: &PlcRecord) -> Result<bool, String> {
+        let (output, format, min_level) = match self.outputs.get_mut(&*record.sink) {
+            Some(output) => output,
+            None => return Err(format!("no plc log for sink '{}'", record.sink)),
+        };
+        if record.level < record.source.min_level || record.level < *min_level {
+            return Ok(false);
+        }
+        let log_file = match output {
+            Output::Single(log_file) => log_file,
+            Output::PerSource(log_files) => match log_files.get(&record.source.name) {
+                Some(log_file) => log_file,
+                None => return Err(format!("no plc log for source '{}'", record.source.name)),
+            },
+            Output::Sqlite(sqlite) => {
+                return sqlite
+                    .insert(record)
+                    .map(|_| true)
+                    .map_err(|err| format!("failed to write sqlite sink '{}': {err}", record.sink));
+            }
+        };
+        if !self.unsynced.contains(&log_file.path) {
+            self.unsynced.push(log_file.path.clone());
+        }
+
+        // the record fields are handed to the pattern encoder through the mdc, cleared
+        // first as parsed fields differ from record to record
+        log_mdc::clear();
+        for (key, value) in record.fields() {
+            log_mdc::insert(key, value);
+        }
+        for (key, value) in &record.parsed {
+            log_mdc::insert(key.clone(), value.to_string());
+        }
+        log_mdc::insert("fields", record.parsed_summary());
+
+        let line = match format {
+            SinkFormat::Text => record.payload.clone(),
+            SinkFormat::Json | SinkFormat::Sqlite => record.to_json(),
+        };
+        log_file
+            .appender
+            .append(
+                &Record::builder()
+                    .args(format_args!("{line}"))
+                    .level(record.level.log_level())
+                    .target("plc")
+                    .build(),
+            )
+            .map(|_| true)
+            .map_err(|err| format!("failed to write {}: {err}", log_file.path.display()))
+    }
+}
+
+fn sink_output(appconfig: &AppConfig, sink: &Sink) -> Result<Output, Box<dyn Error>> {
+    // json lines are complete when they reach the appender
+    let pattern = match sink.format {
+        SinkFormat::Text => &sink.plc_log_pattern,
+        SinkFormat::Json => "{m}{n}",
+        SinkFormat::Sqlite => {
+            let sqlite = SqliteSink::open(&sink.log_path)?;
+            info!("Writing sink '{}' to database {}", sink.name, sink.log_path.display());
+            return Ok(Output::Sqlite(sqlite));
+        }
+    };
+    if !sink.split_by_source {
+        let appender = rolling_appender(
+            &sink.log_path,
+            &sink.archive_pattern,
+            pattern,
+            &appconfig.rotation(),
+        )?;
+        info!("Logging sink '{}' to {}", sink.name, sink.log_path.display());
+        return Ok(Output::Single(LogFile {
+            appender,
+            path: sink.log_path.clone(),
+        }));
+    }
+
+    let mut log_files = HashMap::new();
+    for source in appconfig.sources.sources() {
+        let (log_path, roller_pattern) = source_paths(sink, &source);
+        let appender = rolling_appender(
+            &log_path,
+            &roller_pattern,
+            pattern,
+            &source_rotation(&source, appconfig.rotation()),
+        )?;
+        info!(
+            "Logging source '{}' of sink '{}' to {}",
+            source.name,
+            sink.name,
+            log_path.display()
+        );
+        log_files.insert(
+            source.name.clone(),
+            LogFile {
+                appender,
+                path: log_path,
+            },
+        );
+    }
+    Ok(Output::PerSource(log_files))
+}
+
+// every source gets its own directory holding the active log and its archives
+pub fn source_paths(sink: &Sink, source: &Source) -> (PathBuf, PathBuf) {
+    let log_file_name = sink.log_path.file_name().unwrap_or(OsStr::new("plc.log"));
+    let archive_file_name = sink.archive_pattern.file_name().unwrap_or(OsStr::new("plclog_{}.gz"));
+    let dir = sink.source_log_dir.join(dir_name(&source.name));
+    let log_path = dir.join(log_file_name);
+    let roller_pattern = dir.join("history").join(archive_file_name);
+    (log_path, roller_pattern)
+}
+
+// archive patterns of every plc log the writer produces
+pub fn archive_patterns(appconfig: &AppConfig) -> Vec<PathBuf> {
+    let mut patterns = Vec::new();
+    for sink in appconfig.sinks.iter().filter(|sink| sink.format != SinkFormat::Sqlite) {
+        if !sink.split_by_source {
+            patterns.push(sink.archive_pattern.clone());
+            continue;
+        }
+        for source in appconfig.sources.sources() {
+            patterns.push(source_paths(sink, &source).1);
+        }
+    }
+    patterns
+}
+
+// databases of the sqlite sinks, pruned with the same limits as the archives
+pub fn databases(appconfig: &AppConfig) -> Vec<PathBuf> {
+    appconfig
+        .sinks
+        .iter()
+        .filter(|sink| sink.format == SinkFormat::Sqlite)
+        .map(|sink| sink.log_path.clone())
+        .collect()
+}
+
+// rotation of a source, falling back to the global settings for anything not overridden
+fn source_rotation(source: &Source, global: Rotation) -> Rotation {
+    Rotation {
+        max_size_mb: source.log_max_size_mb.unwrap_or(global.max_size_mb),
+        history_to_keep: source.log_history_to_keep.unwrap_or(global.history_to_keep),
+        ..global
+    }
+}
+
+// every source needs a directory of its own, names differing only in case or in
+// characters that are replaced would share one on some file systems
+pub fn check_source_dirs(sources: &SourceMap) -> Result<(), String> {
+    let mut dirs: HashMap<String, String> = HashMap::new();
+    for source in sources.sources() {
+        let dir = dir_name(&source.name);
+        if dir.is_empty() {
+            return Err(format!("source name '{}' can not be used as a directory name", source.name));
+        }
+        if let Some(other) = dirs.insert(dir.to_lowercase(), source.name.clone()) {
+            return Err(format!(
+                "sources '{other}' and '{}' would both log to directory '{dir}'",
+                source.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+// source names are free text, keep them from escaping the log directory
+fn dir_name(source_name: &str) -> String {
+    source_name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect::<String>()
+        .trim_start_matches('.')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rotation::{RotationInterval, RotationTz};
+    use crate::sources::SourceConfig;
+    use flate2::read::GzDecoder;
+    use std::fs;
+    use std::io::Read;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn source_map(names: &[&str], unknown: Option<&str>) -> SourceMap {
+        let sources: Vec<SourceConfig> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| SourceConfig {
+                name: name.to_string(),
+                address: format!("10.0.1.{}", i + 1),
+                area: String::new(),
+                line: String::new(),
+                log_max_size_mb: None,
+                log_history_to_keep: None,
+                min_level: None,
+                layout: None,
+            })
+            .collect();
+        SourceMap::new(&sources, unknown, Severity::Trace, &[]).unwrap()
+    }
+
+    fn record(payload: &str) -> PlcRecord {
+        PlcRecord {
+            received: chrono::Local::now(),
+            plc_time: None,
+            src: "10.0.1.1:4000".parse().unwrap(),
+            source: Arc::new(Source::default()),
+            listener: Arc::from("udp"),
+            sink: Arc::from("default"),
+            payload: payload.to_string(),
+            len: payload.len(),
+            level: Severity::Info,
+            syslog: None,
+            seq: None,
+            parsed: Vec::new(),
+            ack: None,
+            sequence_event: None,
+        }
+    }
+
+    #[test]
+    fn roll_between_write_and_sync() {
+        let dir = std::env::temp_dir().join(format!("plclogger-{}-roll", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let log_path = dir.join("plc.log");
+        let rotation = Rotation {
+            max_size_mb: 1,
+            history_to_keep: 2,
+            interval: RotationInterval::None,
+            timezone: RotationTz::Utc,
+            on_size: true,
+        };
+        let appender =
+            rolling_appender(&log_path, &dir.join("plclog_{}.gz"), "{m}{n}", &rotation).unwrap();
+        let log_file = LogFile {
+            appender,
+            path: log_path.clone(),
+        };
+        let mut writer = PlcWriter {
+            outputs: HashMap::from([(
+                String::from("default"),
+                (Output::Single(log_file), SinkFormat::Text, Severity::Trace),
+            )]),
+            unsynced: Vec::new(),
+        };
+
+        let first = "a".repeat(600_000);
+        let second = "b".repeat(600_000);
+        assert_eq!(writer.write(&record(&first)), Ok(true));
+        assert_eq!(writer.write(&record(&second)), Ok(true));
+        // the second line took the log over the limit, it was synced and rolled away
+        assert!(!log_path.exists());
+        writer.sync().unwrap();
+        assert!(writer.unsynced.is_empty());
+
+        let archive = dir.join("plclog_1.gz");
+        for _ in 0..100 {
+            let rolling = fs::read_dir(&dir).unwrap().any(|entry| {
+                entry
+                    .unwrap()
+                    .path()
+                    .to_string_lossy()
+                    .ends_with(".rolling")
+            });
+            if archive.exists() && !rolling {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let mut archived = String::new();
+        GzDecoder::new(fs::File::open(&archive).unwrap())
+            .read_to_string(&mut archived)
+            .unwrap();
+        assert_eq!(archived, format!("{first}\n{second}\n"));
+
+        assert_eq!(writer.write(&record("third")), Ok(true));
+        writer.sync().unwrap();
+        assert_eq!(fs::read_to_string(&log_path).unwrap(), "third\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dir_names() {
+        assert_eq!(dir_name("press-01"), "press-01");
+        assert_eq!(dir_name("press 01"), "press_01");
+        assert_eq!(dir_name("../etc"), "_etc");
+        assert_eq!(dir_name(".."), "");
+    }
+
+    #[test]
+    fn distinct_source_dirs() {
+        assert_eq!(
+            check_source_dirs(&source_map(&["press 01", "press 02"], Some("unknown"))),
+            Ok(())
+        );
+        // several addresses of one source share its directory
+        assert_eq!(
+            check_source_dirs(&source_map(&["press", "press"], None)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn colliding_source_dirs() {
+        let cases = [
+            (
+                source_map(&["press 01", "press/01"], None),
+                "sources 'press 01' and 'press/01' would both log to directory 'press_01'",
+            ),
+            (
+                source_map(&["Press", "press"], None),
+                "sources 'Press' and 'press' would both log to directory 'press'",
+            ),
+            (
+                source_map(&["unknown"], Some("Unknown")),
+                "sources 'unknown' and 'Unknown' would both log to directory 'Unknown'",
+            ),
+            (
+                source_map(&[".."], None),
+                "source name '..' can not be used as a directory name",
+            ),
+        ];
+        for (sources, err) in cases {
+            assert_eq!(check_source_dirs(&sources).unwrap_err(), err);
+        }
+    }
+}
@@ -0,0 +1,893 @@
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use flate2::read::GzDecoder;
+use regex::Regex;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params_from_iter, Connection, OpenFlags};
+use serde_json::{Map, Value};
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::plc_writer::source_paths;
+use crate::record::RECEIVED_FORMAT;
+use crate::rotation::archive_files;
+use crate::settings::AppConfig;
+use crate::severity::Severity;
+use crate::sinks::{Sink, SinkFormat};
+use crate::sqlite_sink::{self, TIME_FORMAT};
+
+// filters of `plclogger query`, times are local like the received field of the logs
+#[derive(Debug, Default)]
+pub struct QueryArgs {
+    pub sink: Option<String>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    pub source: Option<String>,
+    // this level and up
+    pub level: Option<Severity>,
+    pub grep: Option<Regex>,
+    pub json: bool,
+    // stop after this many records
+    pub limit: Option<usize>,
+    // newest records first, the last ones matching when limited
+    pub newest: bool,
+}
+
+impl QueryArgs {
+    fn done(&self, count: usize) -> bool {
+        self.limit.is_some_and(|limit| count >= limit)
+    }
+
+    fn matches(
+        &self,
+        received: Option<NaiveDateTime>,
+        source: Option<&str>,
+        level: Option<Severity>,
+        text: &str,
+    ) -> bool {
+        if let (Some(from), Some(received)) = (self.from, received) {
+            if received < from {
+                return false;
+            }
+        }
+        if let (Some(to), Some(received)) = (self.to, received) {
+            if received > to {
+                return false;
+            }
+        }
+        if let Some(wanted) = &self.source {
+            if source != Some(wanted.as_str()) {
+                return false;
+            }
+        }
+        if let Some(min_level) = self.level {
+            if level.is_none_or(|level| level < min_level) {
+                return false;
+            }
+        }
+        match &self.grep {
+            Some(grep) => grep.is_match(text),
+            None => true,
+        }
+    }
+}
+
+// absolute local times ("2024-03-01", "2024-03-01 14:30", "2024-03-01T14:30:15") or
+// a time ago in seconds, minutes, hours or days ("90s", "15m", "2h", "7d")
+pub fn parse_time(s: &str) -> Result<NaiveDateTime, String> {
+    let formats = [
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M",
+    ];
+    if let Some(time) = formats
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+    {
+        return Ok(time);
+    }
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap());
+    }
+    let invalid = || format!("invalid time '{s}', expected ie. 2024-03-01 14:30 or 2h");
+    let unit = s.chars().last().ok_or_else(invalid)?;
+    let count: i64 = s[..s.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| invalid())?;
+    let ago = match unit {
+        's' => chrono::Duration::seconds(count),
+        'm' => chrono::Duration::minutes(count),
+        'h' => chrono::Duration::hours(count),
+        'd' => chrono::Duration::days(count),
+        _ => return Err(invalid()),
+    };
+    Ok((Local::now() - ago).naive_local())
+}
+
+// print the records of a sink matching the filters, oldest first, returns the number printed
+pub fn run(appconfig: &AppConfig, args: &QueryArgs) -> Result<usize, String> {
+    match search(appconfig, args, &mut io::stdout().lock()) {
+        // output piped into head and the like
+        Err(QueryError::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe => Ok(0),
+        result => result.map_err(QueryError::into_message),
+    }
+}
+
+// write the records of a sink matching the filters, oldest first unless newest is set,
+// one per line
+pub fn search(
+    appconfig: &AppConfig,
+    args: &QueryArgs,
+    out: &mut impl Write,
+) -> Result<usize, QueryError> {
+    let sink_name = args.sink.as_deref().unwrap_or(crate::sinks::DEFAULT_SINK);
+    let sink = match appconfig.sinks.iter().find(|sink| sink.name == sink_name) {
+        Some(sink) => sink,
+        None => return Err(format!("unknown sink '{sink_name}'").into()),
+    };
+    match sink.format {
+        SinkFormat::Sqlite => query_database(sink, args, out),
+        SinkFormat::Text | SinkFormat::Json => query_files(appconfig, sink, args, out),
+    }
+}
+
+#[derive(Debug)]
+pub enum QueryError {
+    Io(io::Error),
+    // the query itself is wrong, like an unknown sink or a filter the logs can not answer
+    Message(String),
+}
+
+impl QueryError {
+    pub fn into_message(self) -> String {
+        match self {
+            QueryError::Io(err) => err.to_string(),
+            QueryError::Message(err) => err,
+        }
+    }
+}
+
+impl From<io::Error> for QueryError {
+    fn from(err: io::Error) -> Self {
+        QueryError::Io(err)
+    }
+}
+
+impl From<String> for QueryError {
+    fn from(err: String) -> Self {
+        QueryError::Message(err)
+    }
+}
+
+impl From<rusqlite::Error> for QueryError {
+    fn from(err: rusqlite::Error) -> Self {
+        QueryError::Message(err.to_string())
+    }
+}
+
+fn query_files(
+    appconfig: &AppConfig,
+    sink: &Sink,
+    args: &QueryArgs,
+    out: &mut impl Write,
+) -> Result<usize, QueryError> {
+    let pattern = match sink.format {
+        SinkFormat::Text => Some(LinePattern::new(&sink.plc_log_pattern).map_err(|err| {
+            format!(
+                "failed to read the plc_log_pattern of sink '{}': {err}",
+                sink.name
+            )
+        })?),
+        _ => None,
+    };
+    // without the fields in the lines the filters can not be applied
+    if let Some(pattern) = &pattern {
+        let missing =
+            |field: &str| format!("the plc_log_pattern of sink '{}' has no {field}", sink.name);
+        if (args.from.is_some() || args.to.is_some()) && !pattern.has("received") {
+            return Err(missing("{X(received)}").into());
+        }
+        if args.source.is_some() && !sink.split_by_source && !pattern.has("source") {
+            return Err(missing("{X(source)}").into());
+        }
+        if args.level.is_some() && !pattern.has("level") {
+            return Err(missing("{l} or {X(level)}").into());
+        }
+    }
+
+    // split logs are searched side by side and merged by the time received
+    let logs: Vec<(Option<String>, PathBuf, PathBuf)> = match sink.split_by_source {
+        false => vec![(None, sink.log_path.clone(), sink.archive_pattern.clone())],
+        true => appconfig
+            .sources
+            .sources()
+            .iter()
+            .filter(|source| {
+                args.source
+                    .as_ref()
+                    .is_none_or(|wanted| *wanted == source.name)
+            })
+            .map(|source| {
+                let (log_path, archive_pattern) = source_paths(sink, source);
+                (Some(source.name.clone()), log_path, archive_pattern)
+            })
+            .collect(),
+    };
+    let logs = logs
+        .into_iter()
+        .map(|(source, log_path, archive_pattern)| {
+            let mut files = archives_in_order(&archive_pattern);
+            files.push(log_path);
+            LogMatches::new(args, pattern.as_ref(), source, files)
+        })
+        .collect();
+    write_merged(logs, args, out)
+}
+
+// the matches of every log, the next one taken from whichever log holds the oldest
+// (or newest) record. lines without a received time keep the order of the logs
+fn write_merged(
+    mut logs: Vec<LogMatches>,
+    args: &QueryArgs,
+    out: &mut impl Write,
+) -> Result<usize, QueryError> {
+    let mut heads = Vec::new();
+    for log in logs.iter_mut() {
+        heads.push(log.next_match()?);
+    }
+    let mut count = 0;
+    while !args.done(count) {
+        let mut next: Option<usize> = None;
+        for (index, head) in heads.iter().enumerate() {
+            let received = match head {
+                Some(head) => head.received,
+                None => continue,
+            };
+            let before = next.is_none_or(|next| {
+                let best = heads[next].as_ref().and_then(|head| head.received);
+                match args.newest {
+                    true => received > best,
+                    false => received < best,
+                }
+            });
+            if before {
+                next = Some(index);
+            }
+        }
+        let next = match next {
+            Some(next) => next,
+            None => break,
+        };
+        let head = std::mem::replace(&mut heads[next], logs[next].next_match()?);
+        writeln!(out, "{}", head.unwrap().line)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+// a line matching the filters, as it is written out
+struct Match {
+    received: Option<NaiveDateTime>,
+    line: String,
+}
+
+// matching lines of a log and its archives, oldest first or newest first
+struct LogMatches<'a> {
+    args: &'a QueryArgs,
+    pattern: Option<&'a LinePattern>,
+    // source of a split log
+    source: Option<String>,
+    // files left, the next one last
+    files: Vec<PathBuf>,
+    // lines of the file being read oldest first
+    lines: Option<io::Split<Box<dyn BufRead>>>,
+    // matches of the file being read newest first, files are read from the start so
+    // the last matches of a file are kept and handed out backwards
+    newest: VecDeque<Match>,
+}
+
+impl<'a> LogMatches<'a> {
+    fn new(
+        args: &'a QueryArgs,
+        pattern: Option<&'a LinePattern>,
+        source: Option<String>,
+        mut files: Vec<PathBuf>,
+    ) -> LogMatches<'a> {
+        if !args.newest {
+            files.reverse();
+        }
+        LogMatches {
+            args,
+            pattern,
+            source,
+            files,
+            lines: None,
+            newest: VecDeque::new(),
+        }
+    }
+
+    fn next_match(&mut self) -> Result<Option<Match>, QueryError> {
+        loop {
+            if let Some(found) = self.newest.pop_back() {
+                return Ok(Some(found));
+            }
+            if let Some(lines) = self.lines.as_mut() {
+                match lines.next() {
+                    Some(line) => {
+                        if let Some(found) = self.matches(&line?) {
+                            return Ok(Some(found));
+                        }
+                        continue;
+                    }
+                    None => self.lines = None,
+                }
+            }
+            let file = match self.files.pop() {
+                Some(file) => file,
+                None => return Ok(None),
+            };
+            // a file last written before the start of the range holds nothing newer
+            if let (Some(from), Some(modified)) = (self.args.from, modified(&file)) {
+                if modified < from {
+                    continue;
+                }
+            }
+            let reader = match open(&file) {
+                Ok(reader) => reader,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(format!("failed to open {}: {err}", file.display()).into()),
+            };
+            if !self.args.newest {
+                self.lines = Some(reader.split(b'\n'));
+                continue;
+            }
+            for line in reader.split(b'\n') {
+                if let Some(found) = self.matches(&line?) {
+                    self.newest.push_back(found);
+                    if self.args.limit.is_some_and(|limit| self.newest.len() > limit) {
+                        self.newest.pop_front();
+                    }
+                }
+            }
+        }
+    }
+
+    fn matches(&self, line: &[u8]) -> Option<Match> {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            return None;
+        }
+        // lines of another pattern or the message continued on a new line do not parse
+        let fields = match self.pattern {
+            Some(pattern) => pattern.fields(line)?,
+            None => json_fields(line)?,
+        };
+        let text_field = |name: &str| fields.get(name).and_then(Value::as_str);
+        let received = text_field("received")
+            .and_then(|received| NaiveDateTime::parse_from_str(received, RECEIVED_FORMAT).ok());
+        let mut level = text_field("level").and_then(|level| level.parse().ok());
+        // {l} writes critical as ERROR, such a line may hold either
+        if self.pattern.is_some_and(LinePattern::log_level) && level == Some(Severity::Error) {
+            level = Some(Severity::Critical);
+        }
+        let source = self.source.as_deref().or(text_field("source"));
+        if !self.args.matches(received, source, level, line) {
+            return None;
+        }
+        let line = match (self.args.json, self.pattern) {
+            (true, Some(_)) => Value::Object(fields.clone()).to_string(),
+            _ => line.to_string(),
+        };
+        Some(Match { received, line })
+    }
+}
+
+fn json_fields(line: &str) -> Option<Map<String, Value>> {
+    match serde_json::from_str(line) {
+        Ok(Value::Object(object)) => Some(object),
+        _ => None,
+    }
+}
+
+fn query_database(
+    sink: &Sink,
+    args: &QueryArgs,
+    out: &mut impl Write,
+) -> Result<usize, QueryError> {
+    let path = &sink.log_path;
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|err| format!("failed to open {}: {err}", path.display()))?;
+
+    let mut conditions = Vec::new();
+    let mut values: Vec<SqlValue> = Vec::new();
+    // the database holds utc times
+    let utc = |time: NaiveDateTime| {
+        Local
+            .from_local_datetime(&time)
+            .earliest()
+            .map(|time| sqlite_sink::format_time(&time))
+    };
+    if let Some(from) = args.from.and_then(utc) {
+        conditions.push("received >= ?");
+        values.push(SqlValue::Text(from));
+    }
+    if let Some(to) = args.to.and_then(utc) {
+        conditions.push("received <= ?");
+        values.push(SqlValue::Text(to));
+    }
+    if let Some(source) = &args.source {
+        conditions.push("source = ?");
+        values.push(SqlValue::Text(source.clone()));
+    }
+    let mut sql = String::from(
+        "SELECT received, plc_time, source, area, line, src, listener, level, seq, message, fields FROM records",
+    );
+    if !conditions.is_empty() {
+        sql.push_str(" WHERE ");
+        sql.push_str(&conditions.join(" AND "));
+    }
+    match args.newest {
+        true => sql.push_str(" ORDER BY received DESC, id DESC"),
+        false => sql.push_str(" ORDER BY received, id"),
+    }
+
+    let mut stmt = conn.prepare(&sql)?;
+    let columns: Vec<String> = stmt
+        .column_names()
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+    let mut rows = stmt.query(params_from_iter(values))?;
+    let mut count = 0;
+    while let Some(row) = rows.next()? {
+        let mut object = Map::new();
+        for (index, column) in columns.iter().enumerate() {
+            let value = match row.get::<_, SqlValue>(index)? {
+                SqlValue::Null => continue,
+                SqlValue::Integer(value) => Value::from(value),
+                SqlValue::Real(value) => Value::from(value),
+                // times back in local time, like the logs
+                SqlValue::Text(value) if column == "received" || column == "plc_time" => {
+                    Value::String(local_time(&value))
+                }
+                SqlValue::Text(value) if column == "fields" => {
+                    serde_json::from_str(&value).unwrap_or(Value::String(value))
+                }
+                SqlValue::Text(value) => Value::String(value),
+                SqlValue::Blob(_) => continue,
+            };
+            object.insert(column.clone(), value);
+        }
+        let text_field = |name: &str| object.get(name).and_then(Value::as_str).unwrap_or_default();
+        let level = text_field("level").parse().ok();
+        let line = match object.get("fields") {
+            Some(fields) => format!(
+                "{} | {} | {} | {} | {fields}",
+                text_field("received"),
+                text_field("source"),
+                text_field("level"),
+                text_field("message")
+            ),
+            None => format!(
+                "{} | {} | {} | {}",
+                text_field("received"),
+                text_field("source"),
+                text_field("level"),
+                text_field("message")
+            ),
+        };
+        // time and source are filtered by the query already
+        if !args.matches(None, None, level, &line) {
+            continue;
+        }
+        if args.done(count) {
+            break;
+        }
+        match args.json {
+            true => writeln!(out, "{}", Value::Object(object))?,
+            false => writeln!(out, "{line}")?,
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn local_time(utc: &str) -> String {
+    match NaiveDateTime::parse_from_str(utc, TIME_FORMAT) {
+        Ok(time) => Utc
+            .from_utc_datetime(&time)
+            .with_timezone(&Local)
+            .format(RECEIVED_FORMAT)
+            .to_string(),
+        Err(_) => utc.to_string(),
+    }
+}
+
+// archives oldest first, by the naming of the rollers: the fixed window roller numbers
+// them from 1 (newest) up, time based rotation stamps them with the date of the period
+// and a counter for more than one archive in a period (2024-03-01, 2024-03-01.1, ..)
+pub fn archives_in_order(archive_pattern: &Path) -> Vec<PathBuf> {
+    let file_pattern = archive_pattern
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let (prefix, suffix) = match file_pattern.split_once("{}") {
+        Some(parts) => parts,
+        None => return Vec::new(),
+    };
+
+    let mut numbered = Vec::new();
+    let mut dated = Vec::new();
+    for (path, _) in archive_files(archive_pattern) {
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let stamp = &name[prefix.len()..name.len() - suffix.len()];
+        if let Ok(index) = stamp.parse::<u32>() {
+            numbered.push((Reverse(index), path));
+            continue;
+        }
+        let (label, counter) = match stamp.rsplit_once('.') {
+            Some((label, counter)) => match counter.parse::<u32>() {
+                Ok(counter) => (label.to_string(), counter),
+                Err(_) => (stamp.to_string(), 0),
+            },
+            None => (stamp.to_string(), 0),
+        };
+        dated.push(((label, counter), path));
+    }
+    numbered.sort();
+    dated.sort();
+    // numbered archives are left from before time based rotation was turned on
+    numbered
+        .into_iter()
+        .map(|(_, path)| path)
+        .chain(dated.into_iter().map(|(_, path)| path))
+        .collect()
+}
+
+fn modified(path: &Path) -> Option<NaiveDateTime> {
+    let modified: SystemTime = path.metadata().ok()?.modified().ok()?;
+    Some(DateTime::<Local>::from(modified).naive_local())
+}
+
+fn open(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    let reader: Box<dyn Read> = match path.extension().is_some_and(|extension| extension == "gz") {
+        true => Box::new(GzDecoder::new(file)),
+        false => Box::new(file),
+    };
+    Ok(Box::new(BufReader::new(reader)))
+}
+
+// plc_log_pattern turned into a regex, so the record fields can be read back from the lines
+struct LinePattern {
+    regex: Regex,
+    // field name of each capture group, f0, f1, ..
+    names: Vec<String>,
+}
+
+impl LinePattern {
+    fn new(pattern: &str) -> Result<LinePattern, String> {
+        let mut regex = String::from("^");
+        let mut names: Vec<String> = Vec::new();
+        let mut rest = pattern;
+        while let Some(start) = rest.find('{') {
+            regex.push_str(&regex::escape(&rest[..start]));
+            let end = match closing_brace(&rest[start..]) {
+                Some(end) => start + end,
+                None => {
+                    rest = &rest[start..];
+                    break;
+                }
+            };
+            let spec = &rest[start + 1..end];
+            rest = &rest[end + 1..];
+
+            let name = spec.split(['(', ':']).next().unwrap_or_default();
+            let field = match name {
+                "X" | "mdc" => spec
+                    .split_once('(')
+                    .and_then(|(_, arg)| arg.split(')').next())
+                    .map(str::to_string),
+                // the log level, read as the level unless {X(level)} is there as well
+                "l" | "level" => Some(String::from("l")),
+                "m" | "message" => Some(String::from("message")),
+                // lines are split on the newline already
+                "n" => continue,
+                _ => None,
+            };
+            match field {
+                Some(field) if !names.contains(&field) => {
+                    regex.push_str(&format!("(?P<f{}>.*?)", names.len()));
+                    names.push(field);
+                }
+                _ => regex.push_str(".*?"),
+            }
+        }
+        regex.push_str(&regex::escape(rest));
+        regex.push('$');
+        let regex = Regex::new(&regex).map_err(|err| err.to_string())?;
+        Ok(LinePattern { regex, names })
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.names.iter().any(|field| field == name) || (name == "level" && self.log_level())
+    }
+
+    // the level of the lines is the log level of {l}, error for critical records as well
+    fn log_level(&self) -> bool {
+        self.names.iter().any(|field| field == "l")
+            && !self.names.iter().any(|field| field == "level")
+    }
+
+    fn fields(&self, line: &str) -> Option<Map<String, Value>> {
+        let captures = self.regex.captures(line)?;
+        let mut fields = Map::new();
+        for (index, name) in self.names.iter().enumerate() {
+            // {l} is padded by some patterns, fields the record did not have are left out
+            // like in the json sinks
+            let value = captures.name(&format!("f{index}")).map(|value| value.as_str().trim());
+            if let Some(value) = value.filter(|value| !value.is_empty()) {
+                fields.insert(name.clone(), Value::String(value.to_string()));
+            }
+        }
+        if let Some(level) = fields.remove("l") {
+            if !fields.contains_key("level") {
+                fields.insert(String::from("level"), level);
+            }
+        }
+        Some(fields)
+    }
+}
+
+// end of the {..} starting the text, braces nest in patterns like {h({l})}
+fn closing_brace(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("plclogger-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn fields(pattern: &LinePattern, line: &str) -> Vec<(String, String)> {
+        pattern
+            .fields(line)
+            .unwrap()
+            .into_iter()
+            .map(|(name, value)| (name, value.as_str().unwrap().to_string()))
+            .collect()
+    }
+
+    fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        let mut pairs: Vec<(String, String)> = expected
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        pairs.sort();
+        pairs
+    }
+
+    #[test]
+    fn line_pattern_fields() {
+        let pattern = LinePattern::new("{X(received)} | {X(source)} | {X(src)} | {m}{n}").unwrap();
+        assert!(pattern.has("received") && pattern.has("source"));
+        assert!(!pattern.has("level"));
+        let line = "2024-03-01 10:00:00.000000 | press | 10.0.1.1:4000 | motor on | ok";
+        assert_eq!(
+            fields(&pattern, line),
+            pairs(&[
+                ("received", "2024-03-01 10:00:00.000000"),
+                ("source", "press"),
+                ("src", "10.0.1.1:4000"),
+                ("message", "motor on | ok"),
+            ])
+        );
+        // a continued message or a line of another pattern
+        assert!(pattern.fields("motor on").is_none());
+    }
+
+    #[test]
+    fn line_pattern_empty_and_unknown_fields() {
+        let pattern = LinePattern::new("[{d}] {X(seq)}|{mdc(area)}|{m}").unwrap();
+        assert_eq!(
+            fields(&pattern, "[2024] |hall 1|start"),
+            pairs(&[("area", "hall 1"), ("message", "start")])
+        );
+        // regex characters of the pattern are taken literally
+        let pattern = LinePattern::new("({X(source)}) *{m}").unwrap();
+        assert_eq!(
+            fields(&pattern, "(press) *start"),
+            pairs(&[("source", "press"), ("message", "start")])
+        );
+        assert!(pattern.fields("press start").is_none());
+    }
+
+    #[test]
+    fn line_pattern_levels() {
+        let pattern = LinePattern::new("{X(received)} {l:5.5} {m}").unwrap();
+        assert!(pattern.has("level") && pattern.log_level());
+        assert_eq!(
+            fields(&pattern, "2024-03-01 ERROR boom"),
+            pairs(&[
+                ("received", "2024-03-01"),
+                ("level", "ERROR"),
+                ("message", "boom")
+            ])
+        );
+        // the record level is read when both are there
+        let pattern = LinePattern::new("{l} {X(level)} {m}").unwrap();
+        assert!(pattern.has("level") && !pattern.log_level());
+        assert_eq!(
+            fields(&pattern, "ERROR critical boom"),
+            pairs(&[("level", "critical"), ("message", "boom")])
+        );
+    }
+
+    #[test]
+    fn unclosed_braces() {
+        assert_eq!(closing_brace("{h({l})} {m}"), Some(7));
+        assert_eq!(closing_brace("{h({l}"), None);
+        let pattern = LinePattern::new("{m} {X(source").unwrap();
+        assert_eq!(
+            fields(&pattern, "start {X(source"),
+            pairs(&[("message", "start")])
+        );
+    }
+
+    #[test]
+    fn archive_order() {
+        let dir = test_dir("archive-order");
+        let names = [
+            "plclog_2024-03-01.10.gz",
+            "plclog_1.gz",
+            "plclog_2024-03-01.gz",
+            "plclog_10.gz",
+            "plclog_2024-02-28_2200.gz",
+            "plclog_2.gz",
+            "plclog_2024-03-01.2.gz",
+            "plclog_.gz",
+            "other_1.gz",
+        ];
+        for name in names {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let archives: Vec<String> = archives_in_order(&dir.join("plclog_{}.gz"))
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            archives,
+            [
+                "plclog_10.gz",
+                "plclog_2.gz",
+                "plclog_1.gz",
+                "plclog_2024-02-28_2200.gz",
+                "plclog_2024-03-01.gz",
+                "plclog_2024-03-01.2.gz",
+                "plclog_2024-03-01.10.gz",
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // two split logs, press with an archive, written at interleaved times
+    fn split_logs(dir: &Path) -> Vec<(Option<String>, Vec<PathBuf>)> {
+        let line = |second: u32, level: &str| {
+            format!("2024-03-01 10:00:0{second}.000000 | {level} | message {second}\n")
+        };
+        fs::write(dir.join("press_1.log"), line(1, "INFO") + &line(3, "ERROR")).unwrap();
+        fs::write(dir.join("press.log"), line(5, "INFO")).unwrap();
+        fs::write(
+            dir.join("robot.log"),
+            line(2, "INFO") + &line(4, "WARN") + &line(6, "INFO"),
+        )
+        .unwrap();
+        vec![
+            (
+                Some(String::from("press")),
+                vec![dir.join("press_1.log"), dir.join("press.log")],
+            ),
+            (Some(String::from("robot")), vec![dir.join("robot.log")]),
+        ]
+    }
+
+    fn merged(logs: &[(Option<String>, Vec<PathBuf>)], args: &QueryArgs) -> Vec<String> {
+        let pattern = LinePattern::new("{X(received)} | {l} | {m}{n}").unwrap();
+        let logs = logs
+            .iter()
+            .map(|(source, files)| {
+                LogMatches::new(args, Some(&pattern), source.clone(), files.clone())
+            })
+            .collect();
+        let mut out = Vec::new();
+        let count = write_merged(logs, args, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let messages: Vec<String> = out
+            .lines()
+            .map(|line| line.rsplit(" | ").next().unwrap().to_string())
+            .collect();
+        assert_eq!(messages.len(), count);
+        messages
+    }
+
+    #[test]
+    fn split_logs_merged_by_time() {
+        let dir = test_dir("merged");
+        let logs = split_logs(&dir);
+        let all: Vec<String> = (1..=6).map(|second| format!("message {second}")).collect();
+        assert_eq!(merged(&logs, &QueryArgs::default()), all);
+
+        let newest = QueryArgs {
+            newest: true,
+            limit: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(
+            merged(&logs, &newest),
+            ["message 6", "message 5", "message 4"]
+        );
+        let oldest = QueryArgs {
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(merged(&logs, &oldest), ["message 1", "message 2"]);
+        let press = QueryArgs {
+            source: Some(String::from("press")),
+            newest: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            merged(&logs, &press),
+            ["message 5", "message 3", "message 1"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn critical_from_the_log_level() {
+        let dir = test_dir("critical");
+        let logs = split_logs(&dir);
+        let level = |level| QueryArgs {
+            level: Some(level),
+            ..Default::default()
+        };
+        // {l} holds ERROR for critical records too
+        assert_eq!(merged(&logs, &level(Severity::Critical)), ["message 3"]);
+        assert_eq!(merged(&logs, &level(Severity::Error)), ["message 3"]);
+        assert_eq!(
+            merged(&logs, &level(Severity::Warn)),
+            ["message 3", "message 4"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
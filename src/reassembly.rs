@@ -0,0 +1,414 @@
+use chrono::{DateTime, Local};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+// plcs with small send buffers split long messages into datagrams starting with
+// "<id>:<index>:<count>|", where id is up to 16 letters or digits chosen by the plc,
+// index runs from 1 to count and count is at most MAX_FRAGMENTS
+pub const MAX_FRAGMENTS: usize = 256;
+
+// incomplete messages and their bytes held at most, per sender address and for all
+// senders of a socket. the oldest message is discarded to make room
+const LIMITS: Limits = Limits {
+    sender_messages: 64,
+    sender_bytes: 16 * 1024 * 1024,
+    messages: 4096,
+    bytes: 64 * 1024 * 1024,
+};
+
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    sender_messages: usize,
+    sender_bytes: usize,
+    messages: usize,
+    bytes: usize,
+}
+
+// a message whose fragments are still arriving
+struct Pending {
+    parts: Vec<Option<Vec<u8>>>,
+    missing: usize,
+    bytes: usize,
+    received: DateTime<Local>,
+    started: Instant,
+}
+
+impl Pending {
+    fn new(count: usize, received: DateTime<Local>) -> Pending {
+        Pending {
+            parts: vec![None; count],
+            missing: count,
+            bytes: 0,
+            received,
+            started: Instant::now(),
+        }
+    }
+}
+
+// a message ready to be logged
+pub struct Message {
+    pub data: Vec<u8>,
+    // arrival of the first fragment
+    pub received: DateTime<Local>,
+    pub fragments: usize,
+}
+
+// a message that was given up on because its fragments did not all arrive in time,
+// or to stay within the limits
+pub struct Expired {
+    pub src: SocketAddr,
+    pub id: String,
+    pub missing: usize,
+    pub count: usize,
+}
+
+// puts fragmented messages back together, one per udp socket
+pub struct Reassembler {
+    timeout: Duration,
+    limits: Limits,
+    pending: HashMap<(SocketAddr, String), Pending>,
+    // messages and bytes held per sender address
+    senders: HashMap<IpAddr, (usize, usize)>,
+    bytes: usize,
+    // discarded to stay within the limits since the last call to evicted
+    evicted: Vec<Expired>,
+}
+
+impl Reassembler {
+    pub fn new(timeout: Duration) -> Reassembler {
+        Reassembler {
+            timeout,
+            limits: LIMITS,
+            pending: HashMap::new(),
+            senders: HashMap::new(),
+            bytes: 0,
+            evicted: Vec::new(),
+        }
+    }
+
+    // the complete message once the datagram completes one, datagrams without a
+    // fragment header are complete by themselves
+    pub fn push(
+        &mut self,
+        src: SocketAddr,
+        data: &[u8],
+        received: DateTime<Local>,
+    ) -> Option<Message> {
+        let (id, index, count, part) = match parse_header(data) {
+            Some(header) => header,
+            None => ("", 1, 1, data),
+        };
+        if count == 1 {
+            return Some(Message {
+                data: part.to_vec(),
+                received,
+                fragments: 1,
+            });
+        }
+
+        let key = (src, id.to_string());
+        // a reused id with another count starts a new message
+        if self.pending.get(&key).is_some_and(|pending| pending.parts.len() != count) {
+            self.remove(&key);
+        }
+        if !self.pending.contains_key(&key) {
+            self.pending.insert(key.clone(), Pending::new(count, received));
+            self.senders.entry(src.ip()).or_default().0 += 1;
+        }
+
+        // repeated fragments are ignored
+        let pending = self.pending.get_mut(&key).unwrap();
+        let slot = &mut pending.parts[index - 1];
+        if slot.is_none() {
+            *slot = Some(part.to_vec());
+            pending.missing -= 1;
+            pending.bytes += part.len();
+            self.senders.entry(src.ip()).or_default().1 += part.len();
+            self.bytes += part.len();
+        }
+        if pending.missing > 0 {
+            self.limit(src.ip());
+            return None;
+        }
+
+        let pending = self.remove(&key).unwrap();
+        Some(Message {
+            data: pending.parts.into_iter().flatten().flatten().collect(),
+            received: pending.received,
+            fragments: count,
+        })
+    }
+
+    // drop messages still missing fragments after the timeout
+    pub fn expire(&mut self) -> Vec<Expired> {
+        let timeout = self.timeout;
+        let keys: Vec<(SocketAddr, String)> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.started.elapsed() >= timeout)
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.into_iter().filter_map(|key| self.discard(key)).collect()
+    }
+
+    // messages discarded to stay within the limits since the last call
+    pub fn evicted(&mut self) -> Vec<Expired> {
+        std::mem::take(&mut self.evicted)
+    }
+
+    // discard the oldest messages of the sender while it holds too much, then the
+    // oldest of any sender
+    fn limit(&mut self, ip: IpAddr) {
+        loop {
+            let (messages, bytes) = self.senders.get(&ip).copied().unwrap_or_default();
+            let sender = messages > self.limits.sender_messages || bytes > self.limits.sender_bytes;
+            let all = self.pending.len() > self.limits.messages || self.bytes > self.limits.bytes;
+            if !sender && !all {
+                return;
+            }
+            let oldest = self
+                .pending
+                .iter()
+                .filter(|((src, _), _)| !sender || src.ip() == ip)
+                .min_by_key(|(_, pending)| pending.started)
+                .map(|(key, _)| key.clone());
+            match oldest.and_then(|key| self.discard(key)) {
+                Some(evicted) => self.evicted.push(evicted),
+                None => return,
+            }
+        }
+    }
+
+    fn discard(&mut self, key: (SocketAddr, String)) -> Option<Expired> {
+        let pending = self.remove(&key)?;
+        let (src, id) = key;
+        Some(Expired {
+            src,
+            id,
+            missing: pending.missing,
+            count: pending.parts.len(),
+        })
+    }
+
+    fn remove(&mut self, key: &(SocketAddr, String)) -> Option<Pending> {
+        let pending = self.pending.remove(key)?;
+        let ip = key.0.ip();
+        if let Some((messages, bytes)) = self.senders.get_mut(&ip) {
+            *messages -= 1;
+            *bytes -= pending.bytes;
+            if *messages == 0 {
+                self.senders.remove(&ip);
+            }
+        }
+        self.bytes -= pending.bytes;
+        Some(pending)
+    }
+}
+
+fn parse_header(data: &[u8]) -> Option<(&str, usize, usize, &[u8])> {
+    // the header is short, only its first bytes are searched for the separator
+    let end = data.iter().take(40).position(|b| *b == b'|')?;
+    let header = std::str::from_utf8(&data[..end]).ok()?;
+    let mut parts = header.split(':');
+    let id = parts.next()?;
+    let index: usize = parts.next()?.parse().ok()?;
+    let count: usize = parts.next()?.parse().ok()?;
+    if parts.next().is_some()
+        || id.is_empty()
+        || id.len() > 16
+        || !id.bytes().all(|b| b.is_ascii_alphanumeric())
+        || !(1..=MAX_FRAGMENTS).contains(&count)
+        || !(1..=count).contains(&index)
+    {
+        return None;
+    }
+    Some((id, index, count, &data[end + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn src(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], port))
+    }
+
+    fn reassembler() -> Reassembler {
+        Reassembler::new(Duration::from_secs(60))
+    }
+
+    // data of the completed message
+    fn push(reassembler: &mut Reassembler, port: u16, data: &str) -> Option<Vec<u8>> {
+        reassembler
+            .push(src(port), data.as_bytes(), Local::now())
+            .map(|message| message.data)
+    }
+
+    #[test]
+    fn datagram_without_header_is_complete() {
+        let message = reassembler()
+            .push(src(1), b"belt stopped", Local::now())
+            .unwrap();
+        assert_eq!(message.data, b"belt stopped");
+        assert_eq!(message.fragments, 1);
+
+        let data = push(&mut reassembler(), 1, "a1:1:1|belt stopped");
+        assert_eq!(data.unwrap(), b"belt stopped");
+    }
+
+    #[test]
+    fn fragments_are_joined_in_index_order() {
+        let mut reassembler = reassembler();
+        let first = Local::now();
+        assert!(reassembler.push(src(1), b"m7:3:3|!", first).is_none());
+        assert!(push(&mut reassembler, 1, "m7:1:3|belt ").is_none());
+        let message = reassembler
+            .push(src(1), b"m7:2:3|stopped", Local::now())
+            .unwrap();
+        assert_eq!(message.data, b"belt stopped!");
+        assert_eq!(message.fragments, 3);
+        assert_eq!(message.received, first);
+        assert!(reassembler.expire().is_empty());
+    }
+
+    #[test]
+    fn repeated_fragment_is_ignored() {
+        let mut reassembler = reassembler();
+        assert!(push(&mut reassembler, 1, "m7:1:2|belt ").is_none());
+        assert!(push(&mut reassembler, 1, "m7:1:2|conveyor ").is_none());
+        let data = push(&mut reassembler, 1, "m7:2:2|stopped");
+        assert_eq!(data.unwrap(), b"belt stopped");
+    }
+
+    #[test]
+    fn reused_id_with_another_count_starts_over() {
+        let mut reassembler = reassembler();
+        assert!(push(&mut reassembler, 1, "m7:1:3|old ").is_none());
+        assert!(push(&mut reassembler, 1, "m7:1:2|belt ").is_none());
+        let data = push(&mut reassembler, 1, "m7:2:2|stopped");
+        assert_eq!(data.unwrap(), b"belt stopped");
+        assert!(reassembler.expire().is_empty());
+    }
+
+    #[test]
+    fn senders_do_not_share_ids() {
+        let mut reassembler = reassembler();
+        assert!(push(&mut reassembler, 1, "m7:1:2|belt ").is_none());
+        assert!(push(&mut reassembler, 2, "m7:2:2|running").is_none());
+        let data = push(&mut reassembler, 1, "m7:2:2|stopped");
+        assert_eq!(data.unwrap(), b"belt stopped");
+    }
+
+    #[test]
+    fn invalid_header_is_part_of_the_message() {
+        let too_many = format!("m7:1:{}|x", MAX_FRAGMENTS + 1);
+        let late_separator = format!("m7:1:2{}|x", " ".repeat(40));
+        for data in [
+            "m7:0:2|x",
+            "m7:3:2|x",
+            "m7:1:0|x",
+            too_many.as_str(),
+            "m-7:1:2|x",
+            "abcdefghijklmnopq:1:2|x",
+            ":1:2|x",
+            "m7:1:2:3|x",
+            "m7:a:2|x",
+            late_separator.as_str(),
+        ] {
+            let message = push(&mut reassembler(), 1, data);
+            assert_eq!(message, Some(data.as_bytes().to_vec()), "{data}");
+        }
+    }
+
+    fn limited(limits: Limits) -> Reassembler {
+        let mut reassembler = reassembler();
+        reassembler.limits = limits;
+        reassembler
+    }
+
+    fn evicted_ids(reassembler: &mut Reassembler) -> Vec<String> {
+        reassembler
+            .evicted()
+            .into_iter()
+            .map(|evicted| evicted.id)
+            .collect()
+    }
+
+    #[test]
+    fn oldest_message_of_a_sender_is_evicted() {
+        let mut reassembler = limited(Limits {
+            sender_messages: 2,
+            ..LIMITS
+        });
+        assert!(push(&mut reassembler, 1, "a:1:2|x").is_none());
+        assert!(push(&mut reassembler, 1, "b:1:2|x").is_none());
+        // another sender has room of its own
+        let other = SocketAddr::from(([10, 0, 0, 2], 1));
+        assert!(reassembler.push(other, b"z:1:2|x", Local::now()).is_none());
+        assert!(evicted_ids(&mut reassembler).is_empty());
+        // other ports of the same address count towards the same sender
+        assert!(push(&mut reassembler, 3, "c:1:2|x").is_none());
+        assert_eq!(evicted_ids(&mut reassembler), ["a"]);
+        assert!(push(&mut reassembler, 1, "a:2:2|y").is_none());
+        assert_eq!(evicted_ids(&mut reassembler), ["b"]);
+        let message = reassembler.push(other, b"z:2:2|y", Local::now()).unwrap();
+        assert_eq!(message.data, b"xy");
+    }
+
+    #[test]
+    fn bytes_of_a_sender_are_limited() {
+        let mut reassembler = limited(Limits {
+            sender_bytes: 10,
+            ..LIMITS
+        });
+        assert!(push(&mut reassembler, 1, "a:1:3|12345").is_none());
+        assert!(push(&mut reassembler, 1, "b:1:4|12345").is_none());
+        assert!(evicted_ids(&mut reassembler).is_empty());
+        assert!(push(&mut reassembler, 1, "b:2:4|6").is_none());
+        assert_eq!(evicted_ids(&mut reassembler), ["a"]);
+        // a message too long on its own is given up on as well
+        assert!(push(&mut reassembler, 1, "b:3:4|7890123456").is_none());
+        assert_eq!(evicted_ids(&mut reassembler), ["b"]);
+        assert!(reassembler.pending.is_empty());
+        assert!(reassembler.senders.is_empty());
+        assert_eq!(reassembler.bytes, 0);
+    }
+
+    #[test]
+    fn all_senders_are_limited() {
+        let mut reassembler = limited(Limits {
+            messages: 2,
+            bytes: 8,
+            ..LIMITS
+        });
+        assert!(push(&mut reassembler, 1, "a:1:2|x").is_none());
+        let second = SocketAddr::from(([10, 0, 0, 2], 1));
+        assert!(reassembler.push(second, b"b:1:2|x", Local::now()).is_none());
+        let third = SocketAddr::from(([10, 0, 0, 3], 1));
+        assert!(reassembler.push(third, b"c:1:2|x", Local::now()).is_none());
+        assert_eq!(evicted_ids(&mut reassembler), ["a"]);
+        assert!(reassembler
+            .push(third, b"d:1:2|12345678", Local::now())
+            .is_none());
+        assert_eq!(evicted_ids(&mut reassembler), ["b", "c"]);
+        assert_eq!(reassembler.pending.len(), 1);
+        assert_eq!(reassembler.bytes, 8);
+    }
+
+    #[test]
+    fn incomplete_messages_expire() {
+        let mut reassembler = Reassembler::new(Duration::ZERO);
+        assert!(push(&mut reassembler, 1, "m7:1:3|belt ").is_none());
+        let expired = reassembler.expire();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].src, src(1));
+        assert_eq!(expired[0].id, "m7");
+        assert_eq!(expired[0].missing, 2);
+        assert_eq!(expired[0].count, 3);
+        assert!(reassembler.expire().is_empty());
+
+        // late fragments start a new message
+        assert!(push(&mut reassembler, 1, "m7:2:3|stopped").is_none());
+    }
+}
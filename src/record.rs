@@ -0,0 +1,135 @@
+use chrono::{DateTime, FixedOffset, Local};
+use serde_json::{Map, Value};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+
+use crate::parsers::{self, FieldValue};
+use crate::sequence::SequenceEvent;
+use crate::severity::Severity;
+use crate::sources::Source;
+use crate::syslog::SyslogHeader;
+
+// format used for the receive time of a record
+pub const RECEIVED_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.6f";
+
+// confirmation sent back to the plc once its message is durably written
+#[derive(Debug, Clone)]
+pub struct Ack {
+    pub socket: Arc<UdpSocket>,
+    // address as seen by the socket, which may be ipv4-mapped
+    pub dst: SocketAddr,
+    pub seq: u64,
+}
+
+impl Ack {
+    pub fn send(&self) -> io::Result<()> {
+        self.socket
+            .send_to(format!("ACK {}", self.seq).as_bytes(), self.dst)
+            .map(|_| ())
+    }
+}
+
+// a decoded plc message, passed from the decode workers to the writer
+#[derive(Debug, Clone)]
+pub struct PlcRecord {
+    pub received: DateTime<Local>,
+    // time the plc sent the message at, as read from the message
+    pub plc_time: Option<DateTime<FixedOffset>>,
+    pub src: SocketAddr,
+    pub source: Arc<Source>,
+    pub listener: Arc<str>,
+    // name of the sink the record is written to
+    pub sink: Arc<str>,
+    pub payload: String,
+    pub len: usize,
+    // decided by the severity rules
+    pub level: Severity,
+    // set for messages received on syslog listeners
+    pub syslog: Option<SyslogHeader>,
+    // sequence number sent by the plc
+    pub seq: Option<u64>,
+    // fields parsed from the payload
+    pub parsed: Vec<(String, FieldValue)>,
+    pub ack: Option<Ack>,
+    // irregularity noticed in the sequence number of the message
+    pub sequence_event: Option<SequenceEvent>,
+}
+
+impl PlcRecord {
+    // fields of the record that can be used in the plc log pattern as {X(<name>)}
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let syslog = self.syslog.clone().unwrap_or_default();
+        let (facility, severity) = match &self.syslog {
+            Some(syslog) => (syslog.facility_name(), syslog.severity_name()),
+            None => ("", ""),
+        };
+        vec![
+            ("received", self.received.format(RECEIVED_FORMAT).to_string()),
+            ("plc_time", self.plc_time.map(|time| time.with_timezone(&Local).format(RECEIVED_FORMAT).to_string()).unwrap_or_default()),
+            ("clock_skew_ms", self.clock_skew_ms().map(|skew| skew.to_string()).unwrap_or_default()),
+            ("src", self.src.to_string()),
+            ("src_ip", self.src.ip().to_string()),
+            ("src_port", self.src.port().to_string()),
+            ("source", self.source.name.clone()),
+            ("area", self.source.area.clone()),
+            ("line", self.source.line.clone()),
+            ("listener", self.listener.to_string()),
+            ("sink", self.sink.to_string()),
+            ("level", self.level.to_string()),
+            ("len", self.len.to_string()),
+            ("seq", self.seq.map(|seq| seq.to_string()).unwrap_or_default()),
+            ("facility", facility.to_string()),
+            ("severity", severity.to_string()),
+            ("timestamp", syslog.timestamp),
+            ("hostname", syslog.hostname),
+            ("app_name", syslog.app_name),
+            ("procid", syslog.procid),
+            ("msgid", syslog.msgid),
+            ("structured_data", syslog.structured_data),
+        ]
+    }
+
+    // how far the plc clock is ahead of the logger, negative when behind
+    pub fn clock_skew_ms(&self) -> Option<i64> {
+        self.plc_time
+            .map(|time| time.signed_duration_since(self.received).num_milliseconds())
+    }
+
+    // parsed fields as key=value pairs, available in the pattern as {X(fields)}
+    pub fn parsed_summary(&self) -> String {
+        parsers::summary(&self.parsed)
+    }
+
+    // the record as one json object for the structured sinks, parsed fields keep their types
+    pub fn to_json(&self) -> String {
+        let mut object = Map::new();
+        for (key, value) in self.fields() {
+            if !value.is_empty() {
+                object.insert(key.to_string(), Value::String(value));
+            }
+        }
+        object.insert(String::from("len"), Value::from(self.len));
+        if let Some(seq) = self.seq {
+            object.insert(String::from("seq"), Value::from(seq));
+        }
+        if let Some(skew) = self.clock_skew_ms() {
+            object.insert(String::from("clock_skew_ms"), Value::from(skew));
+        }
+        object.insert(String::from("message"), Value::String(self.payload.clone()));
+        if !self.parsed.is_empty() {
+            object.insert(String::from("fields"), self.parsed_json());
+        }
+        Value::Object(object).to_string()
+    }
+
+    // parsed fields as a json object
+    pub fn parsed_json(&self) -> Value {
+        let parsed = self
+            .parsed
+            .iter()
+            .map(|(key, value)| (key.clone(), serde_json::to_value(value).unwrap_or(Value::Null)))
+            .collect();
+        Value::Object(parsed)
+    }
+}
@@ -0,0 +1,154 @@
+use log::{error, info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::rotation::archive_files;
+use crate::sqlite_sink;
+
+// limits applied to the archives on top of log_history_to_keep, 0 disables a limit
+#[derive(Debug, Clone)]
+pub struct Retention {
+    pub max_age_days: u64,
+    pub max_total_mb: u64,
+    pub min_free_mb: u64,
+    pub check_secs: u64,
+}
+
+impl Retention {
+    pub fn is_enabled(&self) -> bool {
+        self.max_age_days > 0 || self.max_total_mb > 0 || self.min_free_mb > 0
+    }
+}
+
+struct Archive {
+    path: PathBuf,
+    len: u64,
+    modified: SystemTime,
+}
+
+// periodically prune the archives matching any of the patterns and the rows of the
+// databases, oldest first
+pub fn spawn_pruner(retention: Retention, archive_patterns: Vec<PathBuf>, databases: Vec<PathBuf>) {
+    if !retention.is_enabled() {
+        return;
+    }
+
+    info!(
+        "Archive retention - max age: {} days, max total: {} mb, min free: {} mb (0 = off)",
+        retention.max_age_days, retention.max_total_mb, retention.min_free_mb
+    );
+
+    thread::spawn(move || loop {
+        prune(&retention, &archive_patterns);
+        for database in &databases {
+            sqlite_sink::prune(&retention, database);
+        }
+        thread::sleep(Duration::from_secs(retention.check_secs));
+    });
+}
+
+pub fn prune(retention: &Retention, archive_patterns: &[PathBuf]) {
+    let mut archives: Vec<Archive> = archive_patterns
+        .iter()
+        .flat_map(|pattern| archive_files(pattern))
+        .map(|(path, metadata)| Archive {
+            path,
+            len: metadata.len(),
+            modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+        })
+        .collect();
+    archives.sort_by_key(|archive| archive.modified);
+    archives.reverse();
+
+    // archives are popped from the back, which holds the oldest
+    if retention.max_age_days > 0 {
+        let max_age = Duration::from_secs(retention.max_age_days * 24 * 60 * 60);
+        while let Some(oldest) = archives.last() {
+            let age = oldest.modified.elapsed().unwrap_or_default();
+            if age <= max_age {
+                break;
+            }
+            let oldest = archives.pop().unwrap();
+            remove(&oldest, &format!("older than {} days", retention.max_age_days));
+        }
+    }
+
+    if retention.max_total_mb > 0 {
+        let max_total = retention.max_total_mb * 1024 * 1024;
+        let mut total: u64 = archives.iter().map(|archive| archive.len).sum();
+        while total > max_total {
+            let oldest = match archives.pop() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            total -= oldest.len;
+            remove(&oldest, &format!("archives above {} mb", retention.max_total_mb));
+        }
+    }
+
+    if retention.min_free_mb > 0 {
+        let min_free = retention.min_free_mb * 1024 * 1024;
+        for pattern in archive_patterns {
+            let dir = pattern.parent().unwrap_or(Path::new("."));
+            while let Some(free) = free_space(dir) {
+                if free >= min_free {
+                    break;
+                }
+                // oldest archive left in the directory running short
+                let oldest = archives.iter().rposition(|archive| archive.path.starts_with(dir));
+                match oldest {
+                    Some(index) => {
+                        let oldest = archives.remove(index);
+                        remove(&oldest, &format!("less than {} mb free", retention.min_free_mb));
+                    }
+                    None => {
+                        warn!(
+                            "Less than {} mb free for {}, no archives left to prune",
+                            retention.min_free_mb,
+                            dir.display()
+                        );
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn remove(archive: &Archive, reason: &str) {
+    match fs::remove_file(&archive.path) {
+        Ok(()) => info!(
+            "Pruned archive {} ({} bytes): {reason}",
+            archive.path.display(),
+            archive.len
+        ),
+        Err(err) => error!("Failed to prune archive {}: {err}", archive.path.display()),
+    }
+}
+
+// whether free_space can tell the free space of a disk on this platform
+pub const FREE_SPACE_KNOWN: bool = cfg!(unix);
+
+// free space available to the logger on the disk holding the path
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = if path.as_os_str().is_empty() { Path::new(".") } else { path };
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // safety: c_path is a valid nul terminated string and stat is a valid out pointer
+    let result = unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) };
+    if result != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
@@ -0,0 +1,788 @@
+use chrono::{DateTime, Duration, Local, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{error, Record};
+use log4rs::append::rolling_file::policy::compound::roll::Roll;
+use log4rs::append::Append;
+use log4rs::encode::writer::simple::SimpleWriter;
+use log4rs::encode::Encode;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// log files rolled over since the start, plc logs and the diagnostic log alike
+pub static ROTATIONS: AtomicU64 = AtomicU64::new(0);
+
+// archives of size based rotation, numbered from 1 for the newest up to history_to_keep.
+// the log is synced before it is moved away and an archive before the log it was made
+// from is removed, so lines reported durable by a sync of the active log stay durable
+#[derive(Debug)]
+pub struct NumberedRoller {
+    // archive path containing {} for the number
+    archive_pattern: String,
+    history_to_keep: u32,
+    // rolled logs waiting to be archived, in the order they were rolled
+    archiver: Mutex<Option<Sender<PathBuf>>>,
+}
+
+impl NumberedRoller {
+    pub fn new(archive_pattern: &Path, history_to_keep: u32) -> NumberedRoller {
+        NumberedRoller {
+            archive_pattern: archive_pattern.to_string_lossy().to_string(),
+            history_to_keep,
+            archiver: Mutex::new(None),
+        }
+    }
+
+    // archiving runs on a thread of its own, started at the first roll
+    fn archiver(&self) -> Sender<PathBuf> {
+        let mut archiver = self.archiver.lock().unwrap();
+        let archiver = archiver.get_or_insert_with(|| {
+            let (tx, rx) = channel::<PathBuf>();
+            let archive_pattern = self.archive_pattern.clone();
+            let history_to_keep = self.history_to_keep;
+            thread::spawn(move || {
+                for log in rx {
+                    if let Err(err) = shift_archives(&archive_pattern, history_to_keep, &log) {
+                        error!("Failed to archive {}: {err}", log.display());
+                    }
+                }
+            });
+            tx
+        });
+        archiver.clone()
+    }
+}
+
+impl Roll for NumberedRoller {
+    fn roll(&self, file: &Path) -> anyhow::Result<()> {
+        sync_file(file)?;
+        ROTATIONS.fetch_add(1, Ordering::Relaxed);
+        if self.history_to_keep == 0 {
+            return Ok(fs::remove_file(file)?);
+        }
+
+        // move the log out of the way first, so logging continues right away
+        let mut index = 0;
+        let temp = loop {
+            let mut temp = file.to_path_buf().into_os_string();
+            temp.push(format!(".{index}.rolling"));
+            let temp = PathBuf::from(temp);
+            if !temp.exists() {
+                break temp;
+            }
+            index += 1;
+        };
+        move_file(file, &temp)?;
+        self.archiver().send(temp)?;
+        Ok(())
+    }
+}
+
+// move every archive one number up, dropping the last, and archive the log as number 1
+fn shift_archives(archive_pattern: &str, history_to_keep: u32, log: &Path) -> io::Result<()> {
+    let archive = |index: u32| PathBuf::from(archive_pattern.replace("{}", &index.to_string()));
+    if let Some(parent) = archive(1).parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    for index in (1..history_to_keep).rev() {
+        if archive(index).exists() {
+            move_file(&archive(index), &archive(index + 1))?;
+        }
+    }
+    match archive_pattern.ends_with(".gz") {
+        true => compress_file(log, &archive(1)),
+        false => move_file(log, &archive(1)),
+    }
+}
+
+// size, history and time limits of a rolling log file
+#[derive(Debug, Clone)]
+pub struct Rotation {
+    pub max_size_mb: u128,
+    pub history_to_keep: u32,
+    pub interval: RotationInterval,
+    pub timezone: RotationTz,
+    // roll on max_size_mb as well as on the interval
+    pub on_size: bool,
+}
+
+impl Rotation {
+    pub fn max_size_bytes(&self) -> u64 {
+        byte_unit::n_mb_bytes!(self.max_size_mb) as u64
+    }
+}
+
+// time boundaries at which the active log is archived
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RotationInterval {
+    // size based only, archives are numbered
+    None,
+    Hourly,
+    Daily,
+    // at each of the given start times of the day
+    Shifts(Vec<NaiveTime>),
+}
+
+impl RotationInterval {
+    pub fn parse(interval: &str, shifts: &[String]) -> Result<RotationInterval, String> {
+        match interval {
+            "none" => Ok(RotationInterval::None),
+            "hourly" => Ok(RotationInterval::Hourly),
+            "daily" => Ok(RotationInterval::Daily),
+            "shifts" => {
+                let mut starts = Vec::new();
+                for shift in shifts {
+                    match NaiveTime::parse_from_str(shift, "%H:%M") {
+                        Ok(val_ok) => starts.push(val_ok),
+                        Err(_) => return Err(format!("invalid shift start '{shift}', expected HH:MM")),
+                    }
+                }
+                if starts.is_empty() {
+                    return Err(String::from("shift rotation needs at least one entry in rotation_shifts"));
+                }
+                starts.sort();
+                starts.dedup();
+                Ok(RotationInterval::Shifts(starts))
+            }
+            _ => Err(format!(
+                "unknown rotation interval '{interval}', expected one of: none, hourly, daily, shifts"
+            )),
+        }
+    }
+}
+
+// timezone the rotation boundaries and archive dates are expressed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotationTz {
+    Local,
+    Utc,
+    Named(chrono_tz::Tz),
+}
+
+impl FromStr for RotationTz {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(RotationTz::Local),
+            "utc" | "UTC" => Ok(RotationTz::Utc),
+            _ => match s.parse::<chrono_tz::Tz>() {
+                Ok(tz) => Ok(RotationTz::Named(tz)),
+                Err(_) => Err(format!("unknown rotation timezone '{s}'")),
+            },
+        }
+    }
+}
+
+impl RotationTz {
+    fn naive(&self, time: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            RotationTz::Local => time.with_timezone(&Local).naive_local(),
+            RotationTz::Utc => time.naive_utc(),
+            RotationTz::Named(tz) => tz.from_utc_datetime(&time.naive_utc()).naive_local(),
+        }
+    }
+}
+
+impl Rotation {
+    // start of the rotation period the given time falls in, as wall clock time
+    fn period_start(&self, time: DateTime<Utc>) -> Option<NaiveDateTime> {
+        let local = self.timezone.naive(time);
+        match &self.interval {
+            RotationInterval::None => None,
+            RotationInterval::Hourly => local.date().and_hms_opt(local.hour(), 0, 0),
+            RotationInterval::Daily => local.date().and_hms_opt(0, 0, 0),
+            RotationInterval::Shifts(starts) => {
+                // latest shift start at or before the time, otherwise the last shift of the day before
+                match starts.iter().rev().find(|start| **start <= local.time()) {
+                    Some(start) => Some(local.date().and_time(*start)),
+                    None => {
+                        let day_before = local.date() - Duration::days(1);
+                        starts.last().map(|start| day_before.and_time(*start))
+                    }
+                }
+            }
+        }
+    }
+
+    // date stamp used in the archive name for a period
+    fn label(&self, period: NaiveDateTime) -> String {
+        match &self.interval {
+            RotationInterval::Hourly => period.format("%Y-%m-%d_%H").to_string(),
+            RotationInterval::Shifts(_) => period.format("%Y-%m-%d_%H%M").to_string(),
+            _ => period.format("%Y-%m-%d").to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ActiveFile {
+    writer: Option<BufWriter<File>>,
+    len: u64,
+    period: Option<NaiveDateTime>,
+}
+
+// rolling file appender archiving on time boundaries, and optionally on size, with
+// the date of the archived period in the archive name
+//
+// the boundary is checked before a record is written, so the first record of a
+// new period never ends up in the archive of the previous one
+#[derive(Debug)]
+pub struct DatedRollingAppender {
+    path: PathBuf,
+    // archive path containing {} for the date stamp
+    archive_pattern: String,
+    encoder: Box<dyn Encode>,
+    rotation: Rotation,
+    active: Mutex<Option<ActiveFile>>,
+    // keeps archive names unique while compression is still running
+    pending: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+impl DatedRollingAppender {
+    pub fn new(
+        path: &Path,
+        archive_pattern: &Path,
+        encoder: Box<dyn Encode>,
+        rotation: &Rotation,
+    ) -> io::Result<DatedRollingAppender> {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        Ok(DatedRollingAppender {
+            path: path.to_path_buf(),
+            archive_pattern: archive_pattern.to_string_lossy().to_string(),
+            encoder,
+            rotation: rotation.clone(),
+            active: Mutex::new(None),
+            pending: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    fn open(&self, now_period: Option<NaiveDateTime>) -> io::Result<ActiveFile> {
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let metadata = file.metadata()?;
+        let len = metadata.len();
+
+        // a file left over from an earlier run belongs to the period it was last written in
+        let period = match metadata.modified() {
+            Ok(modified) if len > 0 => self.rotation.period_start(DateTime::<Utc>::from(modified)),
+            _ => now_period,
+        };
+
+        Ok(ActiveFile {
+            writer: Some(BufWriter::new(file)),
+            len,
+            period,
+        })
+    }
+
+    // close the active file and archive it under the date stamp of its period
+    fn roll(&self, active: &mut ActiveFile) -> io::Result<()> {
+        // lines already written may have been reported durable by a sync of the path
+        if let Some(mut writer) = active.writer.take() {
+            writer.flush()?;
+            writer.get_ref().sync_data()?;
+        }
+        ROTATIONS.fetch_add(1, Ordering::Relaxed);
+
+        let period = active.period.unwrap_or_else(|| self.rotation.timezone.naive(Utc::now()));
+        let label = self.rotation.label(period);
+        active.len = 0;
+
+        if self.rotation.history_to_keep == 0 {
+            return fs::remove_file(&self.path);
+        }
+
+        let (archive, stamp) = self.reserve_archive(&label)?;
+        let compress = self.archive_pattern.ends_with(".gz");
+
+        // move the active file out of the way first, so logging continues right away
+        let temp = if compress {
+            let mut temp = self.path.clone().into_os_string();
+            temp.push(format!(".{stamp}.rolling"));
+            PathBuf::from(temp)
+        } else {
+            archive.clone()
+        };
+        move_file(&self.path, &temp)?;
+
+        // failures are logged, possibly through this appender, so never while its lock is held
+        let pending = Arc::clone(&self.pending);
+        let archive_pattern = self.archive_pattern.clone();
+        let history_to_keep = self.rotation.history_to_keep;
+        thread::spawn(move || {
+            if compress {
+                if let Err(err) = compress_file(&temp, &archive) {
+                    error!("Failed to compress {}: {err}", temp.display());
+                }
+            }
+            pending.lock().unwrap().retain(|p| *p != archive);
+            prune_archives(&archive_pattern, history_to_keep);
+        });
+
+        Ok(())
+    }
+
+    // first free archive name for the label, more than one archive in a period
+    // (size rolls) get a counter appended: 2023-03-03, 2023-03-03.1, ...
+    fn reserve_archive(&self, label: &str) -> io::Result<(PathBuf, String)> {
+        let mut pending = self.pending.lock().unwrap();
+        let mut index = 0;
+        loop {
+            let stamp = match index {
+                0 => label.to_string(),
+                _ => format!("{label}.{index}"),
+            };
+            let archive = PathBuf::from(self.archive_pattern.replace("{}", &stamp));
+            if !archive.exists() && !pending.contains(&archive) {
+                if let Some(parent) = archive.parent() {
+                    if !parent.as_os_str().is_empty() {
+                        fs::create_dir_all(parent)?;
+                    }
+                }
+                pending.push(archive.clone());
+                return Ok((archive, stamp));
+            }
+            index += 1;
+        }
+    }
+}
+
+impl Append for DatedRollingAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        let mut state = self.active.lock().unwrap();
+        let now_period = self.rotation.period_start(Utc::now());
+
+        if state.is_none() {
+            *state = Some(self.open(now_period)?);
+        }
+        let active = state.as_mut().unwrap();
+
+        // time boundary passed since the last record
+        if active.len > 0 && active.period != now_period {
+            self.roll(active)?;
+        }
+        active.period = now_period;
+
+        if active.writer.is_none() {
+            let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            active.writer = Some(BufWriter::new(file));
+        }
+
+        let mut buf = Vec::new();
+        self.encoder.encode(&mut SimpleWriter(&mut buf), record)?;
+        let writer = active.writer.as_mut().unwrap();
+        writer.write_all(&buf)?;
+        writer.flush()?;
+        active.len += buf.len() as u64;
+
+        if self.rotation.on_size && active.len > self.rotation.max_size_bytes() {
+            self.roll(active)?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+// flush the data of a file to disk, through a handle opened for writing as windows
+// does not flush read-only handles
+pub fn sync_file(path: &Path) -> io::Result<()> {
+    OpenOptions::new().write(true).open(path)?.sync_data()
+}
+
+fn move_file(src: &Path, dst: &Path) -> io::Result<()> {
+    match fs::rename(src, dst) {
+        Ok(()) => Ok(()),
+        // the archive directory can be on another mount
+        Err(_) => {
+            fs::copy(src, dst)?;
+            sync_file(dst)?;
+            fs::remove_file(src)
+        }
+    }
+}
+
+fn compress_file(src: &Path, dst: &Path) -> io::Result<()> {
+    let mut input = File::open(src)?;
+    let mut output = GzEncoder::new(File::create(dst)?, Compression::default());
+    io::copy(&mut input, &mut output)?;
+    output.finish()?.sync_all()?;
+    drop(input);
+    fs::remove_file(src)
+}
+
+// delete the oldest archives matching the pattern beyond the number to keep
+fn prune_archives(archive_pattern: &str, history_to_keep: u32) {
+    let mut archives = archive_files(Path::new(archive_pattern));
+    while archives.len() > history_to_keep as usize {
+        let (oldest, _) = archives.remove(0);
+        if let Err(err) = fs::remove_file(&oldest) {
+            error!("Failed to remove archive {}: {err}", oldest.display());
+        }
+    }
+}
+
+// archives in the directory of the pattern whose name matches it, oldest first
+pub fn archive_files(archive_pattern: &Path) -> Vec<(PathBuf, fs::Metadata)> {
+    let dir = match archive_pattern.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let file_pattern = match archive_pattern.file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => return Vec::new(),
+    };
+    let (prefix, suffix) = match file_pattern.split_once("{}") {
+        Some(parts) => parts,
+        None => return Vec::new(),
+    };
+
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut archives: Vec<(PathBuf, fs::Metadata)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            name.len() > prefix.len() + suffix.len()
+                && name.starts_with(prefix)
+                && name.ends_with(suffix)
+        })
+        .filter_map(|entry| entry.metadata().ok().map(|metadata| (entry.path(), metadata)))
+        .filter(|(_, metadata)| metadata.is_file())
+        .collect();
+
+    archives.sort_by_key(|(_, metadata)| metadata.modified().ok());
+    archives
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use io::Read;
+    use log4rs::encode::pattern::PatternEncoder;
+    use std::time::Duration as StdDuration;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("plclogger-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn rotation(interval: RotationInterval, history_to_keep: u32) -> Rotation {
+        Rotation {
+            max_size_mb: 1,
+            history_to_keep,
+            interval,
+            timezone: RotationTz::Utc,
+            on_size: true,
+        }
+    }
+
+    // archiving runs in the background, done once no rolled log is left
+    fn wait_archived(dir: &Path, archives: usize) -> Vec<PathBuf> {
+        for _ in 0..100 {
+            let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "gz"))
+                .collect();
+            let rolling = fs::read_dir(dir).unwrap().any(|entry| {
+                entry
+                    .unwrap()
+                    .path()
+                    .to_string_lossy()
+                    .ends_with(".rolling")
+            });
+            if paths.len() == archives && !rolling {
+                paths.sort();
+                return paths;
+            }
+            thread::sleep(StdDuration::from_millis(50));
+        }
+        panic!("{} archives not written in {}", archives, dir.display());
+    }
+
+    fn gunzip(path: &Path) -> String {
+        let mut text = String::new();
+        GzDecoder::new(File::open(path).unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    fn append(appender: &dyn Append, line: &str) {
+        appender
+            .append(&Record::builder().args(format_args!("{line}")).build())
+            .unwrap();
+    }
+
+    fn at(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn wall(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn shifts(starts: &[&str]) -> RotationInterval {
+        let starts: Vec<String> = starts.iter().map(|start| start.to_string()).collect();
+        RotationInterval::parse("shifts", &starts).unwrap()
+    }
+
+    #[test]
+    fn periods_and_labels() {
+        let berlin = RotationTz::Named(chrono_tz::Europe::Berlin);
+        for (interval, timezone, time, start, label) in [
+            (
+                RotationInterval::Hourly,
+                RotationTz::Utc,
+                "2024-03-01T12:59:59Z",
+                "2024-03-01 12:00",
+                "2024-03-01_12",
+            ),
+            (
+                RotationInterval::Hourly,
+                RotationTz::Utc,
+                "2024-03-01T13:00:00Z",
+                "2024-03-01 13:00",
+                "2024-03-01_13",
+            ),
+            (
+                RotationInterval::Hourly,
+                RotationTz::Utc,
+                "2024-03-01T23:59:59Z",
+                "2024-03-01 23:00",
+                "2024-03-01_23",
+            ),
+            (
+                RotationInterval::Hourly,
+                RotationTz::Utc,
+                "2024-03-02T00:00:00Z",
+                "2024-03-02 00:00",
+                "2024-03-02_00",
+            ),
+            // the hour skipped by the change to summer time
+            (
+                RotationInterval::Hourly,
+                berlin,
+                "2024-03-31T00:59:59Z",
+                "2024-03-31 01:00",
+                "2024-03-31_01",
+            ),
+            (
+                RotationInterval::Hourly,
+                berlin,
+                "2024-03-31T01:00:00Z",
+                "2024-03-31 03:00",
+                "2024-03-31_03",
+            ),
+            (
+                RotationInterval::Daily,
+                RotationTz::Utc,
+                "2024-02-29T23:59:59Z",
+                "2024-02-29 00:00",
+                "2024-02-29",
+            ),
+            (
+                RotationInterval::Daily,
+                RotationTz::Utc,
+                "2024-03-01T00:00:00Z",
+                "2024-03-01 00:00",
+                "2024-03-01",
+            ),
+            (
+                RotationInterval::Daily,
+                RotationTz::Utc,
+                "2024-12-31T23:59:59Z",
+                "2024-12-31 00:00",
+                "2024-12-31",
+            ),
+            // the day starts at midnight in the zone of the rotation
+            (
+                RotationInterval::Daily,
+                berlin,
+                "2024-03-01T22:59:59Z",
+                "2024-03-01 00:00",
+                "2024-03-01",
+            ),
+            (
+                RotationInterval::Daily,
+                berlin,
+                "2024-03-01T23:00:00Z",
+                "2024-03-02 00:00",
+                "2024-03-02",
+            ),
+        ] {
+            let rotation = Rotation {
+                timezone,
+                ..rotation(interval, 1)
+            };
+            let period = rotation.period_start(at(time));
+            assert_eq!(period, Some(wall(start)), "{time}");
+            assert_eq!(rotation.label(period.unwrap()), label);
+        }
+        assert_eq!(
+            rotation(RotationInterval::None, 1).period_start(at("2024-03-01T12:00:00Z")),
+            None
+        );
+    }
+
+    #[test]
+    fn shift_periods() {
+        let three = rotation(shifts(&["22:00", "06:00", "14:00", "06:00"]), 1);
+        assert_eq!(three.interval, shifts(&["06:00", "14:00", "22:00"]));
+        for (time, start, label) in [
+            (
+                "2024-03-01T06:00:00Z",
+                "2024-03-01 06:00",
+                "2024-03-01_0600",
+            ),
+            (
+                "2024-03-01T13:59:59Z",
+                "2024-03-01 06:00",
+                "2024-03-01_0600",
+            ),
+            (
+                "2024-03-01T14:00:00Z",
+                "2024-03-01 14:00",
+                "2024-03-01_1400",
+            ),
+            (
+                "2024-03-01T23:59:59Z",
+                "2024-03-01 22:00",
+                "2024-03-01_2200",
+            ),
+            // the night shift runs on past midnight
+            (
+                "2024-03-02T00:00:00Z",
+                "2024-03-01 22:00",
+                "2024-03-01_2200",
+            ),
+            (
+                "2024-03-02T05:59:59Z",
+                "2024-03-01 22:00",
+                "2024-03-01_2200",
+            ),
+            (
+                "2024-03-01T05:59:59Z",
+                "2024-02-29 22:00",
+                "2024-02-29_2200",
+            ),
+            (
+                "2025-01-01T01:00:00Z",
+                "2024-12-31 22:00",
+                "2024-12-31_2200",
+            ),
+        ] {
+            let period = three.period_start(at(time));
+            assert_eq!(period, Some(wall(start)), "{time}");
+            assert_eq!(three.label(period.unwrap()), label);
+        }
+
+        let single = rotation(shifts(&["07:30"]), 1);
+        assert_eq!(
+            single.period_start(at("2024-03-01T07:29:59Z")),
+            Some(wall("2024-02-29 07:30"))
+        );
+        assert_eq!(
+            single.period_start(at("2024-03-01T07:30:00Z")),
+            Some(wall("2024-03-01 07:30"))
+        );
+    }
+
+    #[test]
+    fn invalid_shifts() {
+        let parse = |starts: &[&str]| {
+            let starts: Vec<String> = starts.iter().map(|start| start.to_string()).collect();
+            RotationInterval::parse("shifts", &starts).unwrap_err()
+        };
+        assert_eq!(
+            parse(&[]),
+            "shift rotation needs at least one entry in rotation_shifts"
+        );
+        assert_eq!(
+            parse(&["06:00", "25:00"]),
+            "invalid shift start '25:00', expected HH:MM"
+        );
+        assert_eq!(parse(&["6"]), "invalid shift start '6', expected HH:MM");
+        assert!(RotationInterval::parse("weekly", &[]).is_err());
+    }
+
+    #[test]
+    fn dated_roll_between_write_and_sync() {
+        let dir = test_dir("dated");
+        let log = dir.join("plc.log");
+        let appender = DatedRollingAppender::new(
+            &log,
+            &dir.join("plclog_{}.gz"),
+            Box::new(PatternEncoder::new("{m}{n}")),
+            &rotation(RotationInterval::Daily, 2),
+        )
+        .unwrap();
+        let first = "a".repeat(600_000);
+        let second = "b".repeat(600_000);
+        append(&appender, &first);
+        append(&appender, &second);
+        // rolled on size, a sync of the path finds nothing left to sync
+        assert_eq!(sync_file(&log).unwrap_err().kind(), io::ErrorKind::NotFound);
+        let archives = wait_archived(&dir, 1);
+        let label = Utc::now().format("%Y-%m-%d").to_string();
+        assert_eq!(archives, [dir.join(format!("plclog_{label}.gz"))]);
+        assert_eq!(gunzip(&archives[0]), format!("{first}\n{second}\n"));
+
+        append(&appender, "third");
+        sync_file(&log).unwrap();
+        assert_eq!(fs::read_to_string(&log).unwrap(), "third\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn numbered_archives_move_up() {
+        let dir = test_dir("numbered");
+        let log = dir.join("plc.log");
+        let roller = NumberedRoller::new(&dir.join("plclog_{}.gz"), 2);
+        for line in ["first", "second", "third"] {
+            fs::write(&log, line).unwrap();
+            roller.roll(&log).unwrap();
+            assert!(!log.exists());
+        }
+        let archives = wait_archived(&dir, 2);
+        assert_eq!(archives, [dir.join("plclog_1.gz"), dir.join("plclog_2.gz")]);
+        assert_eq!(gunzip(&archives[0]), "third");
+        assert_eq!(gunzip(&archives[1]), "second");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn numbered_without_history() {
+        let dir = test_dir("no-history");
+        let log = dir.join("plc.log");
+        fs::write(&log, "line").unwrap();
+        NumberedRoller::new(&dir.join("plclog_{}.gz"), 0)
+            .roll(&log)
+            .unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
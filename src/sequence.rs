@@ -0,0 +1,446 @@
+use log::info;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::sources::SourceMap;
+use crate::stats::Stats;
+
+// how far back a late message is still recognised as reordered or duplicate, beyond
+// that a lower sequence number means the plc restarted its counter
+const WINDOW: u64 = 1024;
+
+// "#<seq> " in front of the message, the plc counting seq up by one per message
+pub fn split_header(data: &[u8]) -> Option<(u64, &[u8])> {
+    let rest = data.strip_prefix(b"#")?;
+    let end = rest.iter().take(21).position(|b| *b == b' ')?;
+    let seq = std::str::from_utf8(&rest[..end]).ok()?.parse().ok()?;
+    Some((seq, &rest[end + 1..]))
+}
+
+// irregularities noticed in the sequence numbers of a plc
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SequenceEvent {
+    Gap { expected: u64, received: u64 },
+    Duplicate(u64),
+    Reordered(u64),
+    Reset { last: u64, received: u64 },
+}
+
+impl fmt::Display for SequenceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SequenceEvent::Gap { expected, received } => write!(
+                f,
+                "sequence gap: {} messages missing (expected {expected}, received {received})",
+                received - expected
+            ),
+            SequenceEvent::Duplicate(seq) => write!(f, "sequence duplicate: {seq} received again"),
+            SequenceEvent::Reordered(seq) => write!(f, "sequence reordered: {seq} arrived late"),
+            SequenceEvent::Reset { last, received } => write!(
+                f,
+                "sequence reset: counter restarted at {received} after {last}, plc restarted?"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SequenceCounters {
+    pub received: u64,
+    pub lost: u64,
+    pub duplicates: u64,
+    pub reordered: u64,
+    pub resets: u64,
+}
+
+impl SequenceCounters {
+    fn add(&mut self, other: &SequenceCounters) {
+        self.received += other.received;
+        self.lost += other.lost;
+        self.duplicates += other.duplicates;
+        self.reordered += other.reordered;
+        self.resets += other.resets;
+    }
+}
+
+#[derive(Debug)]
+struct Peer {
+    last: u64,
+    // numbers skipped by a gap that may still arrive late
+    missing: BTreeSet<u64>,
+    counters: SequenceCounters,
+}
+
+// sequence numbers of every plc sending them, by listener and sender address. a plc
+// logging to several listeners keeps a counter for each
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    peers: Mutex<HashMap<(Arc<str>, IpAddr), Peer>>,
+}
+
+impl SequenceTracker {
+    pub fn track(&self, listener: &Arc<str>, ip: IpAddr, seq: u64) -> Option<SequenceEvent> {
+        let mut peers = self.peers.lock().unwrap();
+        let key = (Arc::clone(listener), ip);
+        let peer = match peers.get_mut(&key) {
+            Some(peer) => peer,
+            None => {
+                peers.insert(
+                    key,
+                    Peer {
+                        last: seq,
+                        missing: BTreeSet::new(),
+                        counters: SequenceCounters {
+                            received: 1,
+                            ..Default::default()
+                        },
+                    },
+                );
+                return None;
+            }
+        };
+        peer.counters.received += 1;
+
+        let expected = peer.last.wrapping_add(1);
+        if seq == expected {
+            peer.last = seq;
+            return None;
+        }
+        if seq > expected {
+            peer.counters.lost += seq - expected;
+            peer.missing.extend(expected.max(seq.saturating_sub(WINDOW))..seq);
+            peer.last = seq;
+            // numbers too old to still arrive are forgotten
+            let oldest = seq.saturating_sub(WINDOW);
+            peer.missing = peer.missing.split_off(&oldest);
+            return Some(SequenceEvent::Gap {
+                expected,
+                received: seq,
+            });
+        }
+
+        if peer.missing.remove(&seq) {
+            peer.counters.lost -= 1;
+            peer.counters.reordered += 1;
+            return Some(SequenceEvent::Reordered(seq));
+        }
+        // counters restart at 0 or 1, anything else close behind was seen before
+        if seq == peer.last || (seq > 1 && peer.last - seq < WINDOW) {
+            peer.counters.duplicates += 1;
+            return Some(SequenceEvent::Duplicate(seq));
+        }
+
+        let last = peer.last;
+        peer.counters.resets += 1;
+        peer.last = seq;
+        peer.missing.clear();
+        Some(SequenceEvent::Reset {
+            last,
+            received: seq,
+        })
+    }
+
+    // counters of every plc, added up per source name
+    pub fn by_source(&self, sources: &SourceMap) -> Vec<(String, SequenceCounters)> {
+        let peers = self.peers.lock().unwrap();
+        let mut by_source: HashMap<String, SequenceCounters> = HashMap::new();
+        for ((_, ip), peer) in peers.iter() {
+            let name = match sources.lookup(*ip) {
+                Some(source) => source.name.clone(),
+                None => ip.to_string(),
+            };
+            by_source.entry(name).or_default().add(&peer.counters);
+        }
+        let mut by_source: Vec<(String, SequenceCounters)> = by_source.into_iter().collect();
+        by_source.sort_by(|a, b| a.0.cmp(&b.0));
+        by_source
+    }
+}
+
+// sequence numbers of the messages the writer logged lately, by listener and sender
+// address. with acks a message is logged once, a retransmit of a logged message is a
+// lost ack and only acked again
+#[derive(Debug, Default)]
+pub struct LoggedSequences {
+    peers: HashMap<(Arc<str>, IpAddr), Logged>,
+    // logged since the last sync, forgotten when the sync fails
+    unsynced: Vec<(Arc<str>, IpAddr, u64)>,
+}
+
+// the last WINDOW numbers logged, oldest first
+#[derive(Debug, Default)]
+struct Logged {
+    order: VecDeque<u64>,
+    seqs: HashSet<u64>,
+}
+
+impl LoggedSequences {
+    pub fn contains(&self, listener: &Arc<str>, ip: IpAddr, seq: u64) -> bool {
+        self.peers
+            .get(&(Arc::clone(listener), ip))
+            .is_some_and(|logged| logged.seqs.contains(&seq))
+    }
+
+    pub fn insert(&mut self, listener: &Arc<str>, ip: IpAddr, seq: u64) {
+        let logged = self.peers.entry((Arc::clone(listener), ip)).or_default();
+        if !logged.seqs.insert(seq) {
+            return;
+        }
+        self.unsynced.push((Arc::clone(listener), ip, seq));
+        logged.order.push_back(seq);
+        if logged.order.len() > WINDOW as usize {
+            if let Some(oldest) = logged.order.pop_front() {
+                logged.seqs.remove(&oldest);
+            }
+        }
+    }
+
+    // the plc restarted its counter, the numbers it sends next are new messages
+    pub fn reset(&mut self, listener: &Arc<str>, ip: IpAddr) {
+        self.peers.remove(&(Arc::clone(listener), ip));
+    }
+
+    pub fn synced(&mut self) {
+        self.unsynced.clear();
+    }
+
+    // the messages were not acked, their retransmits are logged again
+    pub fn forget_unsynced(&mut self) {
+        for (listener, ip, seq) in self.unsynced.drain(..) {
+            if let Some(logged) = self.peers.get_mut(&(listener, ip)) {
+                if logged.seqs.remove(&seq) {
+                    logged.order.retain(|logged| *logged != seq);
+                }
+            }
+        }
+    }
+}
+
+// periodically log the loss statistics of every source, skipped when nothing changed
+pub fn spawn_reporter(stats: Arc<Stats>, sources: Arc<SourceMap>, interval_secs: u64) {
+    thread::spawn(move || {
+        let mut last = Vec::new();
+        loop {
+            thread::sleep(Duration::from_secs(interval_secs));
+            let by_source = stats.sequences.by_source(&sources);
+            if by_source == last {
+                continue;
+            }
+            for (name, counters) in &by_source {
+                let expected = counters.received - counters.duplicates + counters.lost;
+                let loss = counters.lost as f64 * 100.0 / expected.max(1) as f64;
+                info!(
+                    "Sequence - {name}: received: {}, lost: {} ({loss:.2}%), duplicates: {}, reordered: {}, resets: {}",
+                    counters.received, counters.lost, counters.duplicates, counters.reordered, counters.resets
+                );
+            }
+            last = by_source;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLC: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 1));
+
+    fn tracked(seqs: &[u64]) -> (SequenceTracker, Arc<str>) {
+        let tracker = SequenceTracker::default();
+        let listener: Arc<str> = Arc::from("udp");
+        for seq in seqs {
+            tracker.track(&listener, PLC, *seq);
+        }
+        (tracker, listener)
+    }
+
+    fn counters(tracker: &SequenceTracker, listener: &Arc<str>) -> SequenceCounters {
+        let peers = tracker.peers.lock().unwrap();
+        peers[&(Arc::clone(listener), PLC)].counters.clone()
+    }
+
+    #[test]
+    fn split_header_takes_the_number_off() {
+        assert_eq!(split_header(b"#42 motor on"), Some((42, &b"motor on"[..])));
+        assert_eq!(split_header(b"#0 "), Some((0, &b""[..])));
+        assert_eq!(split_header(b"42 motor on"), None);
+        assert_eq!(split_header(b"#4x2 motor on"), None);
+        assert_eq!(split_header(b"#42"), None);
+        assert_eq!(split_header(b"# 42 motor on"), None);
+        // longer than any u64
+        assert_eq!(split_header(b"#123456789012345678901 motor on"), None);
+    }
+
+    #[test]
+    fn in_order_numbers_are_not_reported() {
+        let (tracker, listener) = tracked(&[]);
+        for seq in 1..=5 {
+            assert_eq!(tracker.track(&listener, PLC, seq), None);
+        }
+        let counters = counters(&tracker, &listener);
+        assert_eq!(counters.received, 5);
+        assert_eq!(counters.lost, 0);
+    }
+
+    #[test]
+    fn gap_counts_the_missing_numbers() {
+        let (tracker, listener) = tracked(&[1, 2]);
+        let event = tracker.track(&listener, PLC, 6);
+        assert_eq!(
+            event,
+            Some(SequenceEvent::Gap {
+                expected: 3,
+                received: 6
+            })
+        );
+        assert_eq!(
+            event.unwrap().to_string(),
+            "sequence gap: 3 messages missing (expected 3, received 6)"
+        );
+        assert_eq!(counters(&tracker, &listener).lost, 3);
+    }
+
+    #[test]
+    fn late_number_from_a_gap_is_reordered() {
+        let (tracker, listener) = tracked(&[1, 2, 5]);
+        assert_eq!(tracker.track(&listener, PLC, 3), Some(SequenceEvent::Reordered(3)));
+        // only once, after that it is a duplicate
+        assert_eq!(tracker.track(&listener, PLC, 3), Some(SequenceEvent::Duplicate(3)));
+        let counters = counters(&tracker, &listener);
+        assert_eq!(counters.lost, 1);
+        assert_eq!(counters.reordered, 1);
+        assert_eq!(counters.duplicates, 1);
+    }
+
+    #[test]
+    fn number_seen_before_is_a_duplicate() {
+        let (tracker, listener) = tracked(&[1, 2, 3]);
+        assert_eq!(tracker.track(&listener, PLC, 3), Some(SequenceEvent::Duplicate(3)));
+        assert_eq!(tracker.track(&listener, PLC, 2), Some(SequenceEvent::Duplicate(2)));
+        assert_eq!(tracker.track(&listener, PLC, 4), None);
+        assert_eq!(counters(&tracker, &listener).duplicates, 2);
+    }
+
+    #[test]
+    fn counter_restarting_is_a_reset() {
+        let (tracker, listener) = tracked(&[100, 101]);
+        assert_eq!(
+            tracker.track(&listener, PLC, 1),
+            Some(SequenceEvent::Reset {
+                last: 101,
+                received: 1
+            })
+        );
+        // counting goes on from the new start
+        assert_eq!(tracker.track(&listener, PLC, 2), None);
+
+        let (tracker, listener) = tracked(&[5]);
+        assert_eq!(
+            tracker.track(&listener, PLC, 0),
+            Some(SequenceEvent::Reset {
+                last: 5,
+                received: 0
+            })
+        );
+        assert_eq!(counters(&tracker, &listener).resets, 1);
+    }
+
+    #[test]
+    fn number_far_behind_is_a_reset() {
+        let (tracker, listener) = tracked(&[5000]);
+        assert_eq!(
+            tracker.track(&listener, PLC, 5000 - WINDOW),
+            Some(SequenceEvent::Reset {
+                last: 5000,
+                received: 5000 - WINDOW
+            })
+        );
+    }
+
+    #[test]
+    fn gap_remembers_the_window_only() {
+        let (tracker, listener) = tracked(&[1]);
+        tracker.track(&listener, PLC, 10_000);
+        // too old to be told apart from a restart
+        assert!(matches!(
+            tracker.track(&listener, PLC, 2),
+            Some(SequenceEvent::Reset { .. })
+        ));
+
+        let (tracker, listener) = tracked(&[1]);
+        tracker.track(&listener, PLC, 10_000);
+        assert_eq!(
+            tracker.track(&listener, PLC, 10_000 - WINDOW),
+            Some(SequenceEvent::Reordered(10_000 - WINDOW))
+        );
+    }
+
+    #[test]
+    fn listeners_keep_their_own_counters() {
+        let (tracker, udp) = tracked(&[1, 2, 3]);
+        let tcp: Arc<str> = Arc::from("tcp");
+        assert_eq!(tracker.track(&tcp, PLC, 10), None);
+        assert_eq!(tracker.track(&tcp, PLC, 11), None);
+        assert_eq!(tracker.track(&udp, PLC, 4), None);
+    }
+
+    #[test]
+    fn senders_keep_their_own_counters() {
+        let (tracker, listener) = tracked(&[1, 2, 3]);
+        let other = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(tracker.track(&listener, other, 50), None);
+        assert_eq!(tracker.track(&listener, PLC, 4), None);
+    }
+
+    #[test]
+    fn logged_numbers_are_retransmits() {
+        let mut logged = LoggedSequences::default();
+        let listener: Arc<str> = Arc::from("udp");
+        logged.insert(&listener, PLC, 7);
+        assert!(logged.contains(&listener, PLC, 7));
+        assert!(!logged.contains(&listener, PLC, 8));
+        assert!(!logged.contains(&Arc::from("tcp"), PLC, 7));
+    }
+
+    #[test]
+    fn logged_numbers_are_kept_for_the_window() {
+        let mut logged = LoggedSequences::default();
+        let listener: Arc<str> = Arc::from("udp");
+        for seq in 1..=WINDOW + 1 {
+            logged.insert(&listener, PLC, seq);
+        }
+        assert!(!logged.contains(&listener, PLC, 1));
+        assert!(logged.contains(&listener, PLC, 2));
+        assert!(logged.contains(&listener, PLC, WINDOW + 1));
+    }
+
+    #[test]
+    fn reset_forgets_the_logged_numbers() {
+        let mut logged = LoggedSequences::default();
+        let listener: Arc<str> = Arc::from("udp");
+        logged.insert(&listener, PLC, 1);
+        logged.reset(&listener, PLC);
+        assert!(!logged.contains(&listener, PLC, 1));
+    }
+
+    #[test]
+    fn failed_sync_forgets_the_unsynced_numbers() {
+        let mut logged = LoggedSequences::default();
+        let listener: Arc<str> = Arc::from("udp");
+        logged.insert(&listener, PLC, 1);
+        logged.synced();
+        logged.insert(&listener, PLC, 2);
+        logged.insert(&listener, PLC, 3);
+        logged.forget_unsynced();
+        assert!(logged.contains(&listener, PLC, 1));
+        assert!(!logged.contains(&listener, PLC, 2));
+        assert!(!logged.contains(&listener, PLC, 3));
+        // logged again once retransmitted
+        logged.insert(&listener, PLC, 2);
+        assert!(logged.contains(&listener, PLC, 2));
+    }
+}
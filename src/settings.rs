@@ -0,0 +1,728 @@
+use config::{Config, ConfigError, FileFormat};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::decode::DecodePolicy;
+use crate::listeners::{BindConfig, Listener, ListenerConfig, MessageFormat, Protocol};
+use crate::retention::{Retention, FREE_SPACE_KNOWN};
+use crate::rotation::{Rotation, RotationInterval, RotationTz};
+use crate::pipeline::UdpSettings;
+use crate::plc_time::{PlcTimestamp, TimestampSource};
+use crate::plc_writer::{check_source_dirs, LOG_PATTERN_PLC};
+use crate::layouts::{Layout, LayoutConfig};
+use crate::parsers::{Parser, ParserConfig};
+use crate::sinks::{check_sink_name, Sink, SinkConfig, SinkFormat, DEFAULT_SINK};
+use crate::severity::{Severity, SeverityRuleConfig, SeverityRules};
+use crate::sources::{SourceConfig, SourceMap};
+use crate::stream::StreamSettings;
+use crate::http_api::HttpSettings;
+use crate::tcp::{Framing, TcpSettings};
+
+pub struct AppConfig {
+    pub listeners: Vec<Arc<Listener>>,
+    pub sinks: Vec<Sink>,
+    pub sources: Arc<SourceMap>,
+    pub severity_rules: Arc<SeverityRules>,
+    pub log_max_size_mb: u128,
+    pub log_history_to_keep: u32,
+    pub rotation_interval: RotationInterval,
+    pub rotation_timezone: RotationTz,
+    pub rotate_on_size: bool,
+    pub retention: Retention,
+    pub app_log_path: PathBuf,
+    pub archive_dir: PathBuf,
+    pub queue_depth: usize,
+    pub decode_workers: usize,
+    pub stats_interval_secs: u64,
+    // None when streaming is turned off
+    pub stream: Option<StreamSettings>,
+    // None when the http api is turned off
+    pub http: Option<HttpSettings>,
+    // every key of the config file and its default, secrets replaced
+    pub redacted: Value,
+}
+
+impl AppConfig {
+    // rotation used for every log file unless a source overrides it
+    pub fn rotation(&self) -> Rotation {
+        Rotation {
+            max_size_mb: self.log_max_size_mb,
+            history_to_keep: self.log_history_to_keep,
+            interval: self.rotation_interval.clone(),
+            timezone: self.rotation_timezone,
+            on_size: self.rotate_on_size,
+        }
+    }
+}
+
+// range checks shared by the global log settings and the per-source overrides
+pub fn check_log_max_size_mb(log_max_size_mb: i64) -> Result<u128, String> {
+    if !(1..=100).contains(&log_max_size_mb) {
+        return Err(String::from("max log size must be between 1 - 100 (mb)"));
+    }
+    Ok(log_max_size_mb.try_into().unwrap())
+}
+
+pub fn check_log_history_to_keep(log_history_to_keep: i64) -> Result<u32, String> {
+    if !(0..=1000).contains(&log_history_to_keep) {
+        return Err(String::from("log history must be between 0 - 1000"));
+    }
+    Ok(log_history_to_keep.try_into().unwrap())
+}
+
+fn check_udp_settings(
+    udp_max_datagram_bytes: i64,
+    udp_reassembly: bool,
+    udp_reassembly_timeout_ms: i64,
+) -> Result<UdpSettings, String> {
+    if !(1..=65535).contains(&udp_max_datagram_bytes) {
+        return Err(String::from(
+            "udp max datagram size must be between 1 - 65535 (bytes)",
+        ));
+    }
+    if !(100..=600_000).contains(&udp_reassembly_timeout_ms) {
+        return Err(String::from(
+            "udp reassembly timeout must be between 100 - 600000 (ms)",
+        ));
+    }
+    let reassembly_timeout = Duration::from_millis(udp_reassembly_timeout_ms.try_into().unwrap());
+    Ok(UdpSettings {
+        max_datagram_bytes: udp_max_datagram_bytes.try_into().unwrap(),
+        reassembly_timeout: udp_reassembly.then_some(reassembly_timeout),
+    })
+}
+
+fn check_tcp_settings(
+    tcp_framing: &str,
+    tcp_length_prefix_bytes: i64,
+    tcp_frame_size: i64,
+    tcp_max_connections: i64,
+    tcp_max_frame_bytes: i64,
+    tcp_idle_timeout_secs: i64,
+) -> Result<TcpSettings, String> {
+    let framing = Framing::parse(tcp_framing, tcp_length_prefix_bytes, tcp_frame_size)?;
+    if !(1..=10000).contains(&tcp_max_connections) {
+        return Err(String::from("tcp max connections must be between 1 - 10000"));
+    }
+    if !(1..=16_777_216).contains(&tcp_max_frame_bytes) {
+        return Err(String::from(
+            "tcp max frame size must be between 1 - 16777216 (bytes)",
+        ));
+    }
+    if !(0..=86400).contains(&tcp_idle_timeout_secs) {
+        return Err(String::from("tcp idle timeout must be between 0 - 86400 (s)"));
+    }
+    Ok(TcpSettings {
+        framing,
+        max_connections: tcp_max_connections.try_into().unwrap(),
+        max_frame_bytes: tcp_max_frame_bytes.try_into().unwrap(),
+        idle_timeout_secs: tcp_idle_timeout_secs.try_into().unwrap(),
+    })
+}
+
+fn check_stream_settings(
+    stream_port: i64,
+    stream_bind: &str,
+    stream_max_clients: i64,
+    stream_buffer: i64,
+) -> Result<Option<StreamSettings>, String> {
+    if stream_port == 0 {
+        return Ok(None);
+    }
+    if !(1..=65535).contains(&stream_port) {
+        return Err(String::from("stream port must be between 1 - 65535"));
+    }
+    let ip = parse_bind_ip(stream_bind)
+        .ok_or_else(|| format!("stream bind '{stream_bind}' is not an ip address"))?;
+    if !(1..=1000).contains(&stream_max_clients) {
+        return Err(String::from("stream max clients must be between 1 - 1000"));
+    }
+    if !(1..=1_000_000).contains(&stream_buffer) {
+        return Err(String::from("stream buffer must be between 1 - 1000000 (records)"));
+    }
+    Ok(Some(StreamSettings {
+        addr: SocketAddr::new(ip, stream_port as u16),
+        max_clients: stream_max_clients.try_into().unwrap(),
+        buffer: stream_buffer.try_into().unwrap(),
+    }))
+}
+
+fn check_http_settings(
+    http_port: i64,
+    http_bind: &str,
+    http_token: String,
+) -> Result<Option<HttpSettings>, String> {
+    if http_port == 0 {
+        return Ok(None);
+    }
+    if !(1..=65535).contains(&http_port) {
+        return Err(String::from("http port must be between 1 - 65535"));
+    }
+    let ip = parse_bind_ip(http_bind).ok_or_else(|| format!("http bind '{http_bind}' is not an ip address"))?;
+    Ok(Some(HttpSettings {
+        addr: SocketAddr::new(ip, http_port as u16),
+        token: (!http_token.is_empty()).then_some(http_token),
+    }))
+}
+
+// "10.0.1.5", "::1" or "[::1]"
+fn parse_bind_ip(bind: &str) -> Option<IpAddr> {
+    bind.trim_start_matches('[').trim_end_matches(']').parse().ok()
+}
+
+// values of keys that look like secrets, at any depth
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                let secret = ["token", "password", "secret"]
+                    .iter()
+                    .any(|word| key.to_ascii_lowercase().contains(word));
+                match value {
+                    Value::String(text) if secret && !text.is_empty() => *text = String::from("***"),
+                    _ => redact(value),
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+fn check_plc_timestamp(
+    plc_timestamp: &str,
+    field: &str,
+    timezone: &str,
+    clock_skew_warn_secs: i64,
+) -> Result<Option<PlcTimestamp>, String> {
+    let source = match plc_timestamp {
+        "none" => return Ok(None),
+        "prefix" => TimestampSource::Prefix,
+        "field" if !field.is_empty() => TimestampSource::Field(field.to_string()),
+        "field" => return Err(String::from("plc timestamp field must not be empty")),
+        _ => {
+            return Err(format!(
+                "unknown plc timestamp '{plc_timestamp}', expected one of: none, prefix, field"
+            ))
+        }
+    };
+    let timezone: RotationTz = timezone
+        .parse()
+        .map_err(|_| format!("unknown plc timezone '{timezone}'"))?;
+    if !(0..=86400).contains(&clock_skew_warn_secs) {
+        return Err(String::from("clock skew warning must be between 0 - 86400 (s)"));
+    }
+    let skew_warn = (clock_skew_warn_secs > 0).then(|| chrono::Duration::seconds(clock_skew_warn_secs));
+    Ok(Some(PlcTimestamp {
+        source,
+        timezone,
+        skew_warn,
+    }))
+}
+
+// a [[listeners]] entry, keys it leaves out are taken from the top-level settings
+fn listener(
+    config: &ListenerConfig,
+    cfg: &Config,
+    parsers: &[Arc<Parser>],
+) -> Result<Listener, ConfigError> {
+    let name = match &config.name {
+        Some(name) => name.clone(),
+        None => format!("{}:{}", config.protocol, config.port),
+    };
+    let message = |err: String| ConfigError::Message(format!("listener '{name}': {err}"));
+
+    if !(0..=65535).contains(&config.port) {
+        return Err(message(String::from("port must be between 0 - 65535")));
+    }
+    let port: u16 = config.port.try_into().unwrap();
+
+    let bind: BindConfig = match &config.bind {
+        Some(bind) => bind.clone(),
+        None => cfg.get("bind")?,
+    };
+    let addrs = bind.resolve(port).map_err(message)?;
+
+    let decode_policy = match &config.decode_policy {
+        Some(decode_policy) => decode_policy.clone(),
+        None => cfg.get_string("decode_policy")?,
+    };
+    let decode: DecodePolicy = decode_policy.parse().map_err(message)?;
+
+    let message_format = match &config.message_format {
+        Some(message_format) => message_format.clone(),
+        None => cfg.get_string("message_format")?,
+    };
+    let format: MessageFormat = message_format.parse().map_err(message)?;
+
+    let parser_name = match &config.parser {
+        Some(parser_name) => parser_name.clone(),
+        None => cfg.get_string("parser")?,
+    };
+    let parser = match parser_name.as_str() {
+        "" => None,
+        parser_name => match parsers.iter().find(|parser| parser.name == parser_name) {
+            Some(parser) => Some(Arc::clone(parser)),
+            None => return Err(message(format!("unknown parser '{parser_name}'"))),
+        },
+    };
+
+    let sequence_numbers = match config.sequence_numbers {
+        Some(sequence_numbers) => sequence_numbers,
+        None => cfg.get_bool("sequence_numbers")?,
+    };
+
+    let ack = match config.ack {
+        Some(ack) => ack,
+        None => cfg.get_bool("ack")?,
+    };
+    if ack && (config.protocol != "udp" || !sequence_numbers) {
+        return Err(message(String::from(
+            "acks are only sent on udp listeners with sequence numbers",
+        )));
+    }
+
+    let int = |value: Option<i64>, key: &str| match value {
+        Some(value) => Ok(value),
+        None => cfg.get_int(key),
+    };
+
+    let plc_timestamp = match &config.plc_timestamp {
+        Some(plc_timestamp) => plc_timestamp.clone(),
+        None => cfg.get_string("plc_timestamp")?,
+    };
+    let plc_timestamp_field = match &config.plc_timestamp_field {
+        Some(plc_timestamp_field) => plc_timestamp_field.clone(),
+        None => cfg.get_string("plc_timestamp_field")?,
+    };
+    let plc_timezone = match &config.plc_timezone {
+        Some(plc_timezone) => plc_timezone.clone(),
+        None => cfg.get_string("plc_timezone")?,
+    };
+    let plc_timestamp = check_plc_timestamp(
+        &plc_timestamp,
+        &plc_timestamp_field,
+        &plc_timezone,
+        int(config.clock_skew_warn_secs, "clock_skew_warn_secs")?,
+    )
+    .map_err(message)?;
+    let protocol = match config.protocol.as_str() {
+        "udp" => {
+            let udp_reassembly = match config.udp_reassembly {
+                Some(udp_reassembly) => udp_reassembly,
+                None => cfg.get_bool("udp_reassembly")?,
+            };
+            let udp = check_udp_settings(
+                int(config.udp_max_datagram_bytes, "udp_max_datagram_bytes")?,
+                udp_reassembly,
+                int(config.udp_reassembly_timeout_ms, "udp_reassembly_timeout_ms")?,
+            )
+            .map_err(message)?;
+            Protocol::Udp(udp)
+        }
+        "tcp" => {
+            let tcp_framing = match &config.tcp_framing {
+                Some(tcp_framing) => tcp_framing.clone(),
+                None => cfg.get_string("tcp_framing")?,
+            };
+            let tcp = check_tcp_settings(
+                &tcp_framing,
+                int(config.tcp_length_prefix_bytes, "tcp_length_prefix_bytes")?,
+                int(config.tcp_frame_size, "tcp_frame_size")?,
+                int(config.tcp_max_connections, "tcp_max_connections")?,
+                int(config.tcp_max_frame_bytes, "tcp_max_frame_bytes")?,
+                int(config.tcp_idle_timeout_secs, "tcp_idle_timeout_secs")?,
+            )
+            .map_err(message)?;
+            Protocol::Tcp(tcp)
+        }
+        protocol => {
+            return Err(message(format!(
+                "unknown protocol '{protocol}', expected udp or tcp"
+            )))
+        }
+    };
+
+    let sink = config.sink.as_deref().unwrap_or(DEFAULT_SINK);
+
+    Ok(Listener {
+        name: Arc::from(name.as_str()),
+        protocol,
+        addrs,
+        port,
+        decode,
+        format,
+        parser,
+        sequence_numbers,
+        ack,
+        plc_timestamp,
+        sink: Arc::from(sink),
+    })
+}
+
+pub fn app_config(config_path: &Path) -> Result<AppConfig, ConfigError> {
+    // read config file
+    let cfg = Config::builder()
+        .set_default("bind", "0.0.0.0")?
+        .set_default("decode_policy", "strict")?
+        .set_default("message_format", "raw")?
+        .set_default("parser", "")?
+        .set_default("sequence_numbers", false)?
+        .set_default("ack", false)?
+        .set_default("plc_timestamp", "none")?
+        .set_default("plc_timestamp_field", "timestamp")?
+        .set_default("plc_timezone", "local")?
+        .set_default("clock_skew_warn_secs", 60)?
+        .set_default("udp_max_datagram_bytes", 1500)?
+        .set_default("udp_reassembly", false)?
+        .set_default("udp_reassembly_timeout_ms", 5000)?
+        .set_default("tcp_port", 0)?
+        .set_default("tcp_framing", "newline")?
+        .set_default("tcp_length_prefix_bytes", 2)?
+        .set_default("tcp_frame_size", 256)?
+        .set_default("tcp_max_connections", 100)?
+        .set_default("tcp_max_frame_bytes", 65536)?
+        .set_default("tcp_idle_timeout_secs", 0)?
+        .set_default("unknown_sources", "log")?
+        .set_default("unknown_source_name", "unknown")?
+        .set_default("default_level", "info")?
+        .set_default("min_level", "trace")?
+        .set_default("rotation_interval", "none")?
+        .set_default("rotation_timezone", "local")?
+        .set_default("rotation_shifts", Vec::<String>::new())?
+        .set_default("rotate_on_size", true)?
+        .set_default("retention_max_age_days", 0)?
+        .set_default("retention_max_total_mb", 0)?
+        .set_default("retention_min_free_mb", 0)?
+        .set_default("retention_check_secs", 300)?
+        .set_default("plc_log_pattern", LOG_PATTERN_PLC)?
+        .set_default("log_path", "plc.log")?
+        .set_default("app_log_path", "plclogger.log")?
+        .set_default("archive_dir", "history")?
+        .set_default("archive_pattern", "plclog_{}.gz")?
+        .set_default("split_by_source", false)?
+        .set_default("source_log_dir", "logs")?
+        .set_default("queue_depth", 10000)?
+        .set_default("decode_workers", 4)?
+        .set_default("stats_interval_secs", 60)?
+        .set_default("stream_port", 0)?
+        .set_default("stream_bind", "127.0.0.1")?
+        .set_default("stream_max_clients", 10)?
+        .set_default("stream_buffer", 1000)?
+        .set_default("http_port", 0)?
+        .set_default("http_bind", "127.0.0.1")?
+        .set_default("http_token", "")?
+        .add_source(config::File::from(config_path).format(FileFormat::Toml))
+        .build()?;
+
+    // check for keys in config.toml file
+    let listener_configs: Vec<ListenerConfig> = match cfg.get("listeners") {
+        Ok(val_ok) => val_ok,
+        Err(ConfigError::NotFound(_)) => Vec::new(),
+        Err(err) => return Err(err),
+    };
+    let parser_configs: Vec<ParserConfig> = match cfg.get("parsers") {
+        Ok(val_ok) => val_ok,
+        Err(ConfigError::NotFound(_)) => Vec::new(),
+        Err(err) => return Err(err),
+    };
+    let layout_configs: Vec<LayoutConfig> = match cfg.get("layouts") {
+        Ok(val_ok) => val_ok,
+        Err(ConfigError::NotFound(_)) => Vec::new(),
+        Err(err) => return Err(err),
+    };
+    let sink_configs: Vec<SinkConfig> = match cfg.get("sinks") {
+        Ok(val_ok) => val_ok,
+        Err(ConfigError::NotFound(_)) => Vec::new(),
+        Err(err) => return Err(err),
+    };
+    let sources: Vec<SourceConfig> = match cfg.get("sources") {
+        Ok(val_ok) => val_ok,
+        Err(ConfigError::NotFound(_)) => Vec::new(),
+        Err(err) => return Err(err),
+    };
+    let severity_rule_configs: Vec<SeverityRuleConfig> = match cfg.get("severity_rules") {
+        Ok(val_ok) => val_ok,
+        Err(ConfigError::NotFound(_)) => Vec::new(),
+        Err(err) => return Err(err),
+    };
+    let unknown_sources = cfg.get_string("unknown_sources")?;
+    let unknown_source_name = cfg.get_string("unknown_source_name")?;
+    let default_level = cfg.get_string("default_level")?;
+    let min_level = cfg.get_string("min_level")?;
+    let log_max_size_mb = cfg.get_int("log_max_size_mb")?;
+    let log_history_to_keep = cfg.get_int("log_history_to_keep")?;
+    let rotation_interval = cfg.get_string("rotation_interval")?;
+    let rotation_timezone = cfg.get_string("rotation_timezone")?;
+    let rotation_shifts: Vec<String> = cfg.get("rotation_shifts")?;
+    let rotate_on_size = cfg.get_bool("rotate_on_size")?;
+    let retention_max_age_days = cfg.get_int("retention_max_age_days")?;
+    let retention_max_total_mb = cfg.get_int("retention_max_total_mb")?;
+    let retention_min_free_mb = cfg.get_int("retention_min_free_mb")?;
+    let retention_check_secs = cfg.get_int("retention_check_secs")?;
+    let plc_log_pattern = cfg.get_string("plc_log_pattern")?;
+    let log_path = cfg.get_string("log_path")?;
+    let app_log_path = cfg.get_string("app_log_path")?;
+    let archive_dir = cfg.get_string("archive_dir")?;
+    let archive_pattern = cfg.get_string("archive_pattern")?;
+    let split_by_source = cfg.get_bool("split_by_source")?;
+    let source_log_dir = cfg.get_string("source_log_dir")?;
+    let queue_depth = cfg.get_int("queue_depth")?;
+    let decode_workers = cfg.get_int("decode_workers")?;
+    let stats_interval_secs = cfg.get_int("stats_interval_secs")?;
+    let stream_port = cfg.get_int("stream_port")?;
+    let stream_bind = cfg.get_string("stream_bind")?;
+    let stream_max_clients = cfg.get_int("stream_max_clients")?;
+    let stream_buffer = cfg.get_int("stream_buffer")?;
+    let http_port = cfg.get_int("http_port")?;
+    let http_bind = cfg.get_string("http_bind")?;
+    let http_token = cfg.get_string("http_token")?;
+    let mut redacted: Value = cfg.clone().try_deserialize()?;
+    redact(&mut redacted);
+
+    // check if values from config.toml file are in valid range
+    // without [[listeners]] the top-level listening_port and tcp_port are used
+    let listener_configs = if listener_configs.is_empty() {
+        let mut listener_configs = vec![ListenerConfig {
+            protocol: String::from("udp"),
+            port: cfg.get_int("listening_port")?,
+            ..Default::default()
+        }];
+        let tcp_port = cfg.get_int("tcp_port")?;
+        if tcp_port != 0 {
+            listener_configs.push(ListenerConfig {
+                protocol: String::from("tcp"),
+                port: tcp_port,
+                ..Default::default()
+            });
+        }
+        listener_configs
+    } else {
+        listener_configs
+    };
+    let mut parsers: Vec<Arc<Parser>> = Vec::new();
+    for parser_config in &parser_configs {
+        let parser = Parser::new(parser_config).map_err(ConfigError::Message)?;
+        if parsers.iter().any(|other| other.name == parser.name) {
+            return Err(ConfigError::Message(format!("duplicate parser name '{}'", parser.name)));
+        }
+        parsers.push(Arc::new(parser));
+    }
+
+    let mut listeners = Vec::new();
+    for listener_config in &listener_configs {
+        let listener = listener(listener_config, &cfg, &parsers)?;
+        if listeners.iter().any(|other: &Arc<Listener>| other.name == listener.name) {
+            return Err(ConfigError::Message(format!(
+                "duplicate listener name '{}', give each listener its own name",
+                listener.name
+            )));
+        }
+        listeners.push(Arc::new(listener));
+    }
+
+    let unknown_name = match unknown_sources.as_str() {
+        "log" => Some(unknown_source_name.as_str()),
+        "reject" => None,
+        _ => {
+            return Err(ConfigError::Message(String::from(
+                "unknown sources must be either 'log' or 'reject'",
+            )))
+        }
+    };
+    let mut layouts: Vec<Arc<Layout>> = Vec::new();
+    for layout_config in &layout_configs {
+        let layout = Layout::new(layout_config).map_err(ConfigError::Message)?;
+        if layouts.iter().any(|other| other.name == layout.name) {
+            return Err(ConfigError::Message(format!("duplicate layout name '{}'", layout.name)));
+        }
+        layouts.push(Arc::new(layout));
+    }
+
+    let min_level = min_level.parse().map_err(ConfigError::Message)?;
+    let sources =
+        SourceMap::new(&sources, unknown_name, min_level, &layouts).map_err(ConfigError::Message)?;
+    let sources = Arc::new(sources);
+
+    let default_level = default_level.parse().map_err(ConfigError::Message)?;
+    let severity_rules =
+        SeverityRules::new(&severity_rule_configs, default_level).map_err(ConfigError::Message)?;
+    let severity_rules = Arc::new(severity_rules);
+
+    let log_max_size_mb = check_log_max_size_mb(log_max_size_mb).map_err(ConfigError::Message)?;
+    let log_history_to_keep =
+        check_log_history_to_keep(log_history_to_keep).map_err(ConfigError::Message)?;
+
+    let rotation_interval = RotationInterval::parse(&rotation_interval, &rotation_shifts)
+        .map_err(ConfigError::Message)?;
+    let rotation_timezone: RotationTz = rotation_timezone.parse().map_err(ConfigError::Message)?;
+    // size is the only trigger when there is no time based rotation
+    let rotate_on_size = rotate_on_size || rotation_interval == RotationInterval::None;
+
+    if !(0..=36500).contains(&retention_max_age_days) {
+        return Err(ConfigError::Message(String::from(
+            "retention max age must be between 0 - 36500 (days)",
+        )));
+    }
+    if !(0..=100_000_000).contains(&retention_max_total_mb) {
+        return Err(ConfigError::Message(String::from(
+            "retention max total must be between 0 - 100000000 (mb)",
+        )));
+    }
+    if !(0..=100_000_000).contains(&retention_min_free_mb) {
+        return Err(ConfigError::Message(String::from(
+            "retention min free must be between 0 - 100000000 (mb)",
+        )));
+    }
+    if retention_min_free_mb > 0 && !FREE_SPACE_KNOWN {
+        return Err(ConfigError::Message(String::from(
+            "retention min free is not supported on this platform, set it to 0",
+        )));
+    }
+    if !(10..=86400).contains(&retention_check_secs) {
+        return Err(ConfigError::Message(String::from(
+            "retention check interval must be between 10 - 86400 (s)",
+        )));
+    }
+    let retention = Retention {
+        max_age_days: retention_max_age_days.try_into().unwrap(),
+        max_total_mb: retention_max_total_mb.try_into().unwrap(),
+        min_free_mb: retention_min_free_mb.try_into().unwrap(),
+        check_secs: retention_check_secs.try_into().unwrap(),
+    };
+
+    // relative paths are taken from the directory of the config file, so the
+    // logger behaves the same no matter where it is started from
+    let config_dir = config_path.parent().unwrap_or(Path::new(""));
+    let app_log_path = config_dir.join(app_log_path);
+    let archive_dir = config_dir.join(archive_dir);
+    let source_log_dir = config_dir.join(source_log_dir);
+
+    // the top-level log keys make up the default sink
+    let mut sinks = vec![Sink {
+        name: String::from(DEFAULT_SINK),
+        log_path: config_dir.join(log_path),
+        archive_pattern: archive_dir.join(archive_pattern),
+        plc_log_pattern: plc_log_pattern.clone(),
+        split_by_source,
+        source_log_dir: source_log_dir.clone(),
+        format: SinkFormat::Text,
+        min_level: Severity::Trace,
+    }];
+    for sink in sink_configs {
+        check_sink_name(&sink.name).map_err(ConfigError::Message)?;
+        if sinks.iter().any(|other| other.name == sink.name) {
+            return Err(ConfigError::Message(format!("duplicate sink name '{}'", sink.name)));
+        }
+        let archive_pattern = match sink.archive_pattern {
+            Some(archive_pattern) => archive_pattern,
+            None => format!("{}_{{}}.gz", sink.name),
+        };
+        let sink_log_dir = match sink.source_log_dir {
+            Some(sink_log_dir) => config_dir.join(sink_log_dir),
+            None => source_log_dir.join(&sink.name),
+        };
+        let format: SinkFormat = match &sink.format {
+            Some(format) => format.parse().map_err(ConfigError::Message)?,
+            None => SinkFormat::Text,
+        };
+        let sink_min_level = match &sink.min_level {
+            Some(level) => level
+                .parse()
+                .map_err(|err| ConfigError::Message(format!("sink '{}': {err}", sink.name)))?,
+            None => Severity::Trace,
+        };
+        // a database holds every source, there are no archives to split
+        let split_by_source = match (format, sink.split_by_source) {
+            (SinkFormat::Sqlite, Some(true)) => {
+                return Err(ConfigError::Message(format!(
+                    "sqlite sink '{}' can not be split by source",
+                    sink.name
+                )))
+            }
+            (SinkFormat::Sqlite, _) => false,
+            (_, sink_split_by_source) => sink_split_by_source.unwrap_or(split_by_source),
+        };
+        sinks.push(Sink {
+            min_level: sink_min_level,
+            format,
+            log_path: config_dir.join(sink.log_path),
+            archive_pattern: archive_dir.join(archive_pattern),
+            plc_log_pattern: sink.plc_log_pattern.unwrap_or_else(|| plc_log_pattern.clone()),
+            split_by_source,
+            source_log_dir: sink_log_dir,
+            name: sink.name,
+        });
+    }
+    for sink in sinks.iter().filter(|sink| sink.format != SinkFormat::Sqlite) {
+        let file_name = sink.archive_pattern.file_name().unwrap_or_default();
+        if !file_name.to_string_lossy().contains("{}") {
+            return Err(ConfigError::Message(format!(
+                "archive pattern of sink '{}' must contain {{}} for the archive index or date",
+                sink.name
+            )));
+        }
+    }
+    if sinks.iter().any(|sink| sink.split_by_source) {
+        check_source_dirs(&sources).map_err(ConfigError::Message)?;
+    }
+    let sink_names: HashSet<&str> = sinks.iter().map(|sink| sink.name.as_str()).collect();
+    for listener in &listeners {
+        if !sink_names.contains(&*listener.sink) {
+            return Err(ConfigError::Message(format!(
+                "listener '{}' writes to unknown sink '{}'",
+                listener.name, listener.sink
+            )));
+        }
+    }
+
+    if !(1..=1_000_000).contains(&queue_depth) {
+        return Err(ConfigError::Message(String::from(
+            "queue depth must be between 1 - 1000000",
+        )));
+    }
+    let queue_depth: usize = queue_depth.try_into().unwrap();
+
+    if !(1..=64).contains(&decode_workers) {
+        return Err(ConfigError::Message(String::from(
+            "decode workers must be between 1 - 64",
+        )));
+    }
+    let decode_workers: usize = decode_workers.try_into().unwrap();
+
+    if !(1..=86400).contains(&stats_interval_secs) {
+        return Err(ConfigError::Message(String::from(
+            "stats interval must be between 1 - 86400 (s)",
+        )));
+    }
+    let stats_interval_secs: u64 = stats_interval_secs.try_into().unwrap();
+
+    let stream = check_stream_settings(stream_port, &stream_bind, stream_max_clients, stream_buffer)
+        .map_err(ConfigError::Message)?;
+    let http = check_http_settings(http_port, &http_bind, http_token).map_err(ConfigError::Message)?;
+
+    Ok(AppConfig {
+        listeners,
+        sinks,
+        sources,
+        severity_rules,
+        log_max_size_mb,
+        log_history_to_keep,
+        rotation_interval,
+        rotation_timezone,
+        rotate_on_size,
+        retention,
+        app_log_path,
+        archive_dir,
+        queue_depth,
+        decode_workers,
+        stats_interval_secs,
+        stream,
+        http,
+        redacted,
+    })
+}
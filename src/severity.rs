@@ -0,0 +1,309 @@
+use log::Level;
+use regex::Regex;
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::parsers::FieldValue;
+use crate::syslog::SyslogHeader;
+
+// level of a plc message, critical is written as an error to the appenders
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    #[default]
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Trace => "TRACE",
+            Severity::Debug => "DEBUG",
+            Severity::Info => "INFO",
+            Severity::Warn => "WARN",
+            Severity::Error => "ERROR",
+            Severity::Critical => "CRITICAL",
+        }
+    }
+
+    // level of the record handed to the appenders, available in the pattern as {l}
+    pub fn log_level(&self) -> Level {
+        match self {
+            Severity::Trace => Level::Trace,
+            Severity::Debug => Level::Debug,
+            Severity::Info => Level::Info,
+            Severity::Warn => Level::Warn,
+            Severity::Error | Severity::Critical => Level::Error,
+        }
+    }
+
+    // syslog severities folded into the six levels
+    pub fn from_syslog(syslog: &SyslogHeader) -> Severity {
+        match syslog.severity {
+            0..=2 => Severity::Critical,
+            3 => Severity::Error,
+            4 => Severity::Warn,
+            5 | 6 => Severity::Info,
+            _ => Severity::Debug,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// case is ignored and the usual abbreviations are accepted, plcs send levels in all forms
+impl FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "trace" => Ok(Severity::Trace),
+            "debug" => Ok(Severity::Debug),
+            "info" | "information" | "notice" => Ok(Severity::Info),
+            "warn" | "warning" => Ok(Severity::Warn),
+            "error" | "err" => Ok(Severity::Error),
+            "critical" | "crit" | "fatal" | "alarm" => Ok(Severity::Critical),
+            _ => Err(format!(
+                "unknown level '{s}', expected one of: trace, debug, info, warn, error, critical"
+            )),
+        }
+    }
+}
+
+// a [[severity_rules]] entry from config.toml, set one of prefix, field or regex
+#[derive(Debug, Deserialize)]
+pub struct SeverityRuleConfig {
+    pub level: Option<String>,
+    // start of the message, case sensitive
+    pub prefix: Option<String>,
+    // parsed field, compared to value when set. without a value a field that is there
+    // gets the level of the rule, or is read as the level itself when the rule has none
+    pub field: Option<String>,
+    pub value: Option<String>,
+    pub regex: Option<String>,
+}
+
+#[derive(Debug)]
+enum Matcher {
+    Prefix(String),
+    Field { name: String, value: Option<String> },
+    Regex(Regex),
+}
+
+#[derive(Debug)]
+struct Rule {
+    matcher: Matcher,
+    // None for field rules taking the level from the field value
+    level: Option<Severity>,
+}
+
+// rules deciding the level of every message, the first matching rule wins
+#[derive(Debug, Default)]
+pub struct SeverityRules {
+    rules: Vec<Rule>,
+    // level of messages no rule matches, syslog messages keep their own severity
+    default: Severity,
+}
+
+impl SeverityRules {
+    pub fn new(configs: &[SeverityRuleConfig], default: Severity) -> Result<SeverityRules, String> {
+        let mut rules = Vec::new();
+        for (index, config) in configs.iter().enumerate() {
+            let rule = index + 1;
+            let matcher = match (&config.prefix, &config.field, &config.regex) {
+                (Some(prefix), None, None) => Matcher::Prefix(prefix.clone()),
+                (None, Some(field), None) => Matcher::Field {
+                    name: field.clone(),
+                    value: config.value.clone(),
+                },
+                (None, None, Some(regex)) => Matcher::Regex(
+                    Regex::new(regex).map_err(|err| format!("severity rule {rule}: {err}"))?,
+                ),
+                _ => {
+                    return Err(format!(
+                        "severity rule {rule} must have exactly one of prefix, field or regex"
+                    ))
+                }
+            };
+            if config.value.is_some() && config.field.is_none() {
+                return Err(format!("severity rule {rule}: value is only used with field"));
+            }
+            let level = match &config.level {
+                Some(level) => Some(
+                    level
+                        .parse()
+                        .map_err(|err| format!("severity rule {rule}: {err}"))?,
+                ),
+                None => None,
+            };
+            let takes_field_value = matches!(matcher, Matcher::Field { value: None, .. });
+            if level.is_none() && !takes_field_value {
+                return Err(format!("severity rule {rule} needs a level"));
+            }
+            rules.push(Rule { matcher, level });
+        }
+        Ok(SeverityRules { rules, default })
+    }
+
+    pub fn classify(
+        &self,
+        payload: &str,
+        parsed: &[(String, FieldValue)],
+        syslog: Option<&SyslogHeader>,
+    ) -> Severity {
+        for rule in &self.rules {
+            let level = match &rule.matcher {
+                Matcher::Prefix(prefix) => payload.starts_with(prefix.as_str()).then_some(rule.level),
+                Matcher::Regex(regex) => regex.is_match(payload).then_some(rule.level),
+                Matcher::Field { name, value } => {
+                    let field = parsed.iter().find(|(key, _)| key == name);
+                    match (field, value) {
+                        (Some((_, field)), Some(value)) => {
+                            field.to_string().eq_ignore_ascii_case(value).then_some(rule.level)
+                        }
+                        (Some((_, field)), None) => match rule.level {
+                            Some(_) => Some(rule.level),
+                            // a field value that is no level does not match
+                            None => field.to_string().parse().ok().map(Some),
+                        },
+                        (None, _) => None,
+                    }
+                }
+            };
+            if let Some(Some(level)) = level {
+                return level;
+            }
+        }
+        match syslog {
+            Some(syslog) => Severity::from_syslog(syslog),
+            None => self.default,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(level: Option<&str>) -> SeverityRuleConfig {
+        SeverityRuleConfig {
+            level: level.map(str::to_string),
+            prefix: None,
+            field: None,
+            value: None,
+            regex: None,
+        }
+    }
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<(String, FieldValue)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), FieldValue::Str(value.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn field_with_value() {
+        let mut estop = rule(Some("critical"));
+        estop.field = Some(String::from("CODE"));
+        estop.value = Some(String::from("E-STOP"));
+        let rules = SeverityRules::new(&[estop], Severity::Info).unwrap();
+        let level = rules.classify("", &fields(&[("CODE", "e-stop")]), None);
+        assert_eq!(level, Severity::Critical);
+        let level = rules.classify("", &fields(&[("CODE", "RUN")]), None);
+        assert_eq!(level, Severity::Info);
+    }
+
+    #[test]
+    fn field_holding_the_level() {
+        let mut lvl = rule(None);
+        lvl.field = Some(String::from("LVL"));
+        let rules = SeverityRules::new(&[lvl], Severity::Info).unwrap();
+        let level = rules.classify("", &fields(&[("LVL", "warning")]), None);
+        assert_eq!(level, Severity::Warn);
+        // no level, no match
+        let level = rules.classify("", &fields(&[("LVL", "high")]), None);
+        assert_eq!(level, Severity::Info);
+    }
+
+    #[test]
+    fn field_present_with_the_level_of_the_rule() {
+        let mut alarm = rule(Some("error"));
+        alarm.field = Some(String::from("ALARM"));
+        let rules = SeverityRules::new(&[alarm], Severity::Info).unwrap();
+        let level = rules.classify("", &fields(&[("ALARM", "door open")]), None);
+        assert_eq!(level, Severity::Error);
+        let level = rules.classify("", &fields(&[("STATE", "door open")]), None);
+        assert_eq!(level, Severity::Info);
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let mut alarm = rule(Some("error"));
+        alarm.prefix = Some(String::from("ALARM"));
+        let mut heartbeat = rule(Some("trace"));
+        heartbeat.regex = Some(String::from("(?i)heartbeat"));
+        let rules = SeverityRules::new(&[alarm, heartbeat], Severity::Info).unwrap();
+        assert_eq!(
+            rules.classify("ALARM heartbeat lost", &[], None),
+            Severity::Error
+        );
+        assert_eq!(rules.classify("Heartbeat", &[], None), Severity::Trace);
+        assert_eq!(rules.classify("motor on", &[], None), Severity::Info);
+        // syslog messages keep their own severity
+        let syslog = SyslogHeader {
+            severity: 4,
+            ..Default::default()
+        };
+        assert_eq!(
+            rules.classify("motor on", &[], Some(&syslog)),
+            Severity::Warn
+        );
+    }
+
+    #[test]
+    fn invalid_rules() {
+        let mut two = rule(Some("error"));
+        two.prefix = Some(String::from("ALARM"));
+        two.regex = Some(String::from("ALARM"));
+        let mut value_without_field = rule(Some("error"));
+        value_without_field.prefix = Some(String::from("ALARM"));
+        value_without_field.value = Some(String::from("1"));
+        let mut no_level = rule(None);
+        no_level.prefix = Some(String::from("ALARM"));
+        let mut unknown_level = rule(Some("loud"));
+        unknown_level.prefix = Some(String::from("ALARM"));
+        let cases = [
+            (
+                rule(Some("error")),
+                "severity rule 1 must have exactly one of prefix, field or regex",
+            ),
+            (
+                two,
+                "severity rule 1 must have exactly one of prefix, field or regex",
+            ),
+            (
+                value_without_field,
+                "severity rule 1: value is only used with field",
+            ),
+            (no_level, "severity rule 1 needs a level"),
+            (
+                unknown_level,
+                "severity rule 1: unknown level 'loud', expected one of: trace, debug, info, warn, error, critical",
+            ),
+        ];
+        for (config, err) in cases {
+            let rules = SeverityRules::new(&[config], Severity::Info);
+            assert_eq!(rules.unwrap_err(), err);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::severity::Severity;
+
+// sink written by listeners that do not name one, configured by the top-level log keys
+pub const DEFAULT_SINK: &str = "plc";
+
+// a [[sinks]] entry from config.toml
+#[derive(Debug, Deserialize)]
+pub struct SinkConfig {
+    pub name: String,
+    pub log_path: String,
+    // defaults to <name>_{}.gz in archive_dir
+    pub archive_pattern: Option<String>,
+    pub plc_log_pattern: Option<String>,
+    pub split_by_source: Option<bool>,
+    // defaults to <source_log_dir>/<name>
+    pub source_log_dir: Option<String>,
+    // text (plc_log_pattern), json (one object per line) or sqlite (log_path is the database)
+    pub format: Option<String>,
+    // messages below this level are left out of the sink
+    pub min_level: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkFormat {
+    Text,
+    // structured, parsed fields keep their types
+    Json,
+    // rows of a sqlite database at log_path
+    Sqlite,
+}
+
+impl FromStr for SinkFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(SinkFormat::Text),
+            "json" => Ok(SinkFormat::Json),
+            "sqlite" => Ok(SinkFormat::Sqlite),
+            _ => Err(format!("unknown sink format '{s}', expected one of: text, json, sqlite")),
+        }
+    }
+}
+
+// a plc log written by the writer, listeners choose theirs by name
+#[derive(Debug, Clone)]
+pub struct Sink {
+    pub name: String,
+    pub log_path: PathBuf,
+    // full path of the archives, {} is replaced with the archive index or date
+    pub archive_pattern: PathBuf,
+    pub plc_log_pattern: String,
+    pub split_by_source: bool,
+    pub source_log_dir: PathBuf,
+    pub format: SinkFormat,
+    pub min_level: Severity,
+}
+
+// sink names end up in file names, keep them plain
+pub fn check_sink_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(format!(
+            "sink name '{name}' may only contain letters, digits, '-' and '_'"
+        ));
+    }
+    Ok(())
+}
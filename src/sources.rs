@@ -0,0 +1,406 @@
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use crate::layouts::Layout;
+use crate::settings::{check_log_history_to_keep, check_log_max_size_mb};
+use crate::severity::Severity;
+
+// a [[sources]] entry from config.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct SourceConfig {
+    pub name: String,
+    // single address or a cidr range, ie. 10.0.1.20 or 10.0.1.0/24
+    pub address: String,
+    #[serde(default)]
+    pub area: String,
+    #[serde(default)]
+    pub line: String,
+    // overrides of the global rotation when logs are split by source
+    pub log_max_size_mb: Option<i64>,
+    pub log_history_to_keep: Option<i64>,
+    // messages below this level are not written, defaults to the global min_level
+    pub min_level: Option<String>,
+    // name of a [[layouts]] entry, the plc sends binary memory blocks
+    pub layout: Option<String>,
+}
+
+// a named plc, attached to every record received from it
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Source {
+    pub name: String,
+    pub area: String,
+    pub line: String,
+    pub log_max_size_mb: Option<u128>,
+    pub log_history_to_keep: Option<u32>,
+    pub min_level: Severity,
+    pub layout: Option<Arc<Layout>>,
+}
+
+// address range matched against the sender of a packet
+#[derive(Debug)]
+struct AddressRange {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl AddressRange {
+    fn parse(address: &str) -> Result<AddressRange, String> {
+        let (ip, prefix) = match address.split_once('/') {
+            Some((ip, prefix)) => (ip, Some(prefix)),
+            None => (address, None),
+        };
+
+        let network: IpAddr = match ip.trim().parse() {
+            Ok(val_ok) => normalize(val_ok),
+            Err(_) => return Err(format!("invalid source address '{address}'")),
+        };
+
+        let max_prefix = match network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = match prefix {
+            Some(prefix) => match prefix.trim().parse::<u8>() {
+                Ok(val_ok) if val_ok <= max_prefix => val_ok,
+                _ => return Err(format!("invalid prefix length in source address '{address}'")),
+            },
+            None => max_prefix,
+        };
+
+        Ok(AddressRange { network, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+// ipv4 senders received on a dual-stack socket show up as ipv4-mapped ipv6 addresses
+pub fn normalize(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => ip,
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+// sender address as shown in the logs, without the ipv4-mapped prefix
+pub fn normalize_addr(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(normalize(addr.ip()), addr.port())
+}
+
+// maps sender addresses to named sources
+#[derive(Debug)]
+pub struct SourceMap {
+    entries: Vec<(AddressRange, Arc<Source>)>,
+    // bucket used for senders that are not configured, None rejects them
+    unknown: Option<Arc<Source>>,
+}
+
+impl SourceMap {
+    pub fn new(
+        sources: &[SourceConfig],
+        unknown_name: Option<&str>,
+        min_level: Severity,
+        layouts: &[Arc<Layout>],
+    ) -> Result<SourceMap, String> {
+        let mut entries = Vec::new();
+        for source in sources {
+            if source.name.trim().is_empty() {
+                return Err(format!("source for '{}' must have a name", source.address));
+            }
+            let range = AddressRange::parse(&source.address)?;
+            let log_max_size_mb = match source.log_max_size_mb {
+                Some(val) => Some(
+                    check_log_max_size_mb(val).map_err(|err| format!("source '{}': {err}", source.name))?,
+                ),
+                None => None,
+            };
+            let log_history_to_keep = match source.log_history_to_keep {
+                Some(val) => Some(
+                    check_log_history_to_keep(val)
+                        .map_err(|err| format!("source '{}': {err}", source.name))?,
+                ),
+                None => None,
+            };
+            let source_min_level = match &source.min_level {
+                Some(level) => level.parse().map_err(|err| format!("source '{}': {err}", source.name))?,
+                None => min_level,
+            };
+            let layout = match &source.layout {
+                Some(name) => match layouts.iter().find(|layout| layout.name == *name) {
+                    Some(layout) => Some(Arc::clone(layout)),
+                    None => return Err(format!("source '{}' uses unknown layout '{name}'", source.name)),
+                },
+                None => None,
+            };
+            let named = Arc::new(Source {
+                name: source.name.clone(),
+                area: source.area.clone(),
+                line: source.line.clone(),
+                log_max_size_mb,
+                log_history_to_keep,
+                min_level: source_min_level,
+                layout,
+            });
+            entries.push((range, named));
+        }
+
+        // the most specific range wins when ranges overlap
+        entries.sort_by_key(|(range, _)| Reverse(range.prefix));
+
+        let unknown = unknown_name.map(|name| {
+            Arc::new(Source {
+                name: name.to_string(),
+                min_level,
+                ..Default::default()
+            })
+        });
+
+        Ok(SourceMap { entries, unknown })
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Option<Arc<Source>> {
+        let ip = normalize(ip);
+        match self.entries.iter().find(|(range, _)| range.contains(ip)) {
+            Some((_, source)) => Some(Arc::clone(source)),
+            None => self.unknown.as_ref().map(Arc::clone),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // one entry per source name, including the unknown bucket last. entries are kept
+    // most specific range first, so a name configured for more than one range takes
+    // its settings from the longest prefix, and from the first configured of equal ones
+    pub fn sources(&self) -> Vec<Arc<Source>> {
+        let mut sources: Vec<Arc<Source>> = Vec::new();
+        let all = self.entries.iter().map(|(_, source)| source).chain(self.unknown.iter());
+        for source in all {
+            if !sources.iter().any(|s| s.name == source.name) {
+                sources.push(Arc::clone(source));
+            }
+        }
+        sources
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    fn config(name: &str, address: &str, area: &str) -> SourceConfig {
+        SourceConfig {
+            name: name.to_string(),
+            address: address.to_string(),
+            area: area.to_string(),
+            line: String::new(),
+            log_max_size_mb: None,
+            log_history_to_keep: None,
+            min_level: None,
+            layout: None,
+        }
+    }
+
+    fn map(sources: &[SourceConfig], unknown_name: Option<&str>) -> Result<SourceMap, String> {
+        SourceMap::new(sources, unknown_name, Severity::Info, &[])
+    }
+
+    fn name_of(sources: &SourceMap, sender: &str) -> Option<String> {
+        sources.lookup(ip(sender)).map(|source| source.name.clone())
+    }
+
+    #[test]
+    fn address_ranges() {
+        for (address, inside, outside) in [
+            (
+                "10.0.1.20",
+                vec!["10.0.1.20", "::ffff:10.0.1.20"],
+                vec!["10.0.1.21", "::1"],
+            ),
+            (
+                " 10.0.1.7 / 24 ",
+                vec!["10.0.1.0", "10.0.1.255"],
+                vec!["10.0.0.255", "10.0.2.0"],
+            ),
+            (
+                "10.0.0.0/8",
+                vec!["10.255.255.255"],
+                vec!["11.0.0.0", "9.255.255.255"],
+            ),
+            (
+                "0.0.0.0/0",
+                vec!["0.0.0.0", "255.255.255.255"],
+                vec!["::", "fd00::1"],
+            ),
+            // written ipv4-mapped in the config, matched as ipv4
+            ("::ffff:10.0.1.20", vec!["10.0.1.20"], vec!["10.0.1.21"]),
+            (
+                "fd00::/8",
+                vec!["fd12::1", "fdff:ffff::"],
+                vec!["fe80::1", "10.0.0.1"],
+            ),
+            ("fd00::1/128", vec!["fd00::1"], vec!["fd00::2"]),
+            ("::/0", vec!["::1", "fe80::1"], vec!["10.0.0.1"]),
+        ] {
+            let range = AddressRange::parse(address).unwrap();
+            for sender in inside {
+                assert!(
+                    range.contains(normalize(ip(sender))),
+                    "{sender} in {address}"
+                );
+            }
+            for sender in outside {
+                assert!(
+                    !range.contains(normalize(ip(sender))),
+                    "{sender} not in {address}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_address_ranges() {
+        for address in ["", "plc1", "10.0.1", "10.0.1/24", "10.0.1.256"] {
+            assert_eq!(
+                AddressRange::parse(address).unwrap_err(),
+                format!("invalid source address '{address}'"),
+                "{address}"
+            );
+        }
+        for address in [
+            "10.0.1.0/33",
+            "10.0.1.0/",
+            "10.0.1.0/-1",
+            "10.0.1.0/x",
+            "fd00::/",
+            "fd00::/129",
+            "::ffff:10.0.0.0/104",
+        ] {
+            assert_eq!(
+                AddressRange::parse(address).unwrap_err(),
+                format!("invalid prefix length in source address '{address}'")
+            );
+        }
+    }
+
+    #[test]
+    fn ipv4_mapped_senders() {
+        assert_eq!(normalize(ip("::ffff:10.0.1.20")), ip("10.0.1.20"));
+        assert_eq!(normalize(ip("10.0.1.20")), ip("10.0.1.20"));
+        assert_eq!(normalize(ip("fd00::1")), ip("fd00::1"));
+        // ipv4-compatible addresses are real ipv6 senders
+        assert_eq!(normalize(ip("::10.0.1.20")), ip("::10.0.1.20"));
+        let addr: SocketAddr = "[::ffff:10.0.1.20]:5140".parse().unwrap();
+        assert_eq!(normalize_addr(addr), "10.0.1.20:5140".parse().unwrap());
+    }
+
+    #[test]
+    fn most_specific_range_wins() {
+        // broadest first, the order in the config does not matter
+        let sources = map(
+            &[
+                config("plant", "10.0.0.0/8", ""),
+                config("hall", "10.0.1.0/24", ""),
+                config("press", "10.0.1.20", ""),
+                config("hall 2", "10.0.2.0/24", ""),
+                config("also hall 2", "10.0.2.0/24", ""),
+            ],
+            None,
+        )
+        .unwrap();
+        for (sender, name) in [
+            ("10.0.1.20", Some("press")),
+            ("::ffff:10.0.1.20", Some("press")),
+            ("10.0.1.21", Some("hall")),
+            ("::ffff:10.0.1.21", Some("hall")),
+            ("10.9.0.1", Some("plant")),
+            // equal ranges, the first configured wins
+            ("10.0.2.1", Some("hall 2")),
+            ("192.168.0.1", None),
+            ("::1", None),
+        ] {
+            assert_eq!(name_of(&sources, sender).as_deref(), name, "{sender}");
+        }
+        assert_eq!(sources.len(), 5);
+    }
+
+    #[test]
+    fn unknown_senders() {
+        let sources = map(&[config("press", "10.0.1.20", "")], Some("unknown")).unwrap();
+        assert_eq!(name_of(&sources, "10.0.1.20").as_deref(), Some("press"));
+        assert_eq!(name_of(&sources, "10.0.1.21").as_deref(), Some("unknown"));
+        let unknown = sources.lookup(ip("fd00::1")).unwrap();
+        assert_eq!(unknown.min_level, Severity::Info);
+        assert_eq!(unknown.area, "");
+    }
+
+    #[test]
+    fn one_source_per_name() {
+        let sources = map(
+            &[
+                config("hall", "10.0.1.0/24", "broad"),
+                config("press", "10.0.2.20", ""),
+                config("hall", "10.0.3.1", "narrow"),
+            ],
+            Some("unknown"),
+        )
+        .unwrap();
+        let listed: Vec<(String, String)> = sources
+            .sources()
+            .iter()
+            .map(|source| (source.name.clone(), source.area.clone()))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                (String::from("press"), String::new()),
+                (String::from("hall"), String::from("narrow")),
+                (String::from("unknown"), String::new()),
+            ]
+        );
+        // every range still matches with its own settings
+        assert_eq!(sources.lookup(ip("10.0.1.5")).unwrap().area, "broad");
+    }
+
+    #[test]
+    fn invalid_sources() {
+        let mut unnamed = config(" ", "10.0.1.20", "");
+        assert_eq!(
+            map(&[unnamed.clone()], None).unwrap_err(),
+            "source for '10.0.1.20' must have a name"
+        );
+        unnamed.name = String::from("press");
+        unnamed.layout = Some(String::from("blocks"));
+        assert_eq!(
+            map(&[unnamed.clone()], None).unwrap_err(),
+            "source 'press' uses unknown layout 'blocks'"
+        );
+        unnamed.layout = None;
+        unnamed.min_level = Some(String::from("loud"));
+        assert!(map(&[unnamed], None)
+            .unwrap_err()
+            .starts_with("source 'press': "));
+        assert!(map(&[config("press", "10.0.1.20/33", "")], None).is_err());
+    }
+}
@@ -0,0 +1,394 @@
+use chrono::{DateTime, TimeZone, Utc};
+use log::{error, info, warn};
+use rusqlite::{params, Connection};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::record::PlcRecord;
+use crate::retention::{free_space, Retention};
+
+// times are stored in utc with this format, so they sort and compare as text and
+// work with the sqlite date functions
+pub const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.6f";
+
+// rows written in one transaction at most, a transaction otherwise ends with every
+// batch the writer takes from the queue
+const MAX_BATCH: usize = 1000;
+
+// rows deleted at once when a size limit is exceeded
+const PRUNE_BATCH: usize = 1000;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS records (
+    id INTEGER PRIMARY KEY,
+    received TEXT NOT NULL,
+    plc_time TEXT,
+    source TEXT NOT NULL,
+    area TEXT NOT NULL,
+    line TEXT NOT NULL,
+    src TEXT NOT NULL,
+    listener TEXT NOT NULL,
+    level TEXT NOT NULL,
+    seq INTEGER,
+    message TEXT NOT NULL,
+    fields TEXT
+);
+CREATE INDEX IF NOT EXISTS records_received ON records (received);
+CREATE INDEX IF NOT EXISTS records_source ON records (source, received);
+";
+
+// plc history in a sqlite database, one row per record with the parsed fields as json
+#[derive(Debug)]
+pub struct SqliteSink {
+    conn: Connection,
+    // rows in the open transaction
+    pending: usize,
+    // rows of transactions that were rolled back since the last commit, reported by it
+    lost: usize,
+}
+
+impl SqliteSink {
+    pub fn open(path: &Path) -> Result<SqliteSink, String> {
+        let conn = open(path).map_err(|err| format!("failed to open {}: {err}", path.display()))?;
+        Ok(SqliteSink {
+            conn,
+            pending: 0,
+            lost: 0,
+        })
+    }
+
+    pub fn insert(&mut self, record: &PlcRecord) -> Result<(), String> {
+        // sqlite tells whether a transaction is open, it rolls back on its own after
+        // some errors (disk full, i/o errors)
+        if self.conn.is_autocommit() {
+            self.lost += std::mem::take(&mut self.pending);
+            self.conn
+                .execute_batch("BEGIN")
+                .map_err(|err| err.to_string())?;
+        }
+        let fields = (!record.parsed.is_empty()).then(|| record.parsed_json().to_string());
+        self.conn
+            .prepare_cached(
+                "INSERT INTO records (received, plc_time, source, area, line, src, listener, level, seq, message, fields)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            )
+            .and_then(|mut stmt| {
+                stmt.execute(params![
+                    format_time(&record.received),
+                    record.plc_time.as_ref().map(format_time),
+                    record.source.name,
+                    record.source.area,
+                    record.source.line,
+                    record.src.to_string(),
+                    &*record.listener,
+                    record.level.as_str(),
+                    record.seq.map(|seq| seq as i64),
+                    record.payload,
+                    fields,
+                ])
+            })
+            .map_err(|err| err.to_string())?;
+        self.pending += 1;
+        if self.pending >= MAX_BATCH {
+            // rows lost here are reported by the next commit, before their acks go out
+            self.end_transaction();
+        }
+        Ok(())
+    }
+
+    // with synchronous = full the rows are on disk once this returns, an error means
+    // rows written since the last commit are gone
+    pub fn commit(&mut self) -> Result<(), String> {
+        self.end_transaction();
+        match std::mem::take(&mut self.lost) {
+            0 => Ok(()),
+            lost => Err(format!("{lost} rows rolled back")),
+        }
+    }
+
+    fn end_transaction(&mut self) {
+        let pending = std::mem::take(&mut self.pending);
+        if self.conn.is_autocommit() {
+            self.lost += pending;
+            return;
+        }
+        if let Err(err) = self.conn.execute_batch("COMMIT") {
+            error!("Failed to commit {pending} rows: {err}");
+            // a failed commit may leave the transaction open, which would fail every
+            // further insert
+            if !self.conn.is_autocommit() {
+                if let Err(err) = self.conn.execute_batch("ROLLBACK") {
+                    error!("Failed to roll back: {err}");
+                }
+            }
+            self.lost += pending;
+        }
+    }
+}
+
+pub fn format_time<Tz: TimeZone>(time: &DateTime<Tz>) -> String {
+    time.with_timezone(&Utc).format(TIME_FORMAT).to_string()
+}
+
+fn open(path: &Path) -> rusqlite::Result<Connection> {
+    if let Some(dir) = path.parent() {
+        // a missing directory shows up as the open error below
+        let _ = fs::create_dir_all(dir);
+    }
+    let conn = Connection::open(path)?;
+    // the pruner and queries run next to the writer
+    conn.busy_timeout(Duration::from_secs(10))?;
+    // auto_vacuum only takes effect on a new database, it lets the pruner hand
+    // deleted rows back to the disk
+    conn.execute_batch(
+        "PRAGMA auto_vacuum = INCREMENTAL;
+         PRAGMA journal_mode = WAL;
+         PRAGMA synchronous = FULL;",
+    )?;
+    conn.execute_batch(SCHEMA)?;
+    Ok(conn)
+}
+
+// the retention limits of the archives applied to the rows of a database, oldest first
+pub fn prune(retention: &Retention, path: &Path) {
+    if !path.exists() {
+        return;
+    }
+    let conn = match open(path) {
+        Ok(conn) => conn,
+        Err(err) => {
+            error!("Failed to open {} for pruning: {err}", path.display());
+            return;
+        }
+    };
+    if let Err(err) = prune_rows(&conn, retention, path) {
+        error!("Failed to prune {}: {err}", path.display());
+    }
+}
+
+fn prune_rows(conn: &Connection, retention: &Retention, path: &Path) -> rusqlite::Result<()> {
+    if retention.max_age_days > 0 {
+        let cutoff = Utc::now() - chrono::Duration::days(retention.max_age_days as i64);
+        let deleted = conn.execute(
+            "DELETE FROM records WHERE received < ?1",
+            params![format_time(&cutoff)],
+        )?;
+        if deleted > 0 {
+            info!(
+                "Pruned {deleted} rows from {}: older than {} days",
+                path.display(),
+                retention.max_age_days
+            );
+            if !vacuum(conn)? {
+                warn!("{} not shrunk, a reader holds the write-ahead log", path.display());
+            }
+        }
+    }
+
+    if retention.max_total_mb > 0 {
+        let max_total = retention.max_total_mb * 1024 * 1024;
+        prune_oldest(
+            conn,
+            path,
+            &format!("database above {} mb", retention.max_total_mb),
+            || size(path) > max_total,
+        )?;
+    }
+
+    if retention.min_free_mb > 0 {
+        let min_free = retention.min_free_mb * 1024 * 1024;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        prune_oldest(
+            conn,
+            path,
+            &format!("less than {} mb free", retention.min_free_mb),
+            || free_space(dir).is_some_and(|free| free < min_free),
+        )?;
+    }
+    Ok(())
+}
+
+// delete the oldest rows while over a limit. the files can not shrink while a reader
+// holds the write-ahead log, pruning stops at the first pass that frees nothing and
+// is tried again at the next check
+fn prune_oldest(
+    conn: &Connection,
+    path: &Path,
+    reason: &str,
+    over_limit: impl Fn() -> bool,
+) -> rusqlite::Result<()> {
+    let mut pruned = 0;
+    while over_limit() {
+        let before = size(path);
+        let deleted = delete_oldest(conn)?;
+        if deleted == 0 {
+            warn!("No rows left to prune in {}: {reason}", path.display());
+            break;
+        }
+        pruned += deleted;
+        if !vacuum(conn)? || size(path) >= before {
+            warn!(
+                "Pruning {} freed no space, a reader holds the write-ahead log: {reason}",
+                path.display()
+            );
+            break;
+        }
+    }
+    if pruned > 0 {
+        info!("Pruned {pruned} rows from {}: {reason}", path.display());
+    }
+    Ok(())
+}
+
+fn delete_oldest(conn: &Connection) -> rusqlite::Result<usize> {
+    conn.execute(
+        "DELETE FROM records WHERE id IN (SELECT id FROM records ORDER BY received LIMIT ?1)",
+        params![PRUNE_BATCH as i64],
+    )
+}
+
+// give the pages of deleted rows back to the disk, the database file shrinks once the
+// write-ahead log is checkpointed. false when a reader kept the checkpoint from
+// completing
+fn vacuum(conn: &Connection) -> rusqlite::Result<bool> {
+    // the vacuum works through its result rows
+    let mut stmt = conn.prepare("PRAGMA incremental_vacuum")?;
+    let mut rows = stmt.query([])?;
+    while rows.next()?.is_some() {}
+    let busy: i64 = conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))?;
+    Ok(busy == 0)
+}
+
+// size of the database with its write-ahead log
+pub fn size(path: &Path) -> u64 {
+    let mut wal = PathBuf::from(path);
+    wal.as_mut_os_string().push("-wal");
+    [path, wal.as_path()]
+        .iter()
+        .filter_map(|path| fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::severity::Severity;
+    use crate::sources::Source;
+    use std::sync::Arc;
+
+    fn test_db(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("plclogger-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("plc.db")
+    }
+
+    fn record(received: DateTime<Utc>, payload: &str) -> PlcRecord {
+        PlcRecord {
+            received: received.into(),
+            plc_time: None,
+            src: "10.0.1.1:4000".parse().unwrap(),
+            source: Arc::new(Source::default()),
+            listener: Arc::from("udp"),
+            sink: Arc::from("db"),
+            payload: payload.to_string(),
+            len: payload.len(),
+            level: Severity::Info,
+            syslog: None,
+            seq: None,
+            parsed: Vec::new(),
+            ack: None,
+            sequence_event: None,
+        }
+    }
+
+    fn retention(max_age_days: u64, max_total_mb: u64) -> Retention {
+        Retention {
+            max_age_days,
+            max_total_mb,
+            min_free_mb: 0,
+            check_secs: 60,
+        }
+    }
+
+    fn count(path: &Path) -> i64 {
+        let conn = Connection::open(path).unwrap();
+        conn.query_row("SELECT count(*) FROM records", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    // 3 mb of rows, the first ones oldest
+    fn fill(sink: &mut SqliteSink, rows: usize) {
+        let payload = "x".repeat(3 * 1024 * 1024 / rows);
+        let start = Utc::now() - chrono::Duration::hours(1);
+        for row in 0..rows {
+            let received = start + chrono::Duration::milliseconds(row as i64);
+            sink.insert(&record(received, &payload)).unwrap();
+        }
+        sink.commit().unwrap();
+    }
+
+    #[test]
+    fn prune_by_age() {
+        let path = test_db("sqlite-age");
+        let mut sink = SqliteSink::open(&path).unwrap();
+        let now = Utc::now();
+        for days in [40, 31, 29, 0] {
+            let received = now - chrono::Duration::days(days);
+            sink.insert(&record(received, &format!("{days} days ago")))
+                .unwrap();
+        }
+        sink.commit().unwrap();
+        prune(&retention(30, 0), &path);
+        let conn = Connection::open(&path).unwrap();
+        let mut stmt = conn
+            .prepare("SELECT message FROM records ORDER BY received")
+            .unwrap();
+        let left: Vec<String> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(left, ["29 days ago", "0 days ago"]);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn prune_by_size() {
+        let path = test_db("sqlite-size");
+        let mut sink = SqliteSink::open(&path).unwrap();
+        fill(&mut sink, 6000);
+        assert!(size(&path) > 3 * 1024 * 1024);
+        prune(&retention(0, 1), &path);
+        assert!(size(&path) <= 1024 * 1024);
+        // the newest rows are kept
+        let left = count(&path);
+        assert!(left > 0 && left < 6000);
+        let conn = Connection::open(&path).unwrap();
+        let newest: i64 = conn
+            .query_row("SELECT max(id) FROM records", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(newest, 6000);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn prune_stops_while_a_reader_holds_the_wal() {
+        let path = test_db("sqlite-reader");
+        let mut sink = SqliteSink::open(&path).unwrap();
+        fill(&mut sink, 6000);
+        let reader = Connection::open(&path).unwrap();
+        reader.execute_batch("BEGIN").unwrap();
+        let _: i64 = reader
+            .query_row("SELECT count(*) FROM records", [], |row| row.get(0))
+            .unwrap();
+        prune(&retention(0, 1), &path);
+        // one batch went before the checkpoint came back busy
+        assert_eq!(count(&path), 6000 - PRUNE_BATCH as i64);
+        reader.execute_batch("COMMIT").unwrap();
+        prune(&retention(0, 1), &path);
+        assert!(size(&path) <= 1024 * 1024);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,236 @@
+use chrono::{DateTime, Local, Utc};
+use log::info;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::metrics::Histogram;
+use crate::plc_time::ClockTracker;
+use crate::record::{PlcRecord, RECEIVED_FORMAT};
+use crate::sequence::SequenceTracker;
+use crate::severity::Severity;
+
+// counters shared between the pipeline threads
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub received: AtomicU64,
+    pub dropped: AtomicU64,
+    pub truncated: AtomicU64,
+    pub reassembled: AtomicU64,
+    pub fragments_expired: AtomicU64,
+    // incomplete messages discarded to stay within the reassembly limits
+    pub fragments_evicted: AtomicU64,
+    pub rejected: AtomicU64,
+    pub unknown_rejected: AtomicU64,
+    pub transcoded: AtomicU64,
+    pub parse_failed: AtomicU64,
+    pub written: AtomicU64,
+    // below the minimum level of their source or sink
+    pub filtered: AtomicU64,
+    // records the sinks failed to take, not acked
+    pub write_failed: AtomicU64,
+    // batches that could not be made durable
+    pub sync_failed: AtomicU64,
+    pub acked: AtomicU64,
+    pub duplicates_suppressed: AtomicU64,
+    // not sent to streaming clients that fell behind
+    pub stream_dropped: AtomicU64,
+    // unix time of the last drop, failed write and failed sync, 0 before the first
+    pub dropped_at: AtomicI64,
+    pub write_failed_at: AtomicI64,
+    pub sync_failed_at: AtomicI64,
+    // sequence numbers of the plcs using them
+    pub sequences: SequenceTracker,
+    // plcs whose clock is off
+    pub clocks: ClockTracker,
+    pub sources: SourceTracker,
+    // messages per listener and source
+    pub traffic: TrafficTracker,
+    // messages waiting for the workers and records waiting for the writer, a receiver
+    // may take one out before its sender counted it in
+    pub datagrams_queued: AtomicI64,
+    pub records_queued: AtomicI64,
+    pub write_seconds: Histogram,
+    pub sync_seconds: Histogram,
+}
+
+impl Stats {
+    pub fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // count a failure and remember when it happened
+    pub fn failed(counter: &AtomicU64, at: &AtomicI64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+        at.store(Utc::now().timestamp(), Ordering::Relaxed);
+    }
+
+    pub fn queued(gauge: &AtomicI64) {
+        gauge.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dequeued(gauge: &AtomicI64) {
+        gauge.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    // every counter with its name and the label used in the diagnostic log
+    pub fn counters(&self) -> Vec<(&'static str, &'static str, u64)> {
+        let counters = [
+            ("received", "received", &self.received),
+            ("dropped", "dropped (queue full)", &self.dropped),
+            ("truncated", "truncated", &self.truncated),
+            ("reassembled", "reassembled", &self.reassembled),
+            ("fragments_expired", "incomplete (fragments missing)", &self.fragments_expired),
+            ("fragments_evicted", "incomplete (reassembly limits)", &self.fragments_evicted),
+            ("rejected", "rejected", &self.rejected),
+            ("unknown_rejected", "unknown source", &self.unknown_rejected),
+            ("transcoded", "transcoded", &self.transcoded),
+            ("parse_failed", "parse failed", &self.parse_failed),
+            ("written", "written", &self.written),
+            ("filtered", "below min level", &self.filtered),
+            ("write_failed", "write failed", &self.write_failed),
+            ("sync_failed", "sync failed", &self.sync_failed),
+            ("acked", "acked", &self.acked),
+            ("duplicates_suppressed", "retransmits suppressed", &self.duplicates_suppressed),
+            ("stream_dropped", "dropped (stream client too slow)", &self.stream_dropped),
+        ];
+        counters
+            .into_iter()
+            .map(|(name, label, counter)| (name, label, counter.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    fn summary(&self) -> String {
+        let counters: Vec<String> = self
+            .counters()
+            .into_iter()
+            .map(|(_, label, value)| format!("{label}: {value}"))
+            .collect();
+        counters.join(", ")
+    }
+}
+
+// records of one source as they reach the writer
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SourceCounters {
+    pub records: u64,
+    pub written: u64,
+    pub filtered: u64,
+    pub warnings: u64,
+    // errors and critical messages
+    pub errors: u64,
+    pub last_received: Option<String>,
+}
+
+// counters of every source that sent something, by source name
+#[derive(Debug, Default)]
+pub struct SourceTracker {
+    sources: Mutex<HashMap<String, SourceCounters>>,
+}
+
+impl SourceTracker {
+    pub fn count(&self, record: &PlcRecord, written: bool) {
+        let mut sources = self.sources.lock().unwrap();
+        let counters = sources.entry(record.source.name.clone()).or_default();
+        counters.records += 1;
+        match written {
+            true => counters.written += 1,
+            false => counters.filtered += 1,
+        }
+        match record.level {
+            Severity::Warn => counters.warnings += 1,
+            Severity::Error | Severity::Critical => counters.errors += 1,
+            _ => {}
+        }
+        counters.last_received = Some(record.received.format(RECEIVED_FORMAT).to_string());
+    }
+
+    pub fn get(&self, name: &str) -> SourceCounters {
+        let sources = self.sources.lock().unwrap();
+        sources.get(name).cloned().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Traffic {
+    pub messages: u64,
+    pub bytes: u64,
+    pub last_seen: Option<DateTime<Local>>,
+}
+
+impl Traffic {
+    fn count(&mut self, bytes: usize, received: DateTime<Local>) {
+        self.messages += 1;
+        self.bytes += bytes as u64;
+        self.last_seen = Some(received);
+    }
+}
+
+// messages by listener as they are received, and by listener and source as they reach
+// the workers, unknown sources that are rejected do not make it that far
+#[derive(Debug, Default)]
+pub struct TrafficTracker {
+    listeners: Mutex<HashMap<Arc<str>, Traffic>>,
+    sources: Mutex<HashMap<(Arc<str>, String), Traffic>>,
+}
+
+impl TrafficTracker {
+    pub fn listener(&self, listener: &Arc<str>, bytes: usize, received: DateTime<Local>) {
+        let mut listeners = self.listeners.lock().unwrap();
+        match listeners.get_mut(listener) {
+            Some(traffic) => traffic.count(bytes, received),
+            None => {
+                let mut traffic = Traffic::default();
+                traffic.count(bytes, received);
+                listeners.insert(Arc::clone(listener), traffic);
+            }
+        }
+    }
+
+    pub fn source(&self, listener: &Arc<str>, source: &str, bytes: usize, received: DateTime<Local>) {
+        let mut sources = self.sources.lock().unwrap();
+        sources
+            .entry((Arc::clone(listener), source.to_string()))
+            .or_default()
+            .count(bytes, received);
+    }
+
+    pub fn by_listener(&self) -> Vec<(Arc<str>, Traffic)> {
+        let listeners = self.listeners.lock().unwrap();
+        let mut by_listener: Vec<(Arc<str>, Traffic)> = listeners
+            .iter()
+            .map(|(name, traffic)| (Arc::clone(name), traffic.clone()))
+            .collect();
+        by_listener.sort_by(|a, b| a.0.cmp(&b.0));
+        by_listener
+    }
+
+    // by listener and source name
+    pub fn by_source(&self) -> Vec<((Arc<str>, String), Traffic)> {
+        let sources = self.sources.lock().unwrap();
+        let mut by_source: Vec<((Arc<str>, String), Traffic)> = sources
+            .iter()
+            .map(|(key, traffic)| (key.clone(), traffic.clone()))
+            .collect();
+        by_source.sort_by(|a, b| a.0.cmp(&b.0));
+        by_source
+    }
+}
+
+// periodically log the counters to the diagnostic log, skipped when nothing changed
+pub fn spawn_reporter(stats: Arc<Stats>, interval_secs: u64) {
+    thread::spawn(move || {
+        let mut last = String::new();
+        loop {
+            thread::sleep(Duration::from_secs(interval_secs));
+            let summary = stats.summary();
+            if summary != last {
+                info!("Stats - {summary}");
+                last = summary;
+            }
+        }
+    });
+}
@@ -0,0 +1,375 @@
+use log::{error, info, warn};
+use regex::Regex;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::{Message, WebSocket};
+
+use crate::record::{PlcRecord, RECEIVED_FORMAT};
+use crate::severity::Severity;
+use crate::sources::normalize_addr;
+use crate::stats::Stats;
+
+// time a client has to send its filters in
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// clients that take longer than this to read a record are disconnected
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+// how often a connection without records is checked for a client that went away
+const IDLE_CHECK: Duration = Duration::from_secs(5);
+const MAX_REQUEST_BYTES: u64 = 4096;
+
+// settings of the streaming endpoint, present when stream_port is set
+#[derive(Debug, Clone)]
+pub struct StreamSettings {
+    pub addr: SocketAddr,
+    pub max_clients: usize,
+    // records queued per client, further records are dropped for that client
+    pub buffer: usize,
+}
+
+// records a client wants to see, sent as a query string: "source=press-01&level=warn&grep=E1&format=json"
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub source: Option<String>,
+    // this level and up
+    pub level: Option<Severity>,
+    // matched against the text line of the record
+    pub grep: Option<Regex>,
+    pub json: bool,
+}
+
+impl Filter {
+    pub fn parse(query: &str) -> Result<Filter, String> {
+        let mut filter = Filter::default();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = urlencoding::decode(&value.replace('+', " "))
+                .map_err(|_| format!("invalid value for '{key}'"))?
+                .into_owned();
+            match key {
+                "source" => filter.source = Some(value),
+                "level" => filter.level = Some(value.parse()?),
+                "grep" => {
+                    filter.grep =
+                        Some(Regex::new(&value).map_err(|err| format!("invalid grep: {err}"))?)
+                }
+                "format" => match value.as_str() {
+                    "text" => filter.json = false,
+                    "json" => filter.json = true,
+                    _ => return Err(format!("unknown format '{value}', expected text or json")),
+                },
+                _ => {
+                    return Err(format!(
+                        "unknown filter '{key}', expected one of: source, level, grep, format"
+                    ))
+                }
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn to_query(&self) -> String {
+        let mut pairs = Vec::new();
+        if let Some(source) = &self.source {
+            pairs.push(format!("source={}", urlencoding::encode(source)));
+        }
+        if let Some(level) = self.level {
+            pairs.push(format!("level={}", level.as_str().to_ascii_lowercase()));
+        }
+        if let Some(grep) = &self.grep {
+            pairs.push(format!("grep={}", urlencoding::encode(grep.as_str())));
+        }
+        if self.json {
+            pairs.push(String::from("format=json"));
+        }
+        pairs.join("&")
+    }
+
+    fn matches(&self, record: &PlcRecord, line: &str) -> bool {
+        if self
+            .source
+            .as_ref()
+            .is_some_and(|source| *source != record.source.name)
+        {
+            return false;
+        }
+        if self.level.is_some_and(|level| record.level < level) {
+            return false;
+        }
+        self.grep.as_ref().is_none_or(|grep| grep.is_match(line))
+    }
+}
+
+// options of `plclogger tail`
+#[derive(Debug, Default)]
+pub struct TailArgs {
+    pub filter: Filter,
+    // host:port of the streaming endpoint, taken from the config file when not given
+    pub connect: Option<String>,
+}
+
+struct Subscriber {
+    filter: Filter,
+    queue: SyncSender<String>,
+    // records not queued since the client last kept up
+    dropped: u64,
+}
+
+// hands the records to the clients of the streaming endpoint
+#[derive(Default)]
+pub struct StreamHub {
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+impl StreamHub {
+    fn subscribe(&self, filter: Filter, buffer: usize) -> Receiver<String> {
+        let (queue, records) = sync_channel(buffer);
+        self.subscribers.lock().unwrap().push(Subscriber {
+            filter,
+            queue,
+            dropped: 0,
+        });
+        records
+    }
+
+    // never waits for a client, records that do not fit the queue of a client are
+    // dropped for it and counted
+    pub fn publish(&self, record: &PlcRecord, stats: &Stats) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        let line = text_line(record);
+        let mut json = None;
+        subscribers.retain_mut(|subscriber| {
+            if !subscriber.filter.matches(record, &line) {
+                return true;
+            }
+            if subscriber.dropped > 0 {
+                let notice = format!(
+                    "*** {} records dropped, client too slow",
+                    subscriber.dropped
+                );
+                let notice = match subscriber.filter.json {
+                    true => serde_json::json!({ "notice": notice }).to_string(),
+                    false => notice,
+                };
+                match subscriber.queue.try_send(notice) {
+                    Ok(()) => subscriber.dropped = 0,
+                    Err(TrySendError::Full(_)) => {
+                        subscriber.dropped += 1;
+                        Stats::incr(&stats.stream_dropped);
+                        return true;
+                    }
+                    Err(TrySendError::Disconnected(_)) => return false,
+                }
+            }
+            let out = match subscriber.filter.json {
+                true => json.get_or_insert_with(|| record.to_json()).clone(),
+                false => line.clone(),
+            };
+            match subscriber.queue.try_send(out) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    subscriber.dropped += 1;
+                    Stats::incr(&stats.stream_dropped);
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+}
+
+// line sent to text clients, the default plc log pattern with the level
+fn text_line(record: &PlcRecord) -> String {
+    format!(
+        "{} | {} | {} | {} | {}",
+        record.received.format(RECEIVED_FORMAT),
+        record.level,
+        record.source.name,
+        record.src,
+        record.payload
+    )
+}
+
+// a connected client, plain tcp or websocket
+enum Client {
+    Tcp(TcpStream),
+    WebSocket(Box<WebSocket<TcpStream>>),
+}
+
+impl Client {
+    fn send(&mut self, line: String) -> io::Result<()> {
+        match self {
+            Client::Tcp(stream) => stream.write_all(format!("{line}\n").as_bytes()),
+            Client::WebSocket(socket) => socket.send(Message::text(line)).map_err(io::Error::other),
+        }
+    }
+
+    // false once the client closed the connection
+    fn alive(&mut self) -> bool {
+        match self {
+            Client::Tcp(stream) => {
+                if stream.set_nonblocking(true).is_err() {
+                    return false;
+                }
+                // anything the client sends after its filters is ignored
+                let mut buf = [0u8; 512];
+                let alive = match stream.read(&mut buf) {
+                    Ok(0) => false,
+                    Ok(_) => true,
+                    Err(err) => err.kind() == io::ErrorKind::WouldBlock,
+                };
+                alive && stream.set_nonblocking(false).is_ok()
+            }
+            Client::WebSocket(socket) => socket.send(Message::Ping(Default::default())).is_ok(),
+        }
+    }
+}
+
+// takes the filters of a websocket client from the query string of its upgrade request,
+// invalid filters are answered with 400 bad request
+struct ReadFilter<'a>(&'a mut Option<Filter>);
+
+impl Callback for ReadFilter<'_> {
+    fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        match Filter::parse(request.uri().query().unwrap_or_default()) {
+            Ok(filter) => {
+                *self.0 = Some(filter);
+                Ok(response)
+            }
+            Err(err) => {
+                let mut reply = ErrorResponse::new(Some(err));
+                *reply.status_mut() = StatusCode::BAD_REQUEST;
+                Err(reply)
+            }
+        }
+    }
+}
+
+// accepts streaming clients, each is served on its own thread
+pub fn spawn_server(settings: StreamSettings, hub: Arc<StreamHub>) -> io::Result<()> {
+    let socket = TcpListener::bind(settings.addr)?;
+    info!("Streaming records on {}", settings.addr);
+    let clients = Arc::new(AtomicUsize::new(0));
+
+    thread::spawn(move || {
+        for stream in socket.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    error!("{err}");
+                    continue;
+                }
+            };
+            let peer = match stream.peer_addr() {
+                Ok(peer) => normalize_addr(peer),
+                Err(err) => {
+                    error!("{err}");
+                    continue;
+                }
+            };
+
+            if clients.load(Ordering::Relaxed) >= settings.max_clients {
+                warn!(
+                    "Refused stream client {peer}, limit of {} clients reached",
+                    settings.max_clients
+                );
+                continue;
+            }
+            clients.fetch_add(1, Ordering::Relaxed);
+
+            let hub = Arc::clone(&hub);
+            let clients = Arc::clone(&clients);
+            let buffer = settings.buffer;
+            thread::spawn(move || {
+                let result = serve(stream, peer, &hub, buffer);
+                clients.fetch_sub(1, Ordering::Relaxed);
+                match result {
+                    Ok(()) => info!("Stream client {peer} disconnected"),
+                    Err(err) => warn!("Stream client {peer} disconnected: {err}"),
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn serve(stream: TcpStream, peer: SocketAddr, hub: &StreamHub, buffer: usize) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+
+    // websocket clients start with their http upgrade request, the filters are in its
+    // query string. tcp clients send the filters as the first line
+    let mut start = [0u8; 4];
+    let read = stream.peek(&mut start)?;
+    let (mut client, filter) = if &start[..read] == b"GET " {
+        let mut filter = None;
+        let socket = tungstenite::accept_hdr(stream, ReadFilter(&mut filter))
+            .map_err(|err| io::Error::other(err.to_string()))?;
+        (
+            Client::WebSocket(Box::new(socket)),
+            filter.unwrap_or_default(),
+        )
+    } else {
+        let mut request = String::new();
+        BufReader::new(&stream)
+            .take(MAX_REQUEST_BYTES)
+            .read_line(&mut request)?;
+        let mut client = Client::Tcp(stream);
+        match Filter::parse(request.trim()) {
+            Ok(filter) => (client, filter),
+            Err(err) => {
+                client.send(format!("error: {err}"))?;
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, err));
+            }
+        }
+    };
+    info!(
+        "Stream client {peer} connected, filters: {}",
+        filter.to_query()
+    );
+
+    let records = hub.subscribe(filter, buffer);
+    loop {
+        match records.recv_timeout(IDLE_CHECK) {
+            Ok(line) => client.send(line)?,
+            Err(RecvTimeoutError::Timeout) => {
+                if !client.alive() {
+                    return Ok(());
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+// print the records streamed by a running logger until it goes away
+pub fn tail(addr: &str, filter: &Filter) -> Result<(), String> {
+    let mut stream =
+        TcpStream::connect(addr).map_err(|err| format!("failed to connect to {addr}: {err}"))?;
+    writeln!(stream, "{}", filter.to_query()).map_err(|err| err.to_string())?;
+
+    let mut out = io::stdout().lock();
+    for line in BufReader::new(stream).lines() {
+        let line = line.map_err(|err| err.to_string())?;
+        if let Some(err) = line.strip_prefix("error: ") {
+            return Err(err.to_string());
+        }
+        if let Err(err) = writeln!(out, "{line}").and_then(|_| out.flush()) {
+            // output piped into head and the like
+            if err.kind() == io::ErrorKind::BrokenPipe {
+                return Ok(());
+            }
+            return Err(err.to_string());
+        }
+    }
+    Err(format!("connection to {addr} closed"))
+}
@@ -0,0 +1,312 @@
+// syslog messages as sent by hmis, managed switches and newer plcs, either in the
+// bsd format (rfc 3164) or the structured format (rfc 5424)
+
+const FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron",
+    "authpriv", "ftp", "ntp", "security", "console", "solaris-cron", "local0", "local1",
+    "local2", "local3", "local4", "local5", "local6", "local7",
+];
+
+const SEVERITIES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// header fields of a syslog message, nil values of rfc 5424 ("-") are left empty
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyslogHeader {
+    pub facility: u8,
+    pub severity: u8,
+    pub timestamp: String,
+    pub hostname: String,
+    pub app_name: String,
+    pub procid: String,
+    pub msgid: String,
+    pub structured_data: String,
+}
+
+impl SyslogHeader {
+    pub fn facility_name(&self) -> &'static str {
+        FACILITIES.get(self.facility as usize).copied().unwrap_or("unknown")
+    }
+
+    pub fn severity_name(&self) -> &'static str {
+        SEVERITIES.get(self.severity as usize).copied().unwrap_or("unknown")
+    }
+}
+
+// split a syslog frame into its header and message. like rfc 3164 relays do, anything
+// that does not look like syslog is kept whole as the message with priority user.notice
+pub fn parse(frame: &str) -> (SyslogHeader, &str) {
+    let frame = frame.trim_end_matches(['\r', '\n', '\0']);
+
+    let (pri, rest) = match parse_pri(frame) {
+        Some(val_ok) => val_ok,
+        None => {
+            let header = SyslogHeader {
+                facility: 1,
+                severity: 5,
+                ..Default::default()
+            };
+            return (header, frame);
+        }
+    };
+
+    let (mut header, message) = match rest.strip_prefix("1 ") {
+        Some(rest) => parse_5424(rest),
+        None => parse_3164(rest),
+    };
+    header.facility = pri / 8;
+    header.severity = pri % 8;
+    (header, message)
+}
+
+// <PRI> with a priority of 0 - 191
+fn parse_pri(frame: &str) -> Option<(u8, &str)> {
+    let rest = frame.strip_prefix('<')?;
+    let end = rest.find('>')?;
+    let digits = &rest[..end];
+    if digits.is_empty() || digits.len() > 3 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let pri: u8 = digits.parse().ok().filter(|pri| *pri <= 191)?;
+    Some((pri, &rest[end + 1..]))
+}
+
+// TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA [MSG]
+fn parse_5424(rest: &str) -> (SyslogHeader, &str) {
+    let mut rest = rest;
+    let mut header = Vec::new();
+    for _ in 0..5 {
+        let (field, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+        header.push(nil(field));
+        rest = tail;
+    }
+
+    let (structured_data, rest) = split_structured_data(rest);
+    let message = rest.strip_prefix(' ').unwrap_or(rest);
+    // the message may start with a utf-8 byte order mark
+    let message = message.strip_prefix('\u{feff}').unwrap_or(message);
+
+    let mut header = header.into_iter();
+    let header = SyslogHeader {
+        timestamp: header.next().unwrap_or_default(),
+        hostname: header.next().unwrap_or_default(),
+        app_name: header.next().unwrap_or_default(),
+        procid: header.next().unwrap_or_default(),
+        msgid: header.next().unwrap_or_default(),
+        structured_data: nil(structured_data),
+        ..Default::default()
+    };
+    (header, message)
+}
+
+// "-" or one or more [id param="value" ...] elements, values may contain \] and \"
+fn split_structured_data(rest: &str) -> (&str, &str) {
+    if let Some(tail) = rest.strip_prefix('-') {
+        return ("-", tail);
+    }
+
+    let bytes = rest.as_bytes();
+    let mut end = 0;
+    while bytes.get(end) == Some(&b'[') {
+        let mut in_value = false;
+        let mut i = end + 1;
+        loop {
+            match bytes.get(i) {
+                None => return (rest, ""),
+                Some(b'\\') => i += 1,
+                Some(b'"') => in_value = !in_value,
+                Some(b']') if !in_value => break,
+                _ => {}
+            }
+            i += 1;
+        }
+        end = i + 1;
+    }
+    rest.split_at(end)
+}
+
+// Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG, every part but the message may be missing
+fn parse_3164(rest: &str) -> (SyslogHeader, &str) {
+    let mut header = SyslogHeader::default();
+    let mut rest = rest;
+
+    if is_3164_timestamp(rest) {
+        header.timestamp = rest[..15].to_string();
+        rest = rest[15..].strip_prefix(' ').unwrap_or(&rest[15..]);
+
+        // without a timestamp there is no hostname either
+        if let Some((hostname, tail)) = rest.split_once(' ') {
+            if !hostname.is_empty() && !hostname.ends_with(':') && !hostname.contains('[') {
+                header.hostname = hostname.to_string();
+                rest = tail;
+            }
+        }
+    }
+
+    // the tag is at most 32 alphanumeric characters, followed by [pid] and/or a colon
+    let tag_len = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' || c == '/'))
+        .unwrap_or(rest.len());
+    if (1..=32).contains(&tag_len) {
+        let (tag, tail) = rest.split_at(tag_len);
+        let (procid, tail) = match tail.strip_prefix('[').and_then(|tail| tail.split_once(']')) {
+            Some((procid, tail)) => (procid, tail),
+            None => ("", tail),
+        };
+        if let Some(tail) = tail.strip_prefix(':') {
+            header.app_name = tag.to_string();
+            header.procid = procid.to_string();
+            rest = tail.strip_prefix(' ').unwrap_or(tail);
+        }
+    }
+
+    (header, rest)
+}
+
+fn is_3164_timestamp(rest: &str) -> bool {
+    let bytes = rest.as_bytes();
+    rest.len() >= 15
+        && rest.is_char_boundary(15)
+        && MONTHS.iter().any(|month| rest.starts_with(month))
+        && bytes[3] == b' '
+        && (bytes[4] == b' ' || bytes[4].is_ascii_digit())
+        && bytes[5].is_ascii_digit()
+        && bytes[6] == b' '
+        && bytes[9] == b':'
+        && bytes[12] == b':'
+}
+
+fn nil(field: &str) -> String {
+    if field == "-" {
+        String::new()
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc_5424_message() {
+        let (header, message) = parse(
+            "<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut=\"3\" eventSource=\"Application\"] \u{feff}An application event",
+        );
+        assert_eq!(
+            header,
+            SyslogHeader {
+                facility: 20,
+                severity: 5,
+                timestamp: String::from("2003-10-11T22:14:15.003Z"),
+                hostname: String::from("mymachine.example.com"),
+                app_name: String::from("evntslog"),
+                procid: String::new(),
+                msgid: String::from("ID47"),
+                structured_data: String::from(
+                    "[exampleSDID@32473 iut=\"3\" eventSource=\"Application\"]"
+                ),
+            }
+        );
+        assert_eq!(header.facility_name(), "local4");
+        assert_eq!(header.severity_name(), "notice");
+        assert_eq!(message, "An application event");
+    }
+
+    #[test]
+    fn rfc_5424_nil_values_and_no_message() {
+        let (header, message) = parse("<34>1 - - - - - -");
+        assert_eq!(header.facility_name(), "auth");
+        assert_eq!(header.severity_name(), "crit");
+        assert_eq!(header.timestamp, "");
+        assert_eq!(header.hostname, "");
+        assert_eq!(header.structured_data, "");
+        assert_eq!(message, "");
+    }
+
+    #[test]
+    fn rfc_5424_structured_data_with_escapes() {
+        let (header, message) =
+            parse(r#"<14>1 - plc01 app 12 - [a x="1\]2"][b y="q\"r" z="]"] belt stopped"#);
+        assert_eq!(header.procid, "12");
+        assert_eq!(header.structured_data, r#"[a x="1\]2"][b y="q\"r" z="]"]"#);
+        assert_eq!(message, "belt stopped");
+    }
+
+    #[test]
+    fn rfc_5424_unterminated_structured_data() {
+        let (header, message) = parse(r#"<14>1 - plc01 app - - [a x="1" belt stopped"#);
+        assert_eq!(header.structured_data, r#"[a x="1" belt stopped"#);
+        assert_eq!(message, "");
+    }
+
+    #[test]
+    fn rfc_3164_message() {
+        let (header, message) = parse("<34>Oct 11 22:14:15 mymachine su: 'su root' failed");
+        assert_eq!(header.facility, 4);
+        assert_eq!(header.severity, 2);
+        assert_eq!(header.timestamp, "Oct 11 22:14:15");
+        assert_eq!(header.hostname, "mymachine");
+        assert_eq!(header.app_name, "su");
+        assert_eq!(header.procid, "");
+        assert_eq!(message, "'su root' failed");
+    }
+
+    #[test]
+    fn rfc_3164_with_pid_and_padded_day() {
+        let (header, message) = parse("<13>Feb  5 17:32:18 plc01 conveyor[42]: belt stopped\r\n");
+        assert_eq!(header.timestamp, "Feb  5 17:32:18");
+        assert_eq!(header.hostname, "plc01");
+        assert_eq!(header.app_name, "conveyor");
+        assert_eq!(header.procid, "42");
+        assert_eq!(message, "belt stopped");
+    }
+
+    #[test]
+    fn rfc_3164_missing_parts() {
+        // no timestamp, so no hostname either
+        let (header, message) = parse("<13>conveyor: belt stopped");
+        assert_eq!(header.timestamp, "");
+        assert_eq!(header.hostname, "");
+        assert_eq!(header.app_name, "conveyor");
+        assert_eq!(message, "belt stopped");
+
+        // no hostname
+        let (header, message) = parse("<13>Feb  5 17:32:18 conveyor: belt stopped");
+        assert_eq!(header.hostname, "");
+        assert_eq!(header.app_name, "conveyor");
+        assert_eq!(message, "belt stopped");
+
+        // no tag
+        let (header, message) = parse("<13>Feb  5 17:32:18 plc01 belt stopped now");
+        assert_eq!(header.hostname, "plc01");
+        assert_eq!(header.app_name, "");
+        assert_eq!(message, "belt stopped now");
+    }
+
+    #[test]
+    fn anything_else_is_user_notice() {
+        for frame in ["belt stopped", "<192>belt stopped", "<>belt stopped", "<1234>belt", "<12x>belt"] {
+            let (header, message) = parse(frame);
+            assert_eq!(header.facility_name(), "user", "{frame}");
+            assert_eq!(header.severity_name(), "notice", "{frame}");
+            assert_eq!(message, frame);
+        }
+    }
+
+    #[test]
+    fn unknown_names() {
+        let header = SyslogHeader {
+            facility: 24,
+            severity: 8,
+            ..Default::default()
+        };
+        assert_eq!(header.facility_name(), "unknown");
+        assert_eq!(header.severity_name(), "unknown");
+    }
+}
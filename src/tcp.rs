@@ -0,0 +1,418 @@
+use chrono::Local;
+use log::{error, info, warn};
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::listeners::Listener;
+use crate::pipeline::{Datagram, WorkQueue};
+use crate::sources::normalize_addr;
+use crate::stats::Stats;
+
+// how messages are delimited on a tcp stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    // one message per line, \n or \r\n terminated
+    Newline,
+    // big-endian length of 1, 2 or 4 bytes in front of every message
+    LengthPrefixed(u8),
+    // every message has the same number of bytes
+    Fixed(usize),
+    // syslog over tcp (rfc 6587), "<length> <message>", frames that do not start with
+    // a length are read up to the next newline
+    OctetCounted,
+}
+
+impl Framing {
+    pub fn parse(framing: &str, prefix_bytes: i64, frame_size: i64) -> Result<Framing, String> {
+        match framing {
+            "newline" => Ok(Framing::Newline),
+            "length" | "length-prefixed" => match prefix_bytes {
+                1 | 2 | 4 => Ok(Framing::LengthPrefixed(prefix_bytes as u8)),
+                _ => Err(String::from("length prefix must be 1, 2 or 4 bytes")),
+            },
+            "fixed" => {
+                if !(1..=65536).contains(&frame_size) {
+                    return Err(String::from("fixed frame size must be between 1 - 65536 (bytes)"));
+                }
+                Ok(Framing::Fixed(frame_size as usize))
+            }
+            "octet-counted" => Ok(Framing::OctetCounted),
+            _ => Err(format!(
+                "unknown tcp framing '{framing}', expected one of: newline, length-prefixed, fixed, octet-counted"
+            )),
+        }
+    }
+}
+
+// settings shared by every connection of a tcp listener
+#[derive(Debug, Clone)]
+pub struct TcpSettings {
+    pub framing: Framing,
+    pub max_connections: usize,
+    pub max_frame_bytes: usize,
+    pub idle_timeout_secs: u64,
+}
+
+// accepts connections and reads frames from each of them on its own thread
+pub fn spawn_listener(
+    socket: TcpListener,
+    listener: Arc<Listener>,
+    settings: TcpSettings,
+    queue: WorkQueue,
+    stats: Arc<Stats>,
+) {
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    thread::spawn(move || {
+        for stream in socket.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    error!("{err}");
+                    continue;
+                }
+            };
+            let peer = match stream.peer_addr() {
+                Ok(peer) => normalize_addr(peer),
+                Err(err) => {
+                    error!("{err}");
+                    continue;
+                }
+            };
+
+            if connections.load(Ordering::Relaxed) >= settings.max_connections {
+                warn!(
+                    "Refused connection from {peer} on {}, limit of {} connections reached",
+                    listener.name, settings.max_connections
+                );
+                continue;
+            }
+            connections.fetch_add(1, Ordering::Relaxed);
+            info!("Accepted connection from {peer} on {}", listener.name);
+
+            let listener = Arc::clone(&listener);
+            let settings = settings.clone();
+            let queue = queue.clone();
+            let stats = Arc::clone(&stats);
+            let connections = Arc::clone(&connections);
+            thread::spawn(move || {
+                let result = handle_connection(stream, peer, &listener, &settings, &queue, &stats);
+                connections.fetch_sub(1, Ordering::Relaxed);
+                match result {
+                    Ok(()) => info!("Connection from {peer} on {} closed", listener.name),
+                    Err(err) => warn!("Connection from {peer} on {} closed: {err}", listener.name),
+                }
+            });
+        }
+    });
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    peer: SocketAddr,
+    listener: &Arc<Listener>,
+    settings: &TcpSettings,
+    queue: &WorkQueue,
+    stats: &Stats,
+) -> io::Result<()> {
+    if settings.idle_timeout_secs > 0 {
+        stream.set_read_timeout(Some(Duration::from_secs(settings.idle_timeout_secs)))?;
+    }
+    let mut reader = BufReader::new(stream);
+
+    while let Some((data, truncated)) = read_frame(&mut reader, settings)? {
+        Stats::incr(&stats.received);
+        if truncated {
+            Stats::incr(&stats.truncated);
+            warn!(
+                "Frame from {peer} truncated to {} bytes",
+                settings.max_frame_bytes
+            );
+        }
+
+        let datagram = Datagram::new(data, peer, Local::now(), Arc::clone(listener), stats);
+        // tcp senders are slowed down instead of losing messages when the queue is full
+        if queue.worker(&peer).send(datagram).is_err() {
+            return Err(io::Error::other("workers stopped"));
+        }
+        Stats::queued(&stats.datagrams_queued);
+    }
+
+    Ok(())
+}
+
+// next frame on the stream, None once the peer closed the connection. frames longer
+// than the maximum are cut off, which is flagged in the result
+fn read_frame(
+    reader: &mut impl BufRead,
+    settings: &TcpSettings,
+) -> io::Result<Option<(Vec<u8>, bool)>> {
+    let max = settings.max_frame_bytes;
+
+    match settings.framing {
+        Framing::Newline => read_line(reader, max),
+        Framing::LengthPrefixed(prefix_bytes) => {
+            let mut prefix = [0u8; 4];
+            let prefix = &mut prefix[4 - prefix_bytes as usize..];
+            if !read_exact_or_eof(reader, prefix)? {
+                return Ok(None);
+            }
+            let mut len_bytes = [0u8; 4];
+            len_bytes[4 - prefix.len()..].copy_from_slice(prefix);
+            let len = u32::from_be_bytes(len_bytes) as usize;
+
+            read_counted(reader, len, max).map(Some)
+        }
+        Framing::Fixed(size) => {
+            let mut frame = vec![0u8; size];
+            if !read_exact_or_eof(reader, &mut frame)? {
+                return Ok(None);
+            }
+            Ok(Some((frame, false)))
+        }
+        Framing::OctetCounted => {
+            let starts_with_digit = match reader.fill_buf()?.first() {
+                Some(first) => first.is_ascii_digit(),
+                None => return Ok(None),
+            };
+            if !starts_with_digit {
+                return read_line(reader, max);
+            }
+
+            let mut digits = Vec::new();
+            reader.by_ref().take(11).read_until(b' ', &mut digits)?;
+            let len = match digits.strip_suffix(b" ") {
+                Some(digits) => std::str::from_utf8(digits).ok().and_then(|len| len.parse().ok()),
+                None => None,
+            };
+            match len {
+                Some(len) => read_counted(reader, len, max).map(Some),
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid octet count in syslog frame",
+                )),
+            }
+        }
+    }
+}
+
+// frame up to the next newline, over-long lines are cut off and the rest skipped
+fn read_line(
+    reader: &mut impl BufRead,
+    max: usize,
+) -> io::Result<Option<(Vec<u8>, bool)>> {
+    let mut line = Vec::new();
+    let read = reader.by_ref().take(max as u64 + 1).read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+
+    let mut truncated = false;
+    if line.last() != Some(&b'\n') && line.len() > max {
+        truncated = true;
+        line.truncate(max);
+        skip_line(reader)?;
+    }
+    while line.last() == Some(&b'\n') || line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some((line, truncated)))
+}
+
+// skip the rest of an over-long line, a buffer at a time without keeping it
+fn skip_line(reader: &mut impl BufRead) -> io::Result<()> {
+    loop {
+        let buf = match reader.fill_buf() {
+            Ok(buf) => buf,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        if buf.is_empty() {
+            return Ok(());
+        }
+        match buf.iter().position(|b| *b == b'\n') {
+            Some(end) => {
+                reader.consume(end + 1);
+                return Ok(());
+            }
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+// frame of a known length, anything beyond the maximum is skipped
+fn read_counted(
+    reader: &mut impl BufRead,
+    len: usize,
+    max: usize,
+) -> io::Result<(Vec<u8>, bool)> {
+    let mut frame = vec![0u8; len.min(max)];
+    reader.read_exact(&mut frame)?;
+    if len > max {
+        io::copy(&mut reader.by_ref().take((len - max) as u64), &mut io::sink())?;
+    }
+    Ok((frame, len > max))
+}
+
+// fill the buffer, false when the stream ended cleanly before the first byte
+fn read_exact_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(framing: Framing, max_frame_bytes: usize) -> TcpSettings {
+        TcpSettings {
+            framing,
+            max_frame_bytes,
+            max_connections: 1,
+            idle_timeout_secs: 0,
+        }
+    }
+
+    // every frame on the stream, a small buffer so frames span several reads
+    fn read_all(framing: Framing, max: usize, data: &[u8]) -> io::Result<Vec<(String, bool)>> {
+        let mut reader = BufReader::with_capacity(4, data);
+        let settings = settings(framing, max);
+        let mut frames = Vec::new();
+        while let Some((frame, truncated)) = read_frame(&mut reader, &settings)? {
+            frames.push((String::from_utf8(frame).unwrap(), truncated));
+        }
+        Ok(frames)
+    }
+
+    fn frames_of(framing: Framing, data: &[u8]) -> Vec<(String, bool)> {
+        read_all(framing, 100, data).unwrap()
+    }
+
+    fn whole(frames: &[&str]) -> Vec<(String, bool)> {
+        frames
+            .iter()
+            .map(|frame| (frame.to_string(), false))
+            .collect()
+    }
+
+    #[test]
+    fn newline_frames() {
+        let frames = read_all(Framing::Newline, 100, b"belt on\r\nbelt off\n\nlast").unwrap();
+        // a line cut short by the end of the stream is still a frame
+        assert_eq!(frames, whole(&["belt on", "belt off", "", "last"]));
+    }
+
+    #[test]
+    fn over_long_lines_are_cut_off() {
+        let data = b"abcdefghijklmnop\nabcde\nabcdef\nxy";
+        let frames = read_all(Framing::Newline, 5, data).unwrap();
+        assert_eq!(
+            frames,
+            [
+                (String::from("abcde"), true),
+                (String::from("abcde"), false),
+                (String::from("abcde"), true),
+                (String::from("xy"), false),
+            ]
+        );
+        // nothing is left of a line running into the end of the stream
+        let frames = read_all(Framing::Newline, 5, &[b'a'; 10_000]).unwrap();
+        assert_eq!(frames, [(String::from("aaaaa"), true)]);
+    }
+
+    #[test]
+    fn length_prefixed_frames() {
+        let data = [0, 3, b'a', b'b', b'c', 0, 0, 0, 2, b'x', b'y'];
+        let frames = read_all(Framing::LengthPrefixed(2), 100, &data).unwrap();
+        assert_eq!(frames, whole(&["abc", "", "xy"]));
+
+        let data = [2, b'a', b'b', 1, b'c'];
+        assert_eq!(
+            frames_of(Framing::LengthPrefixed(1), &data),
+            whole(&["ab", "c"])
+        );
+        // 256 bytes over a 10 byte limit, the rest is skipped
+        let mut long = vec![0, 0, 1, 0];
+        long.extend([b'z'; 256]);
+        long.extend([0, 0, 0, 1, b'!']);
+        let frames = read_all(Framing::LengthPrefixed(4), 10, &long).unwrap();
+        assert_eq!(frames, [("z".repeat(10), true), (String::from("!"), false)]);
+    }
+
+    #[test]
+    fn partial_frames_at_the_end() {
+        for (framing, data) in [
+            (Framing::LengthPrefixed(2), &[0, 5, b'a', b'b'][..]),
+            (Framing::LengthPrefixed(2), &[0, 1, b'a', 0][..]),
+            (Framing::LengthPrefixed(4), &[0, 0][..]),
+            (Framing::Fixed(3), &b"abcdef!"[..]),
+            (Framing::OctetCounted, &b"5 hel"[..]),
+        ] {
+            let err = read_all(framing, 100, data).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof, "{framing:?}");
+        }
+    }
+
+    #[test]
+    fn fixed_frames() {
+        assert_eq!(
+            frames_of(Framing::Fixed(3), b"abcdef"),
+            whole(&["abc", "def"])
+        );
+    }
+
+    #[test]
+    fn octet_counted_frames() {
+        let data = b"5 hello11 <13>line\nab<14>plain\n3 end";
+        assert_eq!(
+            frames_of(Framing::OctetCounted, data),
+            whole(&["hello", "<13>line\nab", "<14>plain", "end"])
+        );
+        let frames = read_all(Framing::OctetCounted, 4, b"8 abcdefgh2 ok").unwrap();
+        assert_eq!(
+            frames,
+            [(String::from("abcd"), true), (String::from("ok"), false)]
+        );
+        for data in [&b"12345678901 x"[..], &b"5x hello"[..]] {
+            let err = read_all(Framing::OctetCounted, 100, data).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn framing_settings() {
+        assert_eq!(Framing::parse("newline", 0, 0), Ok(Framing::Newline));
+        assert_eq!(
+            Framing::parse("length-prefixed", 4, 0),
+            Ok(Framing::LengthPrefixed(4))
+        );
+        assert_eq!(Framing::parse("fixed", 0, 64), Ok(Framing::Fixed(64)));
+        assert_eq!(
+            Framing::parse("length", 3, 0),
+            Err(String::from("length prefix must be 1, 2 or 4 bytes"))
+        );
+        assert_eq!(
+            Framing::parse("fixed", 0, 0),
+            Err(String::from(
+                "fixed frame size must be between 1 - 65536 (bytes)"
+            ))
+        );
+        assert!(Framing::parse("xml", 0, 0).is_err());
+    }
+}